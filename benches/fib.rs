@@ -0,0 +1,38 @@
+//! Times jlox on lox/fib.lox with a smaller argument, as fib(40) takes
+//! minutes in a tree-walker
+//!
+//! Run with `cargo bench --bench fib [-- N]`, N defaults to 25. To compare
+//! against another revision, check it out and run the same command.
+
+use std::{env, fs, io, time::Instant};
+
+use lox::{jlox::diagnostics::Color, JLox, Lox};
+
+const RUNS: usize = 5;
+
+fn main() {
+    let n: u32 = env::args()
+        .skip(1)
+        .find_map(|arg| arg.parse().ok())
+        .unwrap_or(25);
+    let source = fs::read_to_string("lox/fib.lox")
+        .unwrap()
+        .replace("fib(40)", &format!("fib({})", n));
+
+    let mut times: Vec<_> = (0..RUNS)
+        .map(|_| {
+            let mut lox = JLox::with_output(io::sink(), Color::Never);
+            let start = Instant::now();
+            lox.interpret(source.clone()).unwrap();
+            start.elapsed()
+        })
+        .collect();
+    times.sort();
+    println!(
+        "jlox fib({}): best {:?}, median {:?} of {} runs",
+        n,
+        times[0],
+        times[RUNS / 2],
+        RUNS
+    );
+}
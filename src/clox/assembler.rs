@@ -0,0 +1,470 @@
+//! Reads textual bytecode back into a `Chunk`.
+//!
+//! Accepts output of `debug::write_chunk` as is, as well as hand-written
+//! listings:
+//!
+//! ```text
+//! == example ==              ; headers and comments are ignored
+//! .line 1                    ; sets line of following instructions
+//!     OP_CONSTANT '1.0'      ; constant index can be left out
+//!     OP_TRY catch           ; jumps take either a label or `offset -> target`
+//!     OP_THROW
+//! catch:
+//!     OP_PRINT
+//!     OP_RETURN
+//! ```
+//!
+//! Constants given with explicit index keep it, so disassembling and
+//! assembling again gives identical code. Unused slots of the constant pool
+//! are filled with `nil`.
+
+use std::collections::HashMap;
+
+use super::{
+    chunk::{self, Chunk, Opcode},
+    debug::{opcode_info, OperandKind},
+    scanner::Span,
+    value::Value,
+};
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("[line {line}] {message}")]
+pub struct Error {
+    pub line: usize,
+    pub message: String,
+}
+
+enum Operand {
+    None,
+    Byte(u8),
+    Constant(Option<usize>, Value),
+    Label(String),
+    Target(usize),
+}
+
+struct Instruction {
+    // Line of the listing, for errors
+    text_line: usize,
+    op: u8,
+    kind: OperandKind,
+    operand: Operand,
+    span: Span,
+}
+
+enum Item {
+    Label(String),
+    Instruction(Instruction),
+}
+
+pub fn assemble(source: &str) -> Result<Chunk, Error> {
+    let mut items = vec![];
+    let mut line = 1;
+    for (i, text) in source.lines().enumerate() {
+        let error = |message: String| Error {
+            line: i + 1,
+            message,
+        };
+        if let Some(item) = parse_line(text, i + 1, &mut line).map_err(error)? {
+            items.push(item);
+        }
+    }
+
+    // Place constants with explicit index first, so the rest can fill gaps
+    let mut pool: Vec<Option<Value>> = vec![];
+    for item in &mut items {
+        if let Item::Instruction(Instruction {
+            text_line,
+            operand: Operand::Constant(Some(index), value),
+            ..
+        }) = item
+        {
+            if pool.len() <= *index {
+                pool.resize(*index + 1, None);
+            }
+            match &pool[*index] {
+                Some(existing) if existing != value => {
+                    return Err(Error {
+                        line: *text_line,
+                        message: format!(
+                            "Constant {} was already defined as {:?}",
+                            index, existing
+                        ),
+                    })
+                }
+                _ => pool[*index] = Some(value.clone()),
+            }
+        }
+    }
+    for item in &mut items {
+        if let Item::Instruction(Instruction {
+            operand: Operand::Constant(index @ None, value),
+            ..
+        }) = item
+        {
+            let position = pool
+                .iter()
+                .position(|v| v.as_ref() == Some(&*value))
+                .or_else(|| pool.iter().position(Option::is_none));
+            let position = position.unwrap_or_else(|| {
+                pool.push(None);
+                pool.len() - 1
+            });
+            pool[position] = Some(value.clone());
+            *index = Some(position);
+        }
+    }
+
+    // Sizes of instructions are now known, so labels can be resolved
+    let mut labels = HashMap::new();
+    let mut offset = 0;
+    for item in &items {
+        match item {
+            Item::Label(name) => {
+                labels.insert(name.as_str(), offset);
+            }
+            Item::Instruction(instruction) => {
+                offset += instruction_len(instruction)?;
+            }
+        }
+    }
+
+    let mut chunk = Chunk::default();
+    for value in pool {
+        chunk.push_constant(value.unwrap_or_else(Value::nil));
+    }
+    for item in &items {
+        let instruction = match item {
+            Item::Instruction(instruction) => instruction,
+            Item::Label(_) => continue,
+        };
+        let span = instruction.span;
+        let error = |message: String| Error {
+            line: instruction.text_line,
+            message,
+        };
+        match &instruction.operand {
+            Operand::None => chunk.write(instruction.op, span),
+            Operand::Byte(byte) => {
+                chunk.write(instruction.op, span);
+                chunk.write(*byte, span);
+            }
+            Operand::Constant(index, _) => {
+                let index = index.unwrap();
+                chunk.write(instruction.op, span);
+                match instruction.kind {
+                    OperandKind::Constant => chunk.write(index as u8, span),
+                    _ => chunk::encode_index(index, |byte| {
+                        chunk.write(byte, span)
+                    }),
+                }
+            }
+            Operand::Label(_) | Operand::Target(_) => {
+                let target = match &instruction.operand {
+                    Operand::Label(label) => {
+                        *labels.get(label.as_str()).ok_or_else(|| {
+                            error(format!("Undefined label '{}'", label))
+                        })?
+                    }
+                    Operand::Target(target) => *target,
+                    _ => unreachable!(),
+                };
+                let after = chunk.code.len() + 3;
+                let jump = target
+                    .checked_sub(after)
+                    .filter(|&jump| jump <= u16::MAX as usize)
+                    .ok_or_else(|| {
+                        error(format!(
+                            "Can't jump from {} to {}",
+                            after, target
+                        ))
+                    })?;
+                chunk.write(instruction.op, span);
+                for byte in (jump as u16).to_le_bytes().iter() {
+                    chunk.write(*byte, span);
+                }
+            }
+        }
+    }
+    Ok(chunk)
+}
+
+fn instruction_len(instruction: &Instruction) -> Result<usize, Error> {
+    match (instruction.kind, &instruction.operand) {
+        (OperandKind::Constant, Operand::Constant(Some(index), _))
+            if *index > 0xff =>
+        {
+            Err(Error {
+                line: instruction.text_line,
+                message: format!(
+                    "Constant index {} needs a long instruction",
+                    index
+                ),
+            })
+        }
+        (OperandKind::ConstantLong, Operand::Constant(Some(index), _)) => {
+            let mut len = 1;
+            chunk::encode_index(*index, |_| len += 1);
+            Ok(len)
+        }
+        (OperandKind::None, _) => Ok(1),
+        (OperandKind::Jump, _) => Ok(3),
+        _ => Ok(2),
+    }
+}
+
+fn parse_line(
+    text: &str,
+    text_line: usize,
+    line: &mut usize,
+) -> Result<Option<Item>, String> {
+    // Constant literal can contain `;`, so split it off first
+    let (text, literal) = match (text.find('\''), text.rfind('\'')) {
+        (Some(start), Some(end)) if start < end => {
+            (&text[..start], Some(&text[start + 1..end]))
+        }
+        _ => (text.split(';').next().unwrap(), None),
+    };
+    let tokens: Vec<&str> = text.split_whitespace().collect();
+    match tokens.as_slice() {
+        [] => return Ok(None),
+        [first, ..] if first.starts_with("==") => return Ok(None),
+        [".line", number] => {
+            *line = parse_number(number)?;
+            return Ok(None);
+        }
+        [label] if label.ends_with(':') => {
+            let label = &label[..label.len() - 1];
+            return Ok(Some(Item::Label(label.to_owned())));
+        }
+        _ => {}
+    }
+
+    let position = tokens
+        .iter()
+        .position(|t| t.starts_with("OP_"))
+        .ok_or_else(|| format!("Expected instruction, found '{}'", text))?;
+    let (prefix, mnemonic, args) = (
+        &tokens[..position],
+        tokens[position],
+        &tokens[position + 1..],
+    );
+
+    // Disassembly prefix: offset, line or `|`, then raw bytes
+    match prefix {
+        [] => {}
+        [offset, line_column, ..] => {
+            parse_number(offset)?;
+            if *line_column != "|" {
+                *line = parse_number(line_column)?;
+            }
+        }
+        [token] => return Err(format!("Unexpected '{}'", token)),
+    }
+
+    let (opcode, (_, kind)) = (0..=u8::MAX)
+        .filter_map(Opcode::check)
+        .map(|opcode| {
+            let info = opcode_info(&opcode);
+            (opcode as u8, info)
+        })
+        .find(|(_, (name, _))| *name == mnemonic)
+        .ok_or_else(|| format!("Unknown instruction '{}'", mnemonic))?;
+
+    let operand = match (kind, args, literal) {
+        (OperandKind::None, [], None) => Operand::None,
+        (OperandKind::Byte, [byte], None) => Operand::Byte(
+            byte.parse()
+                .map_err(|_| format!("Invalid byte '{}'", byte))?,
+        ),
+        (OperandKind::Constant, args, Some(literal))
+        | (OperandKind::ConstantLong, args, Some(literal))
+            if args.len() <= 1 =>
+        {
+            let index = args.first().map(|i| parse_number(i)).transpose()?;
+            Operand::Constant(index, parse_value(literal)?)
+        }
+        (OperandKind::Jump, [label], None) => Operand::Label(label.to_string()),
+        (OperandKind::Jump, [_, "->", target], None) => {
+            Operand::Target(parse_number(target)?)
+        }
+        _ => return Err(format!("Invalid operands for {}", mnemonic)),
+    };
+
+    Ok(Some(Item::Instruction(Instruction {
+        text_line,
+        op: opcode,
+        kind,
+        operand,
+        span: Span {
+            line: *line,
+            ..Span::default()
+        },
+    })))
+}
+
+fn parse_number(text: &str) -> Result<usize, String> {
+    text.parse()
+        .map_err(|_| format!("Expected number, found '{}'", text))
+}
+
+// Parses constant as printed by `Value`'s `Debug`
+fn parse_value(text: &str) -> Result<Value, String> {
+    match text {
+        "nil" => return Ok(Value::nil()),
+        "true" => return Ok(Value::bool(true)),
+        "false" => return Ok(Value::bool(false)),
+        _ => {}
+    }
+    if let Some(string) =
+        text.strip_prefix('"').and_then(|s| s.strip_suffix('"'))
+    {
+        return unescape(string).map(Value::string);
+    }
+    text.parse()
+        .map(Value::number)
+        .map_err(|_| format!("Invalid constant '{}'", text))
+}
+
+fn unescape(text: &str) -> Result<String, String> {
+    let mut string = String::with_capacity(text.len());
+    let mut chars = text.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            string.push(c);
+            continue;
+        }
+        let escaped = match chars.next() {
+            Some('n') => '\n',
+            Some('r') => '\r',
+            Some('t') => '\t',
+            Some('0') => '\0',
+            Some(c @ '\\') | Some(c @ '"') | Some(c @ '\'') => c,
+            Some('u') => {
+                let rest = chars.as_str();
+                let end = rest.find('}').ok_or("Unterminated escape")?;
+                let code = rest
+                    .strip_prefix('{')
+                    .and_then(|r| u32::from_str_radix(&r[..end - 1], 16).ok())
+                    .and_then(std::char::from_u32)
+                    .ok_or("Invalid unicode escape")?;
+                chars = rest[end + 1..].chars();
+                code
+            }
+            _ => return Err("Invalid escape".into()),
+        };
+        string.push(escaped);
+    }
+    Ok(string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            compiler::compile,
+            debug::write_chunk,
+            optimizer::{optimize, OptLevel},
+            verifier::verify,
+        },
+        *,
+    };
+
+    fn disassemble(chunk: &Chunk) -> String {
+        let mut out = vec![];
+        write_chunk(&mut out, chunk, "test").unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn lines(chunk: &Chunk) -> Vec<Option<usize>> {
+        (0..chunk.code.len()).map(|i| chunk.get_line(i)).collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let mut source = String::from(
+            "var a = \"it's\ta; 'quoted'\";
+            { var b = a; print -b >= 2; }
+            try { throw a; } catch (e) { print e.message; } finally { a; }\n",
+        );
+        for i in 0..300 {
+            source.push_str(&format!("print {};\n", i));
+        }
+        let chunk = compile(&source).unwrap();
+        for level in [OptLevel::O0, OptLevel::O1].iter() {
+            let chunk = optimize(&chunk, *level);
+            let text = disassemble(&chunk);
+            let assembled = assemble(&text).unwrap();
+            assert_eq!(assembled.code, chunk.code);
+            assert_eq!(lines(&assembled), lines(&chunk));
+            assert_eq!(disassemble(&assembled), text);
+        }
+    }
+
+    #[test]
+    fn hand_written() {
+        let chunk = assemble(
+            "
+            .line 1
+                OP_CONSTANT '1.0'   ; loaded twice
+                OP_TRY catch
+                OP_CONSTANT '1.0'
+                OP_THROW
+            catch:
+            .line 2
+                OP_GET_PROPERTY 5 '\"message\"'
+                OP_PRINT
+                OP_POP
+                OP_RETURN
+            ",
+        )
+        .unwrap();
+        assert_eq!(
+            chunk.code,
+            [
+                Opcode::CONSTANT,
+                0,
+                Opcode::TRY,
+                3,
+                0,
+                Opcode::CONSTANT,
+                0,
+                Opcode::THROW,
+                Opcode::GET_PROPERTY,
+                5,
+                Opcode::PRINT,
+                Opcode::POP,
+                Opcode::RETURN,
+            ]
+        );
+        assert_eq!(chunk.get_line(7), Some(1));
+        assert_eq!(chunk.get_line(8), Some(2));
+        assert_eq!(chunk.constants.values.len(), 6);
+        verify(&chunk).unwrap();
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            assemble("OP_NIL\nOP_FOO").err().unwrap(),
+            Error {
+                line: 2,
+                message: "Unknown instruction 'OP_FOO'".into()
+            }
+        );
+        assert_eq!(
+            assemble("OP_JUMP nowhere").err().unwrap().message,
+            "Undefined label 'nowhere'"
+        );
+        assert_eq!(
+            assemble("OP_CONSTANT 0 '1.0'\nOP_CONSTANT 0 '2.0'")
+                .err()
+                .unwrap()
+                .line,
+            2
+        );
+        assert_eq!(
+            assemble("back:\nOP_JUMP back").err().unwrap().message,
+            "Can't jump from 3 to 0"
+        );
+    }
+}
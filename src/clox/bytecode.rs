@@ -0,0 +1,286 @@
+//! Binary format of compiled chunks, `.loxc` files.
+//!
+//! All integers are little-endian:
+//!
+//! ```text
+//! magic      b"LOXC"
+//! version    u16
+//! constants  u32 count, then per entry a tag byte and its payload:
+//!              0 nil, 1 false, 2 true, 3 number (f64),
+//!              4 string (u32 length + utf-8 bytes)
+//! code       u32 length + bytes
+//! lines      u32 count, then per run: u32 start offset,
+//!              u32 line, u32 column, u32 span start, u32 span end
+//! functions  u32 count, then per function: u32 name length + name,
+//!              u32 code offset
+//! ```
+//!
+//! Until functions are compiled, function table holds only the top-level
+//! script.
+
+use std::convert::TryFrom;
+
+use super::{chunk::Chunk, scanner::Span, value::Value};
+
+pub const MAGIC: &[u8; 4] = b"LOXC";
+pub const VERSION: u16 = 1;
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum Error {
+    #[error("Not a compiled Lox file")]
+    BadMagic,
+    #[error("Unsupported bytecode version {0}, expected {}", VERSION)]
+    UnsupportedVersion(u16),
+    #[error("Unexpected end of bytecode file")]
+    Truncated,
+    #[error("Unknown constant tag {0:#x}")]
+    UnknownConstantTag(u8),
+    #[error("String constant isn't valid utf-8")]
+    InvalidString,
+    #[error("Line table doesn't cover code")]
+    InvalidLineTable,
+    #[error("Function table doesn't start with top-level script")]
+    InvalidFunctionTable,
+    #[error("Trailing data after function table")]
+    TrailingData,
+}
+
+const TAG_NIL: u8 = 0;
+const TAG_FALSE: u8 = 1;
+const TAG_TRUE: u8 = 2;
+const TAG_NUMBER: u8 = 3;
+const TAG_STRING: u8 = 4;
+
+struct Writer(Vec<u8>);
+
+impl Writer {
+    fn u32(&mut self, value: usize) {
+        let value = u32::try_from(value).expect("chunk too big to serialize");
+        self.0.extend_from_slice(&value.to_le_bytes());
+    }
+
+    fn bytes(&mut self, bytes: &[u8]) {
+        self.u32(bytes.len());
+        self.0.extend_from_slice(bytes);
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], Error> {
+        if self.0.len() < len {
+            return Err(Error::Truncated);
+        }
+        let (taken, rest) = self.0.split_at(len);
+        self.0 = rest;
+        Ok(taken)
+    }
+
+    fn u8(&mut self) -> Result<u8, Error> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, Error> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<usize, Error> {
+        let mut bytes = [0; 4];
+        bytes.copy_from_slice(self.take(4)?);
+        Ok(u32::from_le_bytes(bytes) as usize)
+    }
+
+    fn f64(&mut self) -> Result<f64, Error> {
+        let mut bytes = [0; 8];
+        bytes.copy_from_slice(self.take(8)?);
+        Ok(f64::from_le_bytes(bytes))
+    }
+
+    fn bytes(&mut self) -> Result<&'a [u8], Error> {
+        let len = self.u32()?;
+        self.take(len)
+    }
+
+    fn string(&mut self) -> Result<String, Error> {
+        let bytes = self.bytes()?;
+        String::from_utf8(bytes.to_vec()).map_err(|_| Error::InvalidString)
+    }
+}
+
+impl Chunk {
+    pub fn serialize(&self) -> Vec<u8> {
+        let mut w = Writer(MAGIC.to_vec());
+        w.0.extend_from_slice(&VERSION.to_le_bytes());
+
+        w.u32(self.constants.values.len());
+        for value in &self.constants.values {
+            match value {
+                Value::Nil => w.0.push(TAG_NIL),
+                Value::Bool(false) => w.0.push(TAG_FALSE),
+                Value::Bool(true) => w.0.push(TAG_TRUE),
+                Value::Number(n) => {
+                    w.0.push(TAG_NUMBER);
+                    w.0.extend_from_slice(&n.to_le_bytes());
+                }
+                value => {
+                    let string = value
+                        .clone()
+                        .into_string()
+                        .expect("only strings can be constants");
+                    w.0.push(TAG_STRING);
+                    w.bytes(string.as_bytes());
+                }
+            }
+        }
+
+        w.bytes(&self.code);
+
+        let runs: Vec<_> = self.span_runs().collect();
+        w.u32(runs.len());
+        for (start, span) in runs {
+            w.u32(start);
+            w.u32(span.line);
+            w.u32(span.column);
+            w.u32(span.start);
+            w.u32(span.end);
+        }
+
+        w.u32(1);
+        w.bytes(b"script");
+        w.u32(0);
+
+        w.0
+    }
+
+    pub fn deserialize(bytes: &[u8]) -> Result<Self, Error> {
+        let mut r = Reader(bytes);
+        if r.take(MAGIC.len()).map_err(|_| Error::BadMagic)? != MAGIC {
+            return Err(Error::BadMagic);
+        }
+        let version = r.u16()?;
+        if version != VERSION {
+            return Err(Error::UnsupportedVersion(version));
+        }
+
+        let mut chunk = Chunk::default();
+        for _ in 0..r.u32()? {
+            let value = match r.u8()? {
+                TAG_NIL => Value::nil(),
+                TAG_FALSE => Value::bool(false),
+                TAG_TRUE => Value::bool(true),
+                TAG_NUMBER => Value::number(r.f64()?),
+                TAG_STRING => Value::string(r.string()?),
+                tag => return Err(Error::UnknownConstantTag(tag)),
+            };
+            chunk.push_constant(value);
+        }
+
+        let code = r.bytes()?;
+
+        let run_count = r.u32()?;
+        let mut runs = Vec::with_capacity(run_count.min(code.len()));
+        for _ in 0..run_count {
+            let start = r.u32()?;
+            let span = Span {
+                line: r.u32()?,
+                column: r.u32()?,
+                start: r.u32()?,
+                end: r.u32()?,
+            };
+            runs.push((start, span));
+        }
+        // Runs have to start at 0 and be strictly increasing within code
+        if runs
+            .first()
+            .map_or(!code.is_empty(), |&(start, _)| start != 0)
+        {
+            return Err(Error::InvalidLineTable);
+        }
+        let ends = runs.iter().skip(1).map(|&(start, _)| start);
+        for (&(start, span), end) in
+            runs.iter().zip(ends.chain(Some(code.len())))
+        {
+            if start >= end || end > code.len() {
+                return Err(Error::InvalidLineTable);
+            }
+            for &byte in &code[start..end] {
+                chunk.write(byte, span);
+            }
+        }
+
+        let function_count = r.u32()?;
+        for i in 0..function_count {
+            let name = r.bytes()?;
+            let offset = r.u32()?;
+            if i == 0 && (name != b"script" || offset != 0) {
+                return Err(Error::InvalidFunctionTable);
+            }
+        }
+        if function_count == 0 {
+            return Err(Error::InvalidFunctionTable);
+        }
+
+        if !r.0.is_empty() {
+            return Err(Error::TrailingData);
+        }
+        Ok(chunk)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::compiler::compile, *};
+
+    const SOURCE: &str = "var a = \"one\";\n\
+        try { throw a + 2; } catch (e) { print -e.line >= nil; }";
+
+    fn spans(chunk: &Chunk) -> Vec<(usize, Span)> {
+        chunk.span_runs().collect()
+    }
+
+    #[test]
+    fn round_trip() {
+        let chunk = compile(SOURCE).unwrap();
+        let bytes = chunk.serialize();
+        assert_eq!(&bytes[..4], MAGIC);
+
+        let loaded = Chunk::deserialize(&bytes).unwrap();
+        assert_eq!(loaded.code, chunk.code);
+        assert_eq!(loaded.constants.values, chunk.constants.values);
+        assert_eq!(spans(&loaded), spans(&chunk));
+        assert_eq!(loaded.serialize(), bytes);
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let mut bytes = compile(SOURCE).unwrap().serialize();
+        bytes[4..6].copy_from_slice(&(VERSION + 1).to_le_bytes());
+        assert_eq!(
+            Chunk::deserialize(&bytes).err(),
+            Some(Error::UnsupportedVersion(VERSION + 1))
+        );
+        assert_eq!(
+            Chunk::deserialize(b"print 1;").err(),
+            Some(Error::BadMagic)
+        );
+    }
+
+    #[test]
+    fn truncated() {
+        let bytes = compile(SOURCE).unwrap().serialize();
+        for len in MAGIC.len()..bytes.len() {
+            assert_eq!(
+                Chunk::deserialize(&bytes[..len]).err(),
+                Some(Error::Truncated),
+                "truncated to {} bytes",
+                len
+            );
+        }
+
+        let mut bytes = bytes;
+        bytes.push(0);
+        assert_eq!(Chunk::deserialize(&bytes).err(), Some(Error::TrailingData));
+    }
+}
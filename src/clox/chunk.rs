@@ -1,4 +1,12 @@
-use super::value::{Value, ValueArray};
+use std::{
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use super::{
+    scanner::Span,
+    value::{Obj, ObjString, Value, ValueArray},
+};
 
 macro_rules! opcodes {
     ( $vis:vis enum $name:ident { $($variant:ident ( $const:ident ),)* } ) => {
@@ -33,10 +41,17 @@ opcodes!(
         DefineGlobalLong(DEFINE_GLOBAL_LONG),
         SetGlobal(SET_GLOBAL),
         SetGlobalLong(SET_GLOBAL_LONG),
+        GetLocal(GET_LOCAL),
+        SetLocal(SET_LOCAL),
+        GetProperty(GET_PROPERTY),
+        GetPropertyLong(GET_PROPERTY_LONG),
 
         Equal(EQUAL),
         Greater(GREATER),
         Less(LESS),
+        NotEqual(NOT_EQUAL),
+        GreaterEqual(GREATER_EQUAL),
+        LessEqual(LESS_EQUAL),
         Add(ADD),
         Subtract(SUBTRACT),
         Multiply(MULTIPLY),
@@ -45,128 +60,322 @@ opcodes!(
         Negate(NEGATE),
 
         Print(PRINT),
+        Jump(JUMP),
+        Try(TRY),
+        PopHandler(POP_HANDLER),
+        Throw(THROW),
+        EndFinally(END_FINALLY),
         Return(RETURN),
     }
 );
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct Chunk {
     pub(super) code: Vec<u8>,
     lines: Lines,
     pub(super) constants: ValueArray,
+    constant_index: HashMap<ConstantKey, usize>,
 }
 
 #[derive(Clone, Copy)]
-pub struct ConstantIndex(usize);
+pub struct ConstantIndex(pub(super) usize);
+
+/// Identity of a constant used for deduplication. Numbers compare by their
+/// bit pattern, so `0.0` and `-0.0` stay distinct, strings hash by their
+/// stored FNV hash.
+#[derive(Clone, PartialEq, Eq)]
+enum ConstantKey {
+    Nil,
+    Bool(bool),
+    Number(u64),
+    String(ObjString),
+}
+
+impl ConstantKey {
+    fn new(value: &Value) -> Option<Self> {
+        match value {
+            Value::Nil => Some(Self::Nil),
+            Value::Bool(b) => Some(Self::Bool(*b)),
+            Value::Number(n) => Some(Self::Number(n.to_bits())),
+            Value::Obj(o) => match o.as_ref() {
+                Obj::ObjString(s) => Some(Self::String(s.clone())),
+                Obj::ObjError(_) => None,
+            },
+        }
+    }
+}
+
+impl Hash for ConstantKey {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        match self {
+            Self::Nil => state.write_u8(0),
+            Self::Bool(b) => b.hash(state),
+            Self::Number(n) => n.hash(state),
+            Self::String(s) => state.write_u32(s.1),
+        }
+    }
+}
+
+/// Writes index of a long constant as LEB128: 7 bits per byte, starting
+/// from the lowest ones, with high bit set on every byte but the last
+pub(super) fn encode_index(mut index: usize, mut write: impl FnMut(u8)) {
+    loop {
+        let byte = (index & 0x7f) as u8;
+        index >>= 7;
+        if index == 0 {
+            write(byte);
+            return;
+        }
+        write(byte | 0x80);
+    }
+}
+
+/// Reads index written by `encode_index` from start of `code`, returning it
+/// together with number of bytes it took
+pub(super) fn decode_index(code: &[u8]) -> Option<(usize, usize)> {
+    let mut index = 0usize;
+    for (i, &byte) in code.iter().enumerate() {
+        let bits = ((byte & 0x7f) as usize).checked_shl(7 * i as u32)?;
+        index |= bits;
+        if byte & 0x80 == 0 {
+            return Some((index, i + 1));
+        }
+    }
+    None
+}
 
 impl Chunk {
-    pub fn write(&mut self, byte: u8, line: usize) {
+    pub fn write(&mut self, byte: u8, span: Span) {
         self.code.push(byte);
-        self.lines.push(line);
+        self.lines.push(span);
     }
 
     pub fn get_line(&self, offset: usize) -> Option<usize> {
-        self.lines.get_line(offset)
+        self.get_span(offset).map(|span| span.line)
+    }
+
+    /// Source location of the token which byte at `offset` was compiled from
+    pub fn get_span(&self, offset: usize) -> Option<Span> {
+        self.lines.get_span(offset)
     }
 
     pub fn add_constant(&mut self, value: Value) -> ConstantIndex {
-        let position = self.constants.values.iter().position(|x| *x == value);
-        ConstantIndex(if let Some(i) = position {
-            i
-        } else {
-            self.constants.write(value);
-            self.constants.values.len() - 1
-        })
+        let key = ConstantKey::new(&value);
+        if let Some(&index) =
+            key.as_ref().and_then(|k| self.constant_index.get(k))
+        {
+            return ConstantIndex(index);
+        }
+        let index = self.constants.values.len();
+        self.constants.write(value);
+        if let Some(key) = key {
+            self.constant_index.insert(key, index);
+        }
+        ConstantIndex(index)
     }
 
-    fn write_op_with_constant(
+    /// Empty chunk sharing constant pool with this one, so existing constant
+    /// indices stay valid
+    pub(super) fn with_constants(&self) -> Self {
+        Self {
+            constants: self.constants.clone(),
+            constant_index: self.constant_index.clone(),
+            ..Self::default()
+        }
+    }
+
+    /// Appends constant without deduplication, so that indices of loaded
+    /// constant pools are preserved
+    pub(super) fn push_constant(&mut self, value: Value) -> ConstantIndex {
+        let index = self.constants.values.len();
+        if let Some(key) = ConstantKey::new(&value) {
+            self.constant_index.entry(key).or_insert(index);
+        }
+        self.constants.write(value);
+        ConstantIndex(index)
+    }
+
+    /// Runs of the line table, as offset of their first byte and span
+    pub(super) fn span_runs(&self) -> impl Iterator<Item = (usize, Span)> + '_ {
+        self.lines.runs.iter().map(|run| (run.start, run.span))
+    }
+
+    pub(super) fn write_op_with_constant(
         &mut self,
         op_short: u8,
         op_long: u8,
         constant: ConstantIndex,
-        line: usize,
+        span: Span,
     ) {
         let ConstantIndex(constant) = constant;
         if constant <= 0xff {
-            self.write(op_short, line);
-            self.write(constant as u8, line);
-        } else if constant <= 0xff_ffff {
-            self.write(op_long, line);
-            for &x in constant.to_le_bytes()[..3].iter() {
-                self.write(x, line);
-            }
+            self.write(op_short, span);
+            self.write(constant as u8, span);
         } else {
-            panic!("index too big for constant: {}", constant);
+            self.write(op_long, span);
+            encode_index(constant, |byte| self.write(byte, span));
         }
     }
 
     pub fn write_constant(
         &mut self,
         value: Value,
-        line: usize,
+        span: Span,
     ) -> ConstantIndex {
         let index = self.add_constant(value);
         self.write_op_with_constant(
             Opcode::CONSTANT,
             Opcode::CONSTANT_LONG,
             index,
-            line,
+            span,
         );
         index
     }
-    pub fn set_global(&mut self, name: ConstantIndex, line: usize) {
+    pub fn set_global(&mut self, name: ConstantIndex, span: Span) {
         self.write_op_with_constant(
             Opcode::SET_GLOBAL,
             Opcode::SET_GLOBAL_LONG,
             name,
-            line,
+            span,
         );
     }
 
-    pub fn define_global(&mut self, name: ConstantIndex, line: usize) {
+    pub fn define_global(&mut self, name: ConstantIndex, span: Span) {
         self.write_op_with_constant(
             Opcode::DEFINE_GLOBAL,
             Opcode::DEFINE_GLOBAL_LONG,
             name,
-            line,
+            span,
         );
     }
 
-    pub fn get_global(&mut self, name: ConstantIndex, line: usize) {
+    pub fn get_global(&mut self, name: ConstantIndex, span: Span) {
         self.write_op_with_constant(
             Opcode::GET_GLOBAL,
             Opcode::GET_GLOBAL_LONG,
             name,
-            line,
+            span,
+        );
+    }
+
+    pub fn get_property(&mut self, name: ConstantIndex, span: Span) {
+        self.write_op_with_constant(
+            Opcode::GET_PROPERTY,
+            Opcode::GET_PROPERTY_LONG,
+            name,
+            span,
         );
     }
 }
 
-#[derive(Default)]
+/// Run-length encoded source spans, one run per sequence of bytes compiled
+/// from the same token. Runs store offset of their first byte, so lookup is
+/// a binary search.
+#[derive(Default, Clone)]
 struct Lines {
-    line_lens: Vec<u8>,
-    lines: Vec<usize>,
+    runs: Vec<Run>,
+    len: usize,
+}
+
+#[derive(Clone)]
+struct Run {
+    start: usize,
+    span: Span,
 }
 
 impl Lines {
-    pub fn push(&mut self, line: usize) {
-        match self.line_lens.last_mut().zip(self.lines.last_mut()) {
-            Some((len, line_no)) if *len < 255 && line == *line_no => *len += 1,
-            _ => {
-                self.line_lens.push(1);
-                self.lines.push(line);
-            }
+    pub fn push(&mut self, span: Span) {
+        match self.runs.last() {
+            Some(last) if last.span == span => {}
+            _ => self.runs.push(Run {
+                start: self.len,
+                span,
+            }),
         }
+        self.len += 1;
     }
 
-    pub fn get_line(&self, offset: usize) -> Option<usize> {
-        let mut counter = 0usize;
-        for (len, line) in self.line_lens.iter().zip(&self.lines) {
-            if (counter..counter + *len as usize).contains(&offset) {
-                return Some(*line);
-            }
-            counter += *len as usize;
+    pub fn get_span(&self, offset: usize) -> Option<Span> {
+        if offset >= self.len {
+            return None;
+        }
+        let index = self.runs.partition_point(|run| run.start <= offset);
+        Some(self.runs[index - 1].span)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn span(line: usize, column: usize) -> Span {
+        Span {
+            line,
+            column,
+            ..Span::default()
+        }
+    }
+
+    #[test]
+    fn long_runs() {
+        let mut lines = Lines::default();
+        for _ in 0..300 {
+            lines.push(span(1, 1));
+        }
+        lines.push(span(1, 5));
+        for _ in 0..1000 {
+            lines.push(span(2, 3));
+        }
+
+        assert_eq!(lines.runs.len(), 3);
+        assert_eq!(lines.get_span(0), Some(span(1, 1)));
+        assert_eq!(lines.get_span(255), Some(span(1, 1)));
+        assert_eq!(lines.get_span(299), Some(span(1, 1)));
+        assert_eq!(lines.get_span(300), Some(span(1, 5)));
+        assert_eq!(lines.get_span(301), Some(span(2, 3)));
+        assert_eq!(lines.get_span(1300), Some(span(2, 3)));
+        assert_eq!(lines.get_span(1301), None);
+    }
+
+    #[test]
+    fn chunk_spans() {
+        let mut chunk = Chunk::default();
+        chunk.write(Opcode::NIL, span(1, 1));
+        for i in 0..400 {
+            chunk.write_constant(Value::number(i as f64), span(2, 7));
+        }
+        chunk.write(Opcode::RETURN, span(3, 1));
+
+        assert_eq!(chunk.get_line(0), Some(1));
+        // 256 short constants followed by 144 long ones
+        let end = 1 + 256 * 2 + 144 * 3;
+        assert_eq!(chunk.get_span(1), Some(span(2, 7)));
+        assert_eq!(chunk.get_span(end - 1), Some(span(2, 7)));
+        assert_eq!(chunk.get_span(end), Some(span(3, 1)));
+        assert_eq!(chunk.get_span(end + 1), None);
+    }
+
+    #[test]
+    fn constant_dedup() {
+        let mut chunk = Chunk::default();
+        let a = chunk.add_constant(Value::number(1.0));
+        let b = chunk.add_constant(Value::string("a".into()));
+        let c = chunk.add_constant(Value::number(-0.0));
+        assert_eq!(chunk.add_constant(Value::number(1.0)).0, a.0);
+        assert_eq!(chunk.add_constant(Value::string("a".into())).0, b.0);
+        assert_ne!(chunk.add_constant(Value::number(0.0)).0, c.0);
+        assert_eq!(chunk.add_constant(Value::number(f64::NAN)).0, 4);
+        assert_eq!(chunk.add_constant(Value::number(f64::NAN)).0, 4);
+        assert_eq!(chunk.constants.values.len(), 5);
+    }
+
+    #[test]
+    fn index_encoding() {
+        for &index in &[0x100, 0x3fff, 0x4000, 0xff_ffff, 0x100_0000, !0] {
+            let mut bytes = vec![];
+            encode_index(index, |byte| bytes.push(byte));
+            assert_eq!(decode_index(&bytes), Some((index, bytes.len())));
         }
-        None
+        assert_eq!(decode_index(&[0x80, 0x80]), None);
     }
 }
@@ -1,9 +1,9 @@
 use core::fmt;
-use std::iter::Peekable;
+use std::{borrow::Cow, iter::Peekable};
 
 use super::{
     chunk::{Chunk, ConstantIndex, Opcode},
-    scanner::{self, Scanner, Token, TokenType},
+    scanner::{self, Scanner, Span, Token, TokenType},
     value::Value,
 };
 
@@ -26,8 +26,8 @@ impl fmt::Display for TokenError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
             f,
-            "[Line {}] Parser error at '{}': {}",
-            self.0.line, self.0.lexeme, self.1
+            "[Line {}:{}] Parser error at '{}': {}",
+            self.0.span.line, self.0.span.column, self.0.lexeme, self.1
         )
     }
 }
@@ -47,16 +47,16 @@ impl fmt::Display for MulipleErrors {
 
 pub fn compile(source: &str) -> Result<Chunk, Error> {
     let mut chunk = Chunk::default();
-    let mut parser = Parser::new(&source, &mut chunk);
-    let mut line = 0;
+    let mut parser = Parser::new(source, &mut chunk);
+    let mut span = Span::default();
     while let Some(t) = parser.peek() {
-        line = t.line;
+        span = t.span;
         if let Err(()) = parser.declaration() {
             break;
         }
     }
     let mut errors = parser.errors;
-    chunk.write(Opcode::RETURN, line);
+    chunk.write(Opcode::RETURN, span);
     match errors.len() {
         0 => Ok(chunk),
         1 => Err(errors.remove(0)),
@@ -69,7 +69,15 @@ struct Parser<'s, 'c> {
     chunk: &'c mut Chunk,
     errors: Vec<Error>,
     panic_mode: bool,
-    last_line: usize,
+    last_span: Span,
+    locals: Vec<Local<'s>>,
+    scope_depth: usize,
+}
+
+struct Local<'s> {
+    name: Cow<'s, str>,
+    // `None` until variable's initializer is compiled
+    depth: Option<usize>,
 }
 
 #[derive(Debug, Clone, Copy, PartialEq, PartialOrd)]
@@ -152,7 +160,7 @@ fn get_rule<'s, 'c>(type_: TokenType) -> Rule<'s, 'c> {
         LeftBrace    => (       None,       None,       Zero),
         RightBrace   => (       None,       None,       Zero),
         Comma        => (       None,       None,       Zero),
-        Dot          => (       None,       None,       Zero),
+        Dot          => (       None,        dot,       Call),
         Minus        => (      unary,     binary,       Term),
         Plus         => (       None,     binary,       Term),
         Semicolon    => (       None,       None,       Zero),
@@ -170,9 +178,11 @@ fn get_rule<'s, 'c>(type_: TokenType) -> Rule<'s, 'c> {
         String       => (     string,       None,       Zero),
         Number       => (     number,       None,       Zero),
         And          => (       None,       None,       Zero),
+        Catch        => (       None,       None,       Zero),
         Class        => (       None,       None,       Zero),
         Else         => (       None,       None,       Zero),
         False        => (    literal,       None,       Zero),
+        Finally      => (       None,       None,       Zero),
         For          => (       None,       None,       Zero),
         Fun          => (       None,       None,       Zero),
         If           => (       None,       None,       Zero),
@@ -182,7 +192,9 @@ fn get_rule<'s, 'c>(type_: TokenType) -> Rule<'s, 'c> {
         Return       => (       None,       None,       Zero),
         Super        => (       None,       None,       Zero),
         This         => (       None,       None,       Zero),
+        Throw        => (       None,       None,       Zero),
         True         => (    literal,       None,       Zero),
+        Try          => (       None,       None,       Zero),
         Var          => (       None,       None,       Zero),
         While        => (       None,       None,       Zero),
     }
@@ -196,16 +208,39 @@ impl<'s, 'c> Parser<'s, 'c> {
             chunk,
             errors: vec![],
             panic_mode: false,
-            last_line: 0,
+            last_span: Span::default(),
+            locals: vec![],
+            scope_depth: 0,
         }
     }
 
     fn emit(&mut self, bytes: &[u8], token: &Token) {
         for &byte in bytes {
-            self.chunk.write(byte, token.line);
+            self.chunk.write(byte, token.span);
         }
     }
 
+    fn emit_jump(&mut self, op: u8, span: Span) -> usize {
+        self.chunk.write(op, span);
+        self.chunk.write(0xff, span);
+        self.chunk.write(0xff, span);
+        self.chunk.code.len() - 2
+    }
+
+    fn patch_jump(&mut self, offset: usize, token: &Token) -> Result<(), ()> {
+        let jump = self.chunk.code.len() - offset - 2;
+        if jump > u16::MAX as usize {
+            let token = token.clone().into_owned();
+            self.error(
+                TokenError(token, "Too much code to jump over.".into()).into(),
+            );
+            return Err(());
+        }
+        self.chunk.code[offset..offset + 2]
+            .copy_from_slice(&(jump as u16).to_le_bytes());
+        Ok(())
+    }
+
     fn error(&mut self, error: Error) -> Option<()> {
         if !self.panic_mode {
             self.errors.push(error);
@@ -229,10 +264,14 @@ impl<'s, 'c> Parser<'s, 'c> {
     fn advance(&mut self) -> Option<Token<'s>> {
         self.consume_errors();
         let token = self.scanner.next().transpose().unwrap()?;
-        self.last_line = token.line;
+        self.last_span = token.span;
         Some(token)
     }
 
+    fn check(&mut self, type_: TokenType) -> bool {
+        matches!(self.peek(), Some(t) if t.type_ == type_)
+    }
+
     // TODO: Probably want to correctly detect Eof
     fn match_(&mut self, type_: TokenType) -> Option<Token<'s>> {
         if self.peek()?.type_ == type_ {
@@ -279,7 +318,9 @@ impl<'s, 'c> Parser<'s, 'c> {
                 | TokenType::If
                 | TokenType::While
                 | TokenType::Print
-                | TokenType::Return => return,
+                | TokenType::Return
+                | TokenType::Throw
+                | TokenType::Try => return,
                 _ => (),
             }
             self.advance();
@@ -288,19 +329,19 @@ impl<'s, 'c> Parser<'s, 'c> {
 
     fn number(&mut self, _can_assign: bool) -> Result<(), ()> {
         let token = self.advance().unwrap();
-        let line = token.line;
+        let span = token.span;
         let value = token.lexeme.parse::<f64>().map_err(|e| {
             let error = TokenError(token.into_owned(), e.to_string());
             self.error(error.into());
         })?;
-        self.chunk.write_constant(Value::number(value), line);
+        self.chunk.write_constant(Value::number(value), span);
         Ok(())
     }
 
     fn string(&mut self, _can_assign: bool) -> Result<(), ()> {
         let token = self.advance().unwrap();
         let string = token.lexeme[1..token.lexeme.len() - 1].to_string();
-        self.chunk.write_constant(Value::string(string), token.line);
+        self.chunk.write_constant(Value::string(string), token.span);
         Ok(())
     }
 
@@ -309,30 +350,81 @@ impl<'s, 'c> Parser<'s, 'c> {
         name: Token,
         can_assign: bool,
     ) -> Result<(), ()> {
-        let line = name.line;
-        let index = self.identifier_constant(name);
-
-        if can_assign && self.match_(TokenType::Equal).is_some() {
+        let span = name.span;
+        let slot = self.resolve_local(&name)?;
+        let assign = can_assign && self.match_(TokenType::Equal).is_some();
+        if assign {
             self.expression()?;
-            self.chunk.set_global(index, line);
-        } else {
-            self.chunk.get_global(index, line);
+        }
+
+        match slot {
+            Some(slot) => {
+                let op = if assign {
+                    Opcode::SET_LOCAL
+                } else {
+                    Opcode::GET_LOCAL
+                };
+                self.chunk.write(op, span);
+                self.chunk.write(slot, span);
+            }
+            None => {
+                let index = self.identifier_constant(name);
+                if assign {
+                    self.chunk.set_global(index, span);
+                } else {
+                    self.chunk.get_global(index, span);
+                }
+            }
         }
 
         Ok(())
     }
 
+    fn resolve_local(&mut self, name: &Token) -> Result<Option<u8>, ()> {
+        let slot = self
+            .locals
+            .iter()
+            .rposition(|local| local.name == name.lexeme);
+        match slot {
+            Some(slot) if self.locals[slot].depth.is_none() => {
+                let token = name.clone().into_owned();
+                self.error(
+                    TokenError(
+                        token,
+                        "Can't read local variable in its own initializer."
+                            .into(),
+                    )
+                    .into(),
+                );
+                Err(())
+            }
+            Some(slot) => Ok(Some(slot as u8)),
+            None => Ok(None),
+        }
+    }
+
     fn variable(&mut self, can_assign: bool) -> Result<(), ()> {
         let name = self.advance().unwrap();
         self.named_variable(name, can_assign)
     }
 
+    fn dot(&mut self, _can_assign: bool) -> Result<(), ()> {
+        self.advance().unwrap();
+        let name = self
+            .consume(TokenType::Identifier, "Expect property name after '.'.")
+            .ok_or(())?;
+        let span = name.span;
+        let index = self.identifier_constant(name);
+        self.chunk.get_property(index, span);
+        Ok(())
+    }
+
     fn literal(&mut self, _can_assign: bool) -> Result<(), ()> {
         let token = self.advance().unwrap();
         match token.type_ {
-            TokenType::Nil => self.chunk.write(Opcode::NIL, token.line),
-            TokenType::True => self.chunk.write(Opcode::TRUE, token.line),
-            TokenType::False => self.chunk.write(Opcode::FALSE, token.line),
+            TokenType::Nil => self.chunk.write(Opcode::NIL, token.span),
+            TokenType::True => self.chunk.write(Opcode::TRUE, token.span),
+            TokenType::False => self.chunk.write(Opcode::FALSE, token.span),
             _ => return Err(()),
         }
         Ok(())
@@ -350,8 +442,8 @@ impl<'s, 'c> Parser<'s, 'c> {
         let token = self.advance().unwrap();
         self.parse_precedence(Precedence::Call)?;
         match token.type_ {
-            TokenType::Bang => self.chunk.write(Opcode::NOT, token.line),
-            TokenType::Minus => self.chunk.write(Opcode::NEGATE, token.line),
+            TokenType::Bang => self.chunk.write(Opcode::NOT, token.span),
+            TokenType::Minus => self.chunk.write(Opcode::NEGATE, token.span),
             _ => return Err(()),
         }
         Ok(())
@@ -374,10 +466,10 @@ impl<'s, 'c> Parser<'s, 'c> {
             TokenType::LessEqual => {
                 self.emit(&[Opcode::GREATER, Opcode::NOT], &op)
             }
-            TokenType::Plus => self.chunk.write(Opcode::ADD, op.line),
-            TokenType::Minus => self.chunk.write(Opcode::SUBTRACT, op.line),
-            TokenType::Star => self.chunk.write(Opcode::MULTIPLY, op.line),
-            TokenType::Slash => self.chunk.write(Opcode::DIVIDE, op.line),
+            TokenType::Plus => self.chunk.write(Opcode::ADD, op.span),
+            TokenType::Minus => self.chunk.write(Opcode::SUBTRACT, op.span),
+            TokenType::Star => self.chunk.write(Opcode::MULTIPLY, op.span),
+            TokenType::Slash => self.chunk.write(Opcode::DIVIDE, op.span),
             _ => return Err(()),
         }
         Ok(())
@@ -427,13 +519,72 @@ impl<'s, 'c> Parser<'s, 'c> {
             .add_constant(Value::string(name.lexeme.into_owned()))
     }
 
-    fn parse_variable(&mut self, error_msg: &str) -> Result<ConstantIndex, ()> {
+    // Returns `None` for local variables, which don't need a name constant
+    fn parse_variable(
+        &mut self,
+        error_msg: &str,
+    ) -> Result<Option<ConstantIndex>, ()> {
         let token = self.consume(TokenType::Identifier, error_msg).ok_or(())?;
-        Ok(self.identifier_constant(token))
+        if self.scope_depth > 0 {
+            self.declare_local(token)?;
+            return Ok(None);
+        }
+        Ok(Some(self.identifier_constant(token)))
+    }
+
+    fn declare_local(&mut self, name: Token<'s>) -> Result<(), ()> {
+        let duplicate = self
+            .locals
+            .iter()
+            .rev()
+            .take_while(|local| {
+                local.depth.is_none_or(|depth| depth >= self.scope_depth)
+            })
+            .any(|local| local.name == name.lexeme);
+        let message = if duplicate {
+            "Already a variable with this name in this scope."
+        } else if self.locals.len() > u8::MAX as usize {
+            "Too many local variables in function."
+        } else {
+            self.add_local(name.lexeme, None);
+            return Ok(());
+        };
+        self.error(TokenError(name.into_owned(), message.into()).into());
+        Err(())
     }
 
-    fn define_variable(&mut self, global: ConstantIndex, token: &Token) {
-        self.chunk.define_global(global, token.line)
+    fn add_local(&mut self, name: Cow<'s, str>, depth: Option<usize>) {
+        self.locals.push(Local { name, depth });
+    }
+
+    fn define_variable(
+        &mut self,
+        global: Option<ConstantIndex>,
+        token: &Token,
+    ) {
+        match global {
+            Some(global) => self.chunk.define_global(global, token.span),
+            None => {
+                self.locals.last_mut().unwrap().depth = Some(self.scope_depth)
+            }
+        }
+    }
+
+    fn begin_scope(&mut self) {
+        self.scope_depth += 1;
+    }
+
+    fn end_scope(&mut self, span: Span) {
+        self.scope_depth -= 1;
+        while self
+            .locals
+            .last()
+            .and_then(|local| local.depth)
+            .is_some_and(|depth| depth > self.scope_depth)
+        {
+            self.locals.pop();
+            self.chunk.write(Opcode::POP, span);
+        }
     }
 
     fn expression(&mut self) -> Result<(), ()> {
@@ -457,7 +608,7 @@ impl<'s, 'c> Parser<'s, 'c> {
         if self.match_(TokenType::Equal).is_some() {
             self.expression()?;
         } else {
-            self.chunk.write(Opcode::NIL, var.line);
+            self.chunk.write(Opcode::NIL, var.span);
         }
         self.consume(
             TokenType::Semicolon,
@@ -471,17 +622,146 @@ impl<'s, 'c> Parser<'s, 'c> {
     fn statement(&mut self) -> Result<(), ()> {
         if self.match_(TokenType::Print).is_some() {
             self.print_statement()
+        } else if let Some(throw) = self.match_(TokenType::Throw) {
+            self.throw_statement(throw)
+        } else if let Some(try_) = self.match_(TokenType::Try) {
+            self.try_statement(try_)
+        } else if self.match_(TokenType::LeftBrace).is_some() {
+            self.begin_scope();
+            let ret = self.block();
+            self.end_scope(self.last_span);
+            ret
         } else {
             self.expression_statement()
         }
     }
 
+    fn block(&mut self) -> Result<(), ()> {
+        while !self.check(TokenType::RightBrace) && self.peek().is_some() {
+            self.declaration()?;
+        }
+        self.consume(TokenType::RightBrace, "Expect '}' after block.")
+            .ok_or(())?;
+        Ok(())
+    }
+
+    fn scoped_block(&mut self, message: &str) -> Result<(), ()> {
+        self.consume(TokenType::LeftBrace, message).ok_or(())?;
+        self.begin_scope();
+        let ret = self.block();
+        self.end_scope(self.last_span);
+        ret
+    }
+
+    fn throw_statement(&mut self, throw: Token) -> Result<(), ()> {
+        self.expression()?;
+        self.consume(TokenType::Semicolon, "Expect ';' after thrown value.")
+            .ok_or(())?;
+        self.chunk.write(Opcode::THROW, throw.span);
+        Ok(())
+    }
+
+    // Layout of compiled `try` statement:
+    //
+    //     TRY -> catch          ; or -> pending without catch clause
+    //     <try body>
+    //     POP_HANDLER
+    //     JUMP -> normal
+    // catch:                    ; stack: [exception]
+    //     TRY -> rethrow
+    //     <catch body>          ; exception is bound as local
+    //     POP_HANDLER
+    //     POP
+    //     JUMP -> normal
+    // rethrow:                  ; stack: [exception, new exception]
+    //     SET_LOCAL <exception>
+    //     POP
+    //     JUMP -> pending
+    // normal:
+    //     NIL
+    //     FALSE
+    //     JUMP -> finally
+    // pending:                  ; stack: [exception]
+    //     TRUE
+    // finally:                  ; stack: [exception, is pending]
+    //     <finally body>
+    //     END_FINALLY           ; rethrows pending exception
+    fn try_statement(&mut self, try_: Token) -> Result<(), ()> {
+        let span = try_.span;
+        let handler = self.emit_jump(Opcode::TRY, span);
+        self.scoped_block("Expect '{' after try.")?;
+        self.chunk.write(Opcode::POP_HANDLER, self.last_span);
+        let mut to_normal = vec![self.emit_jump(Opcode::JUMP, self.last_span)];
+
+        let mut to_pending = vec![];
+        if let Some(catch) = self.match_(TokenType::Catch) {
+            self.patch_jump(handler, &catch)?;
+            self.consume(TokenType::LeftParen, "Expect '(' after catch.")
+                .ok_or(())?;
+            let name = self
+                .consume(TokenType::Identifier, "Expect exception name.")
+                .ok_or(())?;
+            self.consume(
+                TokenType::RightParen,
+                "Expect ')' after exception name.",
+            )
+            .ok_or(())?;
+
+            self.begin_scope();
+            let slot = self.locals.len() as u8;
+            self.declare_local(name)?;
+            self.define_variable(None, &catch);
+            let rethrow = self.emit_jump(Opcode::TRY, catch.span);
+            self.scoped_block("Expect '{' before catch body.")?;
+            self.chunk.write(Opcode::POP_HANDLER, self.last_span);
+            self.end_scope(self.last_span);
+            to_normal.push(self.emit_jump(Opcode::JUMP, self.last_span));
+
+            self.patch_jump(rethrow, &catch)?;
+            self.chunk.write(Opcode::SET_LOCAL, catch.span);
+            self.chunk.write(slot, catch.span);
+            self.chunk.write(Opcode::POP, catch.span);
+            to_pending.push(self.emit_jump(Opcode::JUMP, catch.span));
+        } else if self.check(TokenType::Finally) {
+            to_pending.push(handler);
+        } else {
+            self.consume(
+                TokenType::Finally,
+                "Expect 'catch' or 'finally' after try block.",
+            );
+            return Err(());
+        }
+
+        for jump in to_normal {
+            self.patch_jump(jump, &try_)?;
+        }
+        self.emit(&[Opcode::NIL, Opcode::FALSE], &try_);
+        let to_finally = self.emit_jump(Opcode::JUMP, span);
+        for jump in to_pending {
+            self.patch_jump(jump, &try_)?;
+        }
+        self.chunk.write(Opcode::TRUE, span);
+        self.patch_jump(to_finally, &try_)?;
+
+        // Pending exception and its flag occupy two unnamed slots
+        self.begin_scope();
+        self.add_local("".into(), Some(self.scope_depth));
+        self.add_local("".into(), Some(self.scope_depth));
+        if self.match_(TokenType::Finally).is_some() {
+            self.scoped_block("Expect '{' after finally.")?;
+        }
+        self.locals.truncate(self.locals.len() - 2);
+        self.scope_depth -= 1;
+        self.chunk.write(Opcode::END_FINALLY, self.last_span);
+        Ok(())
+    }
+
     fn print_statement(&mut self) -> Result<(), ()> {
         self.expression()?;
         let token = self
             .consume(TokenType::Semicolon, "Expect ';' after value.")
             .ok_or(())?;
-        self.chunk.write(Opcode::PRINT, token.line);
+        self.chunk.write(Opcode::PRINT, token.span);
         Ok(())
     }
 
@@ -490,7 +770,7 @@ impl<'s, 'c> Parser<'s, 'c> {
         let token = self
             .consume(TokenType::Semicolon, "Expect ';' after value.")
             .ok_or(())?;
-        self.chunk.write(Opcode::POP, token.line);
+        self.chunk.write(Opcode::POP, token.span);
         Ok(())
     }
 }
@@ -1,114 +1,286 @@
-use super::chunk::{Chunk, Opcode};
+use std::{
+    io::{self, Write},
+    str::FromStr,
+};
 
-pub fn disassembly_chunk(chunk: &Chunk, name: &str) {
-    println!("== {} ==", name);
+use serde::Serialize;
 
-    let mut offset = 0;
-    while offset < chunk.code.len() {
-        offset = disassembly_instruction(chunk, offset);
+use super::chunk::{self, Chunk, Opcode};
+
+/// How operand bytes following an opcode are encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    None,
+    Constant,
+    ConstantLong,
+    Byte,
+    Jump,
+}
+
+pub fn opcode_info(opcode: &Opcode) -> (&'static str, OperandKind) {
+    use OperandKind::*;
+    match opcode {
+        Opcode::Constant => ("OP_CONSTANT", Constant),
+        Opcode::ConstantLong => ("OP_CONSTANT_LONG", ConstantLong),
+        Opcode::Nil => ("OP_NIL", None),
+        Opcode::True => ("OP_TRUE", None),
+        Opcode::False => ("OP_FALSE", None),
+        Opcode::Pop => ("OP_POP", None),
+        Opcode::GetGlobal => ("OP_GET_GLOBAL", Constant),
+        Opcode::GetGlobalLong => ("OP_GET_GLOBAL_LONG", ConstantLong),
+        Opcode::DefineGlobal => ("OP_DEFINE_GLOBAL", Constant),
+        Opcode::DefineGlobalLong => ("OP_DEFINE_GLOBAL_LONG", ConstantLong),
+        Opcode::SetGlobal => ("OP_SET_GLOBAL", Constant),
+        Opcode::SetGlobalLong => ("OP_SET_GLOBAL_LONG", ConstantLong),
+        Opcode::GetLocal => ("OP_GET_LOCAL", Byte),
+        Opcode::SetLocal => ("OP_SET_LOCAL", Byte),
+        Opcode::GetProperty => ("OP_GET_PROPERTY", Constant),
+        Opcode::GetPropertyLong => ("OP_GET_PROPERTY_LONG", ConstantLong),
+
+        Opcode::Equal => ("OP_EQUAL", None),
+        Opcode::Greater => ("OP_GREATER", None),
+        Opcode::Less => ("OP_LESS", None),
+        Opcode::NotEqual => ("OP_NOT_EQUAL", None),
+        Opcode::GreaterEqual => ("OP_GREATER_EQUAL", None),
+        Opcode::LessEqual => ("OP_LESS_EQUAL", None),
+        Opcode::Add => ("OP_ADD", None),
+        Opcode::Subtract => ("OP_SUBSTRACT", None),
+        Opcode::Multiply => ("OP_MULTIPLY", None),
+        Opcode::Divide => ("OP_DIVIDE", None),
+        Opcode::Not => ("OP_NOT", None),
+        Opcode::Negate => ("OP_NEGATE", None),
+
+        Opcode::Print => ("OP_PRINT", None),
+        Opcode::Jump => ("OP_JUMP", Jump),
+        Opcode::Try => ("OP_TRY", Jump),
+        Opcode::PopHandler => ("OP_POP_HANDLER", None),
+        Opcode::Throw => ("OP_THROW", None),
+        Opcode::EndFinally => ("OP_END_FINALLY", None),
+        Opcode::Return => ("OP_RETURN", None),
     }
 }
 
-fn bytes(chunk: &Chunk, offset: usize, size: usize) {
-    for i in 0..4 {
-        if i < size {
-            print!("{:02x} ", chunk.code[offset + i]);
-        } else {
-            print!("   ");
+/// Decoded instruction, as shown in disassembly
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Instruction {
+    pub offset: usize,
+    pub line: Option<usize>,
+    /// `None` for unknown opcodes
+    pub opcode: Option<&'static str>,
+    pub bytes: Vec<u8>,
+    pub operand: Operand,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Operand {
+    None,
+    /// Index into constant pool, together with the constant it points to
+    Constant {
+        index: usize,
+        value: String,
+    },
+    Slot {
+        slot: u8,
+    },
+    /// Absolute offset of jump target
+    Jump {
+        target: usize,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Text,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(Self::Text),
+            "json" => Ok(Self::Json),
+            _ => Err("Unsupported format\nAvailable: text, json"),
         }
     }
 }
 
-fn simple_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    bytes(chunk, offset, 1);
-    println!("{}", name);
-    offset + 1
+pub fn disassemble(chunk: &Chunk) -> Vec<Instruction> {
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < chunk.code.len() {
+        let instruction = decode_instruction(chunk, offset);
+        offset += instruction.bytes.len();
+        instructions.push(instruction);
+    }
+    instructions
 }
 
-fn constant_instruction(name: &str, chunk: &Chunk, offset: usize) -> usize {
-    bytes(chunk, offset, 2);
-    let index = chunk.code[offset + 1];
-    let constant = &chunk.constants.values[index as usize];
-    println!("{:16} {:4} '{:?}'", name, index, constant);
-    offset + 2
+pub fn decode_instruction(chunk: &Chunk, offset: usize) -> Instruction {
+    let code = &chunk.code;
+    let constant = |index: usize| Operand::Constant {
+        index,
+        value: format!("{:?}", chunk.constants.values[index]),
+    };
+    let info = Opcode::check(code[offset]).map(|opcode| opcode_info(&opcode));
+    let (operand, len) = match info.map(|(_, kind)| kind) {
+        None | Some(OperandKind::None) => (Operand::None, 1),
+        Some(OperandKind::Constant) => (constant(code[offset + 1] as usize), 2),
+        Some(OperandKind::ConstantLong) => {
+            let (index, len) =
+                chunk::decode_index(&code[offset + 1..]).unwrap();
+            (constant(index), 1 + len)
+        }
+        Some(OperandKind::Byte) => (
+            Operand::Slot {
+                slot: code[offset + 1],
+            },
+            2,
+        ),
+        Some(OperandKind::Jump) => {
+            let jump = u16::from_le_bytes([code[offset + 1], code[offset + 2]]);
+            let target = offset + 3 + jump as usize;
+            (Operand::Jump { target }, 3)
+        }
+    };
+    Instruction {
+        offset,
+        line: chunk.get_line(offset),
+        opcode: info.map(|(name, _)| name),
+        bytes: code[offset..offset + len].to_vec(),
+        operand,
+    }
 }
 
-fn constant_long_instruction(
+pub fn write_chunk(
+    out: &mut dyn Write,
+    chunk: &Chunk,
     name: &str,
+) -> io::Result<()> {
+    render(out, name, &disassemble(chunk), Format::Text)
+}
+
+pub fn write_instruction(
+    out: &mut dyn Write,
     chunk: &Chunk,
     offset: usize,
-) -> usize {
-    bytes(chunk, offset, 4);
-    let mut bytes = [0; std::mem::size_of::<usize>()];
-    for i in 0..3 {
-        bytes[i] = chunk.code[offset + i + 1];
+) -> io::Result<usize> {
+    let instruction = decode_instruction(chunk, offset);
+    let previous_line = offset.checked_sub(1).and_then(|o| chunk.get_line(o));
+    write_text(out, &instruction, previous_line)?;
+    Ok(offset + instruction.bytes.len())
+}
+
+pub fn render(
+    out: &mut dyn Write,
+    name: &str,
+    instructions: &[Instruction],
+    format: Format,
+) -> io::Result<()> {
+    match format {
+        Format::Text => {
+            writeln!(out, "== {} ==", name)?;
+            let mut previous_line = None;
+            for instruction in instructions {
+                write_text(out, instruction, previous_line)?;
+                previous_line = instruction.line;
+            }
+            Ok(())
+        }
+        Format::Json => {
+            let json = serde_json::json!({
+                "name": name,
+                "instructions": instructions,
+            });
+            serde_json::to_writer(&mut *out, &json)?;
+            writeln!(out)
+        }
     }
-    let index = usize::from_le_bytes(bytes);
-    let constant = &chunk.constants.values[index as usize];
-    println!("{:16} {:4} '{:?}'", name, index, constant);
-    offset + 4
 }
 
-pub fn disassembly_instruction(chunk: &Chunk, offset: usize) -> usize {
-    print!("{:04} ", offset);
-    if offset > 0 && chunk.get_line(offset) == chunk.get_line(offset - 1) {
-        print!("   | ");
-    } else {
-        print!("{:4} ", chunk.get_line(offset).unwrap());
+/// Writes instruction as single line of text listing, with `|` in place of
+/// line number when it's the same as `previous_line`
+pub fn write_text(
+    out: &mut dyn Write,
+    instruction: &Instruction,
+    previous_line: Option<usize>,
+) -> io::Result<()> {
+    write!(out, "{:04} ", instruction.offset)?;
+    match instruction.line {
+        Some(_) if instruction.line == previous_line => write!(out, "   | ")?,
+        Some(line) => write!(out, "{:4} ", line)?,
+        None => write!(out, "     ")?,
     }
 
-    let instruction = chunk.code[offset];
-    match Opcode::check(instruction) {
-        Some(Opcode::Constant) => {
-            constant_instruction("OP_CONSTANT", chunk, offset)
-        }
-        Some(Opcode::ConstantLong) => {
-            constant_long_instruction("OP_CONSTANT_LONG", chunk, offset)
-        }
-        Some(Opcode::Nil) => simple_instruction("OP_NIL", chunk, offset),
-        Some(Opcode::True) => simple_instruction("OP_TRUE", chunk, offset),
-        Some(Opcode::False) => simple_instruction("OP_FALSE", chunk, offset),
-        Some(Opcode::Pop) => simple_instruction("OP_POP", chunk, offset),
-        Some(Opcode::GetGlobal) => {
-            constant_instruction("OP_GET_GLOBAL", chunk, offset)
-        }
-        Some(Opcode::GetGlobalLong) => {
-            constant_long_instruction("OP_GET_GLOBAL_LONG", chunk, offset)
-        }
-        Some(Opcode::DefineGlobal) => {
-            constant_instruction("OP_DEFINE_GLOBAL", chunk, offset)
-        }
-        Some(Opcode::DefineGlobalLong) => {
-            constant_long_instruction("OP_DEFINE_GLOBAL_LONG", chunk, offset)
-        }
-        Some(Opcode::SetGlobal) => {
-            constant_instruction("OP_SET_GLOBAL", chunk, offset)
-        }
-        Some(Opcode::SetGlobalLong) => {
-            constant_long_instruction("OP_SET_GLOBAL_LONG", chunk, offset)
+    // Long indices can be wider, but only first 4 bytes fit in a column
+    for i in 0..4 {
+        match instruction.bytes.get(i) {
+            Some(byte) => write!(out, "{:02x} ", byte)?,
+            None => write!(out, "   ")?,
         }
+    }
 
-        Some(Opcode::Equal) => simple_instruction("OP_EQUAL", chunk, offset),
-        Some(Opcode::Greater) => {
-            simple_instruction("OP_GREATER", chunk, offset)
+    let name = match instruction.opcode {
+        Some(name) => name,
+        None => {
+            return writeln!(out, "Unknown opcode {}", instruction.bytes[0])
         }
-        Some(Opcode::Less) => simple_instruction("OP_LESS", chunk, offset),
-        Some(Opcode::Add) => simple_instruction("OP_ADD", chunk, offset),
-        Some(Opcode::Subtract) => {
-            simple_instruction("OP_SUBSTRACT", chunk, offset)
+    };
+    match &instruction.operand {
+        Operand::None => writeln!(out, "{}", name),
+        Operand::Constant { index, value } => {
+            writeln!(out, "{:16} {:4} '{}'", name, index, value)
         }
-        Some(Opcode::Multiply) => {
-            simple_instruction("OP_MULTIPLY", chunk, offset)
+        Operand::Slot { slot } => writeln!(out, "{:16} {:4}", name, slot),
+        Operand::Jump { target } => {
+            writeln!(out, "{:16} {:4} -> {}", name, instruction.offset, target)
         }
-        Some(Opcode::Divide) => simple_instruction("OP_DIVIDE", chunk, offset),
-        Some(Opcode::Not) => simple_instruction("OP_NOT", chunk, offset),
-        Some(Opcode::Negate) => simple_instruction("OP_NEGATE", chunk, offset),
+    }
+}
 
-        Some(Opcode::Print) => simple_instruction("OP_PRINT", chunk, offset),
-        Some(Opcode::Return) => simple_instruction("OP_RETURN", chunk, offset),
-        None => {
-            println!("Unknown opcode {}", instruction);
-            offset + 1
-        }
+#[cfg(test)]
+mod tests {
+    use super::{super::compiler::compile, *};
+
+    #[test]
+    fn records() {
+        let chunk = compile("var a = 1;\nprint a;").unwrap();
+        let instructions = disassemble(&chunk);
+        assert_eq!(
+            instructions[1],
+            Instruction {
+                offset: 2,
+                line: Some(1),
+                opcode: Some("OP_DEFINE_GLOBAL"),
+                bytes: vec![Opcode::DEFINE_GLOBAL, 0],
+                operand: Operand::Constant {
+                    index: 0,
+                    value: "\"a\"".into()
+                },
+            }
+        );
+
+        let mut text = vec![];
+        render(&mut text, "test", &instructions, Format::Text).unwrap();
+        assert_eq!(
+            String::from_utf8(text).unwrap(),
+            "== test ==
+0000    1 00 01       OP_CONSTANT         1 '1.0'
+0002    | 08 00       OP_DEFINE_GLOBAL    0 '\"a\"'
+0004    2 06 00       OP_GET_GLOBAL       0 '\"a\"'
+0006    | 1c          OP_PRINT
+0007    | 22          OP_RETURN
+"
+        );
+
+        let mut json = vec![];
+        render(&mut json, "test", &instructions[3..], Format::Json).unwrap();
+        assert_eq!(
+            String::from_utf8(json).unwrap(),
+            r#"{"instructions":[{"bytes":[28],"line":2,"offset":6,"opcode":"OP_PRINT","operand":{"kind":"none"}},{"bytes":[34],"line":2,"offset":7,"opcode":"OP_RETURN","operand":{"kind":"none"}}],"name":"test"}
+"#
+        );
     }
 }
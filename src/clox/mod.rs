@@ -1,7 +1,12 @@
+pub mod assembler;
+pub mod bytecode;
 pub mod chunk;
 pub mod compiler;
 pub mod debug;
+pub mod optimizer;
 pub mod scanner;
 pub mod table;
+pub mod trace;
 pub mod value;
+pub mod verifier;
 pub mod vm;
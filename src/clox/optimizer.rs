@@ -0,0 +1,382 @@
+use std::str::FromStr;
+
+use super::{
+    chunk::{self, Chunk, Opcode},
+    scanner::Span,
+    value::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OptLevel {
+    O0,
+    #[default]
+    O1,
+}
+
+impl FromStr for OptLevel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "0" => Ok(Self::O0),
+            "1" => Ok(Self::O1),
+            _ => Err("Unsupported optimization level\nAvailable: 0, 1"),
+        }
+    }
+}
+
+pub fn optimize(chunk: &Chunk, level: OptLevel) -> Chunk {
+    match level {
+        OptLevel::O0 => chunk.clone(),
+        OptLevel::O1 => {
+            let mut instructions = decode(chunk);
+            let targets = jump_targets(&instructions);
+            let mut i = 0;
+            while i < instructions.len() {
+                if peephole(&mut instructions, i, &targets, chunk) {
+                    // Rewritten instruction may form a new window with the
+                    // ones before it
+                    i = i.saturating_sub(2);
+                } else {
+                    i += 1;
+                }
+            }
+            encode(chunk, &instructions)
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    None,
+    Byte(u8),
+    // Index into constant pool, long opcodes are stored as their short form
+    Constant(usize),
+    // Folded value, not yet in constant pool
+    Value(Value),
+    // Absolute offset in the original chunk
+    Jump(usize),
+}
+
+#[derive(Debug, Clone)]
+struct Instruction {
+    // Offset in the original chunk
+    offset: usize,
+    op: u8,
+    operand: Operand,
+    span: Span,
+}
+
+fn short_form(op: u8) -> Option<u8> {
+    match Opcode::check(op)? {
+        Opcode::Constant | Opcode::ConstantLong => Some(Opcode::CONSTANT),
+        Opcode::GetGlobal | Opcode::GetGlobalLong => Some(Opcode::GET_GLOBAL),
+        Opcode::DefineGlobal | Opcode::DefineGlobalLong => {
+            Some(Opcode::DEFINE_GLOBAL)
+        }
+        Opcode::SetGlobal | Opcode::SetGlobalLong => Some(Opcode::SET_GLOBAL),
+        Opcode::GetProperty | Opcode::GetPropertyLong => {
+            Some(Opcode::GET_PROPERTY)
+        }
+        _ => None,
+    }
+}
+
+fn long_form(op: u8) -> u8 {
+    match op {
+        Opcode::CONSTANT => Opcode::CONSTANT_LONG,
+        Opcode::GET_GLOBAL => Opcode::GET_GLOBAL_LONG,
+        Opcode::DEFINE_GLOBAL => Opcode::DEFINE_GLOBAL_LONG,
+        Opcode::SET_GLOBAL => Opcode::SET_GLOBAL_LONG,
+        Opcode::GET_PROPERTY => Opcode::GET_PROPERTY_LONG,
+        _ => unreachable!(),
+    }
+}
+
+fn decode(chunk: &Chunk) -> Vec<Instruction> {
+    let code = &chunk.code;
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let op = code[offset];
+        let span = chunk.get_span(offset).unwrap_or_default();
+        let (op, operand, len) = match Opcode::check(op) {
+            Some(Opcode::Constant)
+            | Some(Opcode::GetGlobal)
+            | Some(Opcode::DefineGlobal)
+            | Some(Opcode::SetGlobal)
+            | Some(Opcode::GetProperty) => {
+                (op, Operand::Constant(code[offset + 1] as usize), 2)
+            }
+            Some(Opcode::ConstantLong)
+            | Some(Opcode::GetGlobalLong)
+            | Some(Opcode::DefineGlobalLong)
+            | Some(Opcode::SetGlobalLong)
+            | Some(Opcode::GetPropertyLong) => {
+                let (index, len) =
+                    chunk::decode_index(&code[offset + 1..]).unwrap();
+                (short_form(op).unwrap(), Operand::Constant(index), 1 + len)
+            }
+            Some(Opcode::GetLocal) | Some(Opcode::SetLocal) => {
+                (op, Operand::Byte(code[offset + 1]), 2)
+            }
+            Some(Opcode::Jump) | Some(Opcode::Try) => {
+                let jump =
+                    u16::from_le_bytes([code[offset + 1], code[offset + 2]]);
+                (op, Operand::Jump(offset + 3 + jump as usize), 3)
+            }
+            _ => (op, Operand::None, 1),
+        };
+        instructions.push(Instruction {
+            offset,
+            op,
+            operand,
+            span,
+        });
+        offset += len;
+    }
+    instructions
+}
+
+fn jump_targets(instructions: &[Instruction]) -> Vec<usize> {
+    let mut targets: Vec<usize> = instructions
+        .iter()
+        .filter_map(|i| match i.operand {
+            Operand::Jump(target) => Some(target),
+            _ => None,
+        })
+        .collect();
+    targets.sort_unstable();
+    targets
+}
+
+fn constant_value(instruction: &Instruction, chunk: &Chunk) -> Option<Value> {
+    match (instruction.op, &instruction.operand) {
+        (Opcode::CONSTANT, Operand::Constant(index)) => {
+            Some(chunk.constants.values[*index].clone())
+        }
+        (Opcode::CONSTANT, Operand::Value(value)) => Some(value.clone()),
+        (Opcode::NIL, _) => Some(Value::nil()),
+        (Opcode::TRUE, _) => Some(Value::bool(true)),
+        (Opcode::FALSE, _) => Some(Value::bool(false)),
+        _ => None,
+    }
+}
+
+fn fold_binary(op: u8, a: Value, b: Value) -> Option<Value> {
+    // Only fold what can't fail, errors are left to be reported at runtime
+    match (op, a, b) {
+        (Opcode::EQUAL, a, b) => Some(Value::bool(a == b)),
+        (op, Value::Number(a), Value::Number(b)) => match op {
+            Opcode::GREATER => Some(Value::bool(a > b)),
+            Opcode::LESS => Some(Value::bool(a < b)),
+            Opcode::ADD => Some(Value::number(a + b)),
+            Opcode::SUBTRACT => Some(Value::number(a - b)),
+            Opcode::MULTIPLY => Some(Value::number(a * b)),
+            Opcode::DIVIDE => Some(Value::number(a / b)),
+            _ => None,
+        },
+        (Opcode::ADD, a, b) => {
+            let (a, b) = (a.into_string()?, b.into_string()?);
+            Some(Value::string(a + &b))
+        }
+        _ => None,
+    }
+}
+
+fn fold_unary(op: u8, value: Value) -> Option<Value> {
+    match (op, value) {
+        (Opcode::NOT, value) => Some(Value::bool(value.is_falsey())),
+        (Opcode::NEGATE, Value::Number(n)) => Some(Value::number(-n)),
+        _ => None,
+    }
+}
+
+fn load(value: Value, offset: usize, span: Span) -> Instruction {
+    let (op, operand) = match value {
+        Value::Nil => (Opcode::NIL, Operand::None),
+        Value::Bool(true) => (Opcode::TRUE, Operand::None),
+        Value::Bool(false) => (Opcode::FALSE, Operand::None),
+        value => (Opcode::CONSTANT, Operand::Value(value)),
+    };
+    Instruction {
+        offset,
+        op,
+        operand,
+        span,
+    }
+}
+
+// Rewrites window starting at `i`, returns whether anything changed
+fn peephole(
+    instructions: &mut Vec<Instruction>,
+    i: usize,
+    targets: &[usize],
+    chunk: &Chunk,
+) -> bool {
+    // Instructions after the first one in a window can't be jumped to,
+    // or rewriting would change the meaning of that jump
+    let is_target = |i: &Instruction| targets.binary_search(&i.offset).is_ok();
+    let window = &instructions[i..];
+
+    // Constant, Constant, binary op => Constant
+    if let [a, b, op, ..] = window {
+        if !is_target(b) && !is_target(op) {
+            let values = constant_value(a, chunk).zip(constant_value(b, chunk));
+            if let Some(value) =
+                values.and_then(|(a, b)| fold_binary(op.op, a, b))
+            {
+                let folded = load(value, a.offset, op.span);
+                instructions.splice(i..i + 3, Some(folded));
+                return true;
+            }
+        }
+    }
+
+    let (a, b) = match window {
+        [a, b, ..] if !is_target(b) => (a, b),
+        _ => return false,
+    };
+
+    // Constant, unary op => Constant
+    if let Some(value) =
+        constant_value(a, chunk).and_then(|a| fold_unary(b.op, a))
+    {
+        let folded = load(value, a.offset, b.span);
+        instructions.splice(i..i + 2, Some(folded));
+        return true;
+    }
+
+    // Constant, Pop => nothing
+    if b.op == Opcode::POP && constant_value(a, chunk).is_some() {
+        instructions.drain(i..i + 2);
+        return true;
+    }
+
+    // Comparison, Not => fused comparison
+    let fused = match (a.op, b.op) {
+        (Opcode::LESS, Opcode::NOT) => Opcode::GREATER_EQUAL,
+        (Opcode::GREATER, Opcode::NOT) => Opcode::LESS_EQUAL,
+        (Opcode::EQUAL, Opcode::NOT) => Opcode::NOT_EQUAL,
+        _ => return false,
+    };
+    let fused = Instruction {
+        op: fused,
+        ..a.clone()
+    };
+    instructions.splice(i..i + 2, Some(fused));
+    true
+}
+
+fn encode(original: &Chunk, instructions: &[Instruction]) -> Chunk {
+    let mut chunk = original.with_constants();
+    // (old offset, new offset) of every emitted instruction
+    let mut offsets = Vec::with_capacity(instructions.len());
+    let mut jumps = vec![];
+    for instruction in instructions {
+        let span = instruction.span;
+        offsets.push((instruction.offset, chunk.code.len()));
+        match &instruction.operand {
+            Operand::None => chunk.write(instruction.op, span),
+            Operand::Byte(byte) => {
+                chunk.write(instruction.op, span);
+                chunk.write(*byte, span);
+            }
+            Operand::Constant(index) => chunk.write_op_with_constant(
+                instruction.op,
+                long_form(instruction.op),
+                chunk::ConstantIndex(*index),
+                span,
+            ),
+            Operand::Value(value) => {
+                chunk.write_constant(value.clone(), span);
+            }
+            Operand::Jump(target) => {
+                chunk.write(instruction.op, span);
+                jumps.push((chunk.code.len(), *target));
+                chunk.write(0, span);
+                chunk.write(0, span);
+            }
+        }
+    }
+
+    for (operand, target) in jumps {
+        // Code only shrinks, so if target was removed, jump to whatever
+        // follows it
+        let index = offsets.partition_point(|&(old, _)| old < target);
+        let new_target =
+            offsets.get(index).map_or(chunk.code.len(), |&(_, new)| new);
+        let jump = (new_target - operand - 2) as u16;
+        chunk.code[operand..operand + 2].copy_from_slice(&jump.to_le_bytes());
+    }
+    chunk
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{super::compiler::compile, *};
+
+    fn optimized(source: &str) -> Vec<u8> {
+        optimize(&compile(source).unwrap(), OptLevel::O1).code
+    }
+
+    #[test]
+    fn fold_constants() {
+        let code = optimized("print 1 + 2 * 3;");
+        assert_eq!(code, [Opcode::CONSTANT, 3, Opcode::PRINT, Opcode::RETURN]);
+
+        let code = optimized("print -1 >= 2;");
+        assert_eq!(code, [Opcode::FALSE, Opcode::PRINT, Opcode::RETURN]);
+
+        let code = optimized("print \"a\" + \"b\";");
+        assert_eq!(code, [Opcode::CONSTANT, 2, Opcode::PRINT, Opcode::RETURN]);
+
+        // Errors are kept for runtime
+        let code = optimized("print -nil;");
+        assert_eq!(
+            code,
+            [Opcode::NIL, Opcode::NEGATE, Opcode::PRINT, Opcode::RETURN]
+        );
+    }
+
+    #[test]
+    fn fused_comparisons() {
+        let code = optimized("var a; print a <= a != a;");
+        assert_eq!(
+            code,
+            [
+                Opcode::NIL,
+                Opcode::DEFINE_GLOBAL,
+                0,
+                Opcode::GET_GLOBAL,
+                0,
+                Opcode::GET_GLOBAL,
+                0,
+                Opcode::LESS_EQUAL,
+                Opcode::GET_GLOBAL,
+                0,
+                Opcode::NOT_EQUAL,
+                Opcode::PRINT,
+                Opcode::RETURN,
+            ]
+        );
+    }
+
+    #[test]
+    fn dead_constants() {
+        assert_eq!(optimized("1; nil; \"a\";"), [Opcode::RETURN]);
+    }
+
+    #[test]
+    fn relocate_jumps() {
+        let source = "try { 1 + 2; } catch (e) { print 3 * 4; }";
+        let chunk = compile(source).unwrap();
+        let code = optimize(&chunk, OptLevel::O1).code;
+        assert!(code.len() < chunk.code.len());
+        // Try body folds away, so handler points right past the jump
+        assert_eq!(code[..4], [Opcode::TRY, 4, 0, Opcode::POP_HANDLER]);
+        assert_eq!(code[4], Opcode::JUMP);
+        assert_eq!(code[7..10], [Opcode::TRY, 8, 0]);
+        assert_eq!(code[10..12], [Opcode::CONSTANT, 4]);
+    }
+}
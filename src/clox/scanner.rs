@@ -29,9 +29,11 @@ pub enum TokenType {
     Number,
     // Keywords.
     And,
+    Catch,
     Class,
     Else,
     False,
+    Finally,
     For,
     Fun,
     If,
@@ -41,16 +43,28 @@ pub enum TokenType {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 }
 
+/// Location of a token in source
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub column: usize,
+    /// Byte offsets of token's start and end
+    pub start: usize,
+    pub end: usize,
+}
+
 #[derive(Debug, Clone)]
 pub struct Token<'s> {
     pub type_: TokenType,
     pub lexeme: Cow<'s, str>,
-    pub line: usize,
+    pub span: Span,
 }
 
 impl Token<'_> {
@@ -58,12 +72,12 @@ impl Token<'_> {
         let Token {
             type_,
             lexeme,
-            line,
+            span,
         } = self;
         Token {
             type_,
             lexeme: lexeme.into_owned().into(),
-            line,
+            span,
         }
     }
 }
@@ -73,6 +87,9 @@ pub struct Scanner<'s> {
     start: usize,
     current: usize,
     line: usize,
+    column: usize,
+    // Line and column where current token started
+    start_pos: (usize, usize),
 }
 
 #[derive(Debug, thiserror::Error)]
@@ -84,15 +101,15 @@ pub enum ErrorKind {
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("Scanner error: {kind} at line {line}")]
+#[error("Scanner error: {kind} at line {}:{}", .span.line, .span.column)]
 pub struct Error {
     kind: ErrorKind,
-    line: usize,
+    span: Span,
 }
 
 impl Error {
-    fn new(kind: ErrorKind, line: usize) -> Self {
-        Self { kind, line }
+    fn new(kind: ErrorKind, span: Span) -> Self {
+        Self { kind, span }
     }
 }
 
@@ -111,12 +128,15 @@ impl<'s> Scanner<'s> {
             start: 0,
             current: 0,
             line: 1,
+            column: 1,
+            start_pos: (1, 1),
         }
     }
 
     fn next(&mut self) -> Result<Option<Token<'s>>, Error> {
         self.skip_whitespace();
         self.start = self.current;
+        self.start_pos = (self.line, self.column);
         let c = match self.advance() {
             Some(c) => c,
             None => return Ok(None),
@@ -167,7 +187,7 @@ impl<'s> Scanner<'s> {
             _ => {
                 return Err(Error::new(
                     ErrorKind::UnexpectedCharacter,
-                    self.line,
+                    self.span(),
                 ))
             }
         };
@@ -178,11 +198,20 @@ impl<'s> Scanner<'s> {
         &self.source[self.start..self.current]
     }
 
+    fn span(&self) -> Span {
+        Span {
+            line: self.start_pos.0,
+            column: self.start_pos.1,
+            start: self.start,
+            end: self.current,
+        }
+    }
+
     fn token(&self, type_: TokenType) -> Token<'s> {
         Token {
             type_,
             lexeme: self.lexeme().into(),
-            line: self.line,
+            span: self.span(),
         }
     }
 
@@ -197,13 +226,19 @@ impl<'s> Scanner<'s> {
     fn advance(&mut self) -> Option<char> {
         let c = self.peek()?;
         self.current += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
         Some(c)
     }
 
     fn match_(&mut self, expected: char) -> bool {
         match self.peek() {
             Some(c) if c == expected => {
-                self.current += c.len_utf8();
+                self.advance();
                 true
             }
             _ => false,
@@ -213,20 +248,12 @@ impl<'s> Scanner<'s> {
     fn skip_whitespace(&mut self) {
         loop {
             match self.peek() {
-                Some(' ') | Some('\r') | Some('\t') => {
+                Some(' ') | Some('\r') | Some('\t') | Some('\n') => {
                     self.advance();
                 }
-                Some('\n') => {
-                    self.advance();
-                    self.line += 1;
-                }
-                Some('/') => {
-                    if self.peek_next() == Some('/') {
-                        while self.peek().filter(|x| *x != '\n').is_some() {
-                            self.advance();
-                        }
-                    } else {
-                        return;
+                Some('/') if self.peek_next() == Some('/') => {
+                    while self.peek().filter(|x| *x != '\n').is_some() {
+                        self.advance();
                     }
                 }
                 _ => return,
@@ -236,13 +263,10 @@ impl<'s> Scanner<'s> {
 
     fn string(&mut self) -> Result<Token<'s>, Error> {
         while self.peek().filter(|x| *x != '"').is_some() {
-            if self.peek() == Some('\n') {
-                self.line += 1;
-            }
             self.advance();
         }
         if self.peek().is_none() {
-            Err(Error::new(ErrorKind::UnterminatedString, self.line))
+            Err(Error::new(ErrorKind::UnterminatedString, self.span()))
         } else {
             self.advance();
             Ok(self.token(TokenType::String))
@@ -274,9 +298,11 @@ impl<'s> Scanner<'s> {
         }
         let type_ = match self.lexeme() {
             "and" => TokenType::And,
+            "catch" => TokenType::Catch,
             "class" => TokenType::Class,
             "else" => TokenType::Else,
             "false" => TokenType::False,
+            "finally" => TokenType::Finally,
             "for" => TokenType::For,
             "fun" => TokenType::Fun,
             "if" => TokenType::If,
@@ -286,7 +312,9 @@ impl<'s> Scanner<'s> {
             "return" => TokenType::Return,
             "super" => TokenType::Super,
             "this" => TokenType::This,
+            "throw" => TokenType::Throw,
             "true" => TokenType::True,
+            "try" => TokenType::Try,
             "var" => TokenType::Var,
             "while" => TokenType::While,
             _ => TokenType::Identifier,
@@ -294,3 +322,40 @@ impl<'s> Scanner<'s> {
         self.token(type_)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spans(source: &str) -> Vec<(TokenType, Span)> {
+        Scanner::new(source)
+            .map(|token| token.map(|t| (t.type_, t.span)))
+            .collect::<Result<_, _>>()
+            .unwrap()
+    }
+
+    #[test]
+    fn multiline_strings() {
+        let span = |line, column, start, end| Span {
+            line,
+            column,
+            start,
+            end,
+        };
+        assert_eq!(
+            spans("print \"a\n bc\" +\n\"\""),
+            vec![
+                (TokenType::Print, span(1, 1, 0, 5)),
+                (TokenType::String, span(1, 7, 6, 13)),
+                (TokenType::Plus, span(2, 6, 14, 15)),
+                (TokenType::String, span(3, 1, 16, 18)),
+            ]
+        );
+
+        let error = Scanner::new("1\n\"a\nb").nth(1).unwrap().unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Scanner error: Unterminated string at line 2:1"
+        );
+    }
+}
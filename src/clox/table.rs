@@ -162,19 +162,14 @@ impl<V> Table<V> {
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 enum Entry<V> {
+    #[default]
     Empty,
     Tombstone,
     Occupied(OccupiedEntry<V>),
 }
 
-impl<V> Default for Entry<V> {
-    fn default() -> Self {
-        Self::Empty
-    }
-}
-
 #[derive(Clone)]
 struct OccupiedEntry<V> {
     key: Box<ObjString>,
@@ -0,0 +1,261 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    ops::RangeInclusive,
+    path::PathBuf,
+    str::FromStr,
+};
+
+use serde::Serialize;
+
+use super::{
+    chunk::Chunk,
+    debug::{self, Format, Operand},
+    value::Value,
+};
+
+/// Inclusive range of source lines, written as `5` or `5-10`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LineRange(pub RangeInclusive<usize>);
+
+impl FromStr for LineRange {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const ERROR: &str = "Expected line number or range like 5-10";
+        let mut parts = s.splitn(2, '-');
+        let start = parts.next().unwrap().parse().map_err(|_| ERROR)?;
+        let end = match parts.next() {
+            Some(end) => end.parse().map_err(|_| ERROR)?,
+            None => start,
+        };
+        Ok(Self(start..=end))
+    }
+}
+
+/// What to trace and where to write it
+#[derive(Debug, Clone)]
+pub struct TraceConfig {
+    /// File to write trace to, instead of stdout
+    pub file: Option<PathBuf>,
+    /// Only trace instructions in these functions, all if empty
+    pub functions: Vec<String>,
+    /// Only trace instructions compiled from these lines, all if empty
+    pub lines: Vec<LineRange>,
+    /// Number of values from the top of the stack to show, all if `None`
+    pub stack_depth: Option<usize>,
+    /// Text, or one JSON object per executed instruction
+    pub format: Format,
+}
+
+impl Default for TraceConfig {
+    fn default() -> Self {
+        Self {
+            file: None,
+            functions: vec![],
+            lines: vec![],
+            stack_depth: None,
+            format: Format::Text,
+        }
+    }
+}
+
+pub struct Tracer {
+    config: TraceConfig,
+    out: Box<dyn Write>,
+}
+
+#[derive(Serialize)]
+struct Record<'a> {
+    function: &'a str,
+    offset: usize,
+    line: Option<usize>,
+    column: Option<usize>,
+    opcode: Option<&'static str>,
+    operand: &'a Operand,
+    stack_size: usize,
+    stack: Vec<String>,
+}
+
+impl Tracer {
+    pub fn new(config: TraceConfig) -> io::Result<Self> {
+        let out: Box<dyn Write> = match &config.file {
+            Some(path) => Box::new(BufWriter::new(File::create(path)?)),
+            None => Box::new(io::stdout()),
+        };
+        Ok(Self::with_writer(config, out))
+    }
+
+    pub fn with_writer(config: TraceConfig, out: Box<dyn Write>) -> Self {
+        Self { config, out }
+    }
+
+    /// Writes disassembly of whole chunk, only done for text traces
+    pub fn chunk(&mut self, chunk: &Chunk, name: &str) -> io::Result<()> {
+        if self.config.format == Format::Text {
+            debug::write_chunk(&mut self.out, chunk, name)?;
+        }
+        Ok(())
+    }
+
+    pub fn start(&mut self) -> io::Result<()> {
+        if self.config.format == Format::Text {
+            writeln!(self.out, "---- execution ----")?;
+        }
+        Ok(())
+    }
+
+    fn is_traced(&self, function: &str, line: Option<usize>) -> bool {
+        let config = &self.config;
+        let function_matches = config.functions.is_empty()
+            || config.functions.iter().any(|f| f == function);
+        let line_matches = config.lines.is_empty()
+            || line.is_some_and(|line| {
+                config.lines.iter().any(|range| range.0.contains(&line))
+            });
+        function_matches && line_matches
+    }
+
+    /// Records instruction at `offset`, which is about to be executed
+    pub fn instruction(
+        &mut self,
+        chunk: &Chunk,
+        offset: usize,
+        function: &str,
+        stack: &[Value],
+    ) -> io::Result<()> {
+        let span = chunk.get_span(offset);
+        if !self.is_traced(function, span.map(|s| s.line)) {
+            return Ok(());
+        }
+        let shown = match self.config.stack_depth {
+            Some(depth) => &stack[stack.len().saturating_sub(depth)..],
+            None => stack,
+        };
+
+        match self.config.format {
+            Format::Text => {
+                let hidden = if shown.len() < stack.len() {
+                    "..., "
+                } else {
+                    ""
+                };
+                let values: Vec<_> =
+                    shown.iter().map(|v| format!("{:?}", v)).collect();
+                writeln!(self.out, "[{}{}]", hidden, values.join(", "))?;
+                debug::write_instruction(&mut self.out, chunk, offset)?;
+            }
+            Format::Json => {
+                let instruction = debug::decode_instruction(chunk, offset);
+                let record = Record {
+                    function,
+                    offset,
+                    line: span.map(|s| s.line),
+                    column: span.map(|s| s.column),
+                    opcode: instruction.opcode,
+                    operand: &instruction.operand,
+                    stack_size: stack.len(),
+                    stack: shown.iter().map(|v| format!("{:?}", v)).collect(),
+                };
+                serde_json::to_writer(&mut self.out, &record)?;
+                writeln!(self.out)?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::{
+        super::{
+            compiler::compile,
+            vm::{Hooks, Vm, VmState},
+        },
+        *,
+    };
+
+    // Writer which can be read after tracer is done with it
+    #[derive(Clone, Default)]
+    struct Shared(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    fn trace(source: &str, config: TraceConfig) -> String {
+        let chunk = compile(source).unwrap();
+        let out = Shared::default();
+        let mut tracer = Tracer::with_writer(config, Box::new(out.clone()));
+        Vm::new(&chunk, &mut VmState::default())
+            .interpret(Hooks {
+                tracer: Some(&mut tracer),
+                ..Hooks::default()
+            })
+            .unwrap();
+        let bytes = out.0.borrow().clone();
+        String::from_utf8(bytes).unwrap()
+    }
+
+    #[test]
+    fn line_filter() {
+        let config = TraceConfig {
+            lines: vec!["2".parse().unwrap()],
+            stack_depth: Some(1),
+            ..TraceConfig::default()
+        };
+        let trace = trace("var a = 1;\na + 2 + 3;\nnil;", config);
+        let execution = trace.split("---- execution ----\n").nth(1).unwrap();
+        assert_eq!(
+            execution,
+            "\
+[]
+0004    2 06 00       OP_GET_GLOBAL       0 '\"a\"'
+[1.0]
+0006    | 00 02       OP_CONSTANT         2 '2.0'
+[..., 2.0]
+0008    | 16          OP_ADD
+[3.0]
+0009    | 00 03       OP_CONSTANT         3 '3.0'
+[..., 3.0]
+0011    | 16          OP_ADD
+[6.0]
+0012    | 05          OP_POP
+"
+        );
+    }
+
+    #[test]
+    fn json_records() {
+        let config = TraceConfig {
+            functions: vec!["script".into()],
+            format: Format::Json,
+            ..TraceConfig::default()
+        };
+        let output = trace("print 1;", config);
+        let lines: Vec<_> = output.lines().collect();
+        assert_eq!(lines.len(), 3);
+        assert_eq!(
+            lines[1],
+            r#"{"function":"script","offset":2,"line":1,"column":8,"opcode":"OP_PRINT","operand":{"kind":"none"},"stack_size":1,"stack":["1.0"]}"#
+        );
+
+        let config = TraceConfig {
+            functions: vec!["other".into()],
+            ..TraceConfig::default()
+        };
+        assert!(trace("print 1;", config).ends_with("---- execution ----\n"));
+    }
+}
@@ -25,6 +25,10 @@ impl Value {
         Self::Obj(Box::new(Obj::ObjString(ObjString::new(value))))
     }
 
+    pub fn error(message: String, line: usize) -> Self {
+        Self::Obj(Box::new(Obj::ObjError(ObjError { message, line })))
+    }
+
     pub fn is_falsey(&self) -> bool {
         matches!(self, Self::Nil | Self::Bool(false))
     }
@@ -33,6 +37,7 @@ impl Value {
         match self {
             Value::Obj(o) => match *o {
                 Obj::ObjString(s) => Some(s),
+                _ => None,
             },
             _ => None,
         }
@@ -72,12 +77,15 @@ impl fmt::Debug for Value {
             Value::Number(n) => write!(f, "{:?}", n),
             Value::Obj(o) => match o.as_ref() {
                 Obj::ObjString(ObjString(s, _)) => write!(f, "{:?}", s),
+                Obj::ObjError(ObjError { message, .. }) => {
+                    write!(f, "<error: {}>", message)
+                }
             },
         }
     }
 }
 
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct ObjString(pub Box<str>, pub u32);
 
 impl ObjString {
@@ -87,12 +95,20 @@ impl ObjString {
     }
 }
 
+/// Runtime error caught by a `catch` clause
+#[derive(Debug, Clone, PartialEq)]
+pub struct ObjError {
+    pub message: String,
+    pub line: usize,
+}
+
 #[derive(Debug, Clone, PartialEq)]
 pub enum Obj {
     ObjString(ObjString),
+    ObjError(ObjError),
 }
 
-#[derive(Default)]
+#[derive(Default, Clone)]
 pub struct ValueArray {
     pub(super) values: Vec<Value>,
 }
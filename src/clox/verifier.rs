@@ -0,0 +1,446 @@
+use super::{
+    chunk::{self, Chunk, Opcode},
+    value::{Obj, Value},
+};
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+#[error("[offset {offset}] {kind}")]
+pub struct Error {
+    pub offset: usize,
+    pub kind: ErrorKind,
+}
+
+#[derive(Debug, PartialEq, thiserror::Error)]
+pub enum ErrorKind {
+    #[error("Unknown opcode: {0:#x}")]
+    UnknownOpcode(u8),
+    #[error("Instruction operand is cut off by end of code")]
+    TruncatedOperand,
+    #[error("Constant index {0} is out of range")]
+    ConstantOutOfRange(usize),
+    #[error("Constant {0} has to be a string")]
+    ExpectedStringConstant(usize),
+    #[error("Jump target {0} is out of range")]
+    JumpOutOfRange(usize),
+    #[error("Jump target {0} is in the middle of an instruction")]
+    JumpIntoInstruction(usize),
+    #[error("Local slot {slot} is out of range of stack of size {depth}")]
+    LocalOutOfRange { slot: u8, depth: usize },
+    #[error("Instruction needs {needed} values, but stack has {depth}")]
+    StackUnderflow { needed: usize, depth: usize },
+    #[error("Stack has size {found} here, but {expected} on other path")]
+    InconsistentStackDepth { expected: usize, found: usize },
+    #[error("Different exception handlers are active here on other path")]
+    InconsistentHandlers,
+    #[error("Stack has size {depth} when throwing, but {handler} at TRY")]
+    StackBelowHandler { handler: usize, depth: usize },
+    #[error("Execution can run past end of code")]
+    MissingReturn,
+}
+
+enum Operand {
+    None,
+    Byte(u8),
+    Constant(usize),
+    Name(usize),
+    Jump(usize),
+}
+
+struct Instruction {
+    offset: usize,
+    op: Opcode,
+    operand: Operand,
+}
+
+/// Checks that chunk can be executed by VM without reading past code or
+/// constant pool, underflowing the stack or jumping into the middle of an
+/// instruction. Compiler output always passes, this is meant for chunks
+/// loaded from files or built by hand.
+pub fn verify(chunk: &Chunk) -> Result<(), Error> {
+    let instructions = decode(chunk)?;
+    check_stack(&instructions)
+}
+
+fn decode(chunk: &Chunk) -> Result<Vec<Instruction>, Error> {
+    let code = &chunk.code;
+    let constants = &chunk.constants.values;
+    let mut instructions = vec![];
+    let mut offset = 0;
+    while offset < code.len() {
+        let error = |kind| Error { offset, kind };
+        let byte = code[offset];
+        let op = Opcode::check(byte)
+            .ok_or_else(|| error(ErrorKind::UnknownOpcode(byte)))?;
+        let operands = &code[offset + 1..];
+        let operand = |len: usize| {
+            operands
+                .get(..len)
+                .ok_or_else(|| error(ErrorKind::TruncatedOperand))
+        };
+        let (operand, len) = match op {
+            Opcode::Constant => (Operand::Constant(operand(1)?[0] as usize), 2),
+            Opcode::GetGlobal
+            | Opcode::DefineGlobal
+            | Opcode::SetGlobal
+            | Opcode::GetProperty => {
+                (Operand::Name(operand(1)?[0] as usize), 2)
+            }
+            Opcode::ConstantLong
+            | Opcode::GetGlobalLong
+            | Opcode::DefineGlobalLong
+            | Opcode::SetGlobalLong
+            | Opcode::GetPropertyLong => {
+                let (index, len) = chunk::decode_index(operands)
+                    .ok_or_else(|| error(ErrorKind::TruncatedOperand))?;
+                let operand = match op {
+                    Opcode::ConstantLong => Operand::Constant(index),
+                    _ => Operand::Name(index),
+                };
+                (operand, 1 + len)
+            }
+            Opcode::GetLocal | Opcode::SetLocal => {
+                (Operand::Byte(operand(1)?[0]), 2)
+            }
+            Opcode::Jump | Opcode::Try => {
+                let bytes = operand(2)?;
+                let jump = u16::from_le_bytes([bytes[0], bytes[1]]);
+                (Operand::Jump(offset + 3 + jump as usize), 3)
+            }
+            _ => (Operand::None, 1),
+        };
+
+        match operand {
+            Operand::Constant(index) | Operand::Name(index)
+                if index >= constants.len() =>
+            {
+                return Err(error(ErrorKind::ConstantOutOfRange(index)));
+            }
+            Operand::Name(index) => match &constants[index] {
+                Value::Obj(obj) if matches!(**obj, Obj::ObjString(_)) => {}
+                _ => {
+                    return Err(error(ErrorKind::ExpectedStringConstant(index)))
+                }
+            },
+            _ => {}
+        }
+
+        instructions.push(Instruction {
+            offset,
+            op,
+            operand,
+        });
+        offset += len;
+    }
+    Ok(instructions)
+}
+
+// Stack effect of an instruction, as number of values it needs on the stack
+// and change of stack size
+fn stack_effect(op: &Opcode) -> (usize, isize) {
+    match op {
+        Opcode::Constant
+        | Opcode::ConstantLong
+        | Opcode::Nil
+        | Opcode::True
+        | Opcode::False
+        | Opcode::GetGlobal
+        | Opcode::GetGlobalLong
+        | Opcode::GetLocal => (0, 1),
+        Opcode::Pop
+        | Opcode::DefineGlobal
+        | Opcode::DefineGlobalLong
+        | Opcode::Print
+        | Opcode::Throw => (1, -1),
+        Opcode::SetGlobal
+        | Opcode::SetGlobalLong
+        | Opcode::SetLocal
+        | Opcode::GetProperty
+        | Opcode::GetPropertyLong
+        | Opcode::Not
+        | Opcode::Negate => (1, 0),
+        Opcode::Equal
+        | Opcode::Greater
+        | Opcode::Less
+        | Opcode::NotEqual
+        | Opcode::GreaterEqual
+        | Opcode::LessEqual
+        | Opcode::Add
+        | Opcode::Subtract
+        | Opcode::Multiply
+        | Opcode::Divide => (2, -1),
+        Opcode::EndFinally => (2, -2),
+        Opcode::Jump | Opcode::Try | Opcode::PopHandler | Opcode::Return => {
+            (0, 0)
+        }
+    }
+}
+
+// Whether instruction can fail at runtime and unwind to a handler
+fn can_throw(op: &Opcode) -> bool {
+    !matches!(
+        op,
+        Opcode::Constant
+            | Opcode::ConstantLong
+            | Opcode::Nil
+            | Opcode::True
+            | Opcode::False
+            | Opcode::Pop
+            | Opcode::GetLocal
+            | Opcode::SetLocal
+            | Opcode::Jump
+            | Opcode::Try
+            | Opcode::PopHandler
+            | Opcode::Return
+    )
+}
+
+// Walks every path through the code, making sure each instruction is
+// reached with the same stack size and exception handlers, and that stack
+// never shrinks below what handler restores when unwinding
+fn check_stack(instructions: &[Instruction]) -> Result<(), Error> {
+    let index_of = |offset: usize, from: usize| {
+        let error = |kind| Error { offset: from, kind };
+        instructions
+            .binary_search_by_key(&offset, |i| i.offset)
+            .map_err(|i| match i {
+                i if i == instructions.len() => {
+                    error(ErrorKind::JumpOutOfRange(offset))
+                }
+                _ => error(ErrorKind::JumpIntoInstruction(offset)),
+            })
+    };
+
+    // Stack depth and depths at `TRY` of active handlers, innermost last
+    let mut states: Vec<Option<(usize, Vec<usize>)>> =
+        vec![None; instructions.len()];
+    let mut worklist = vec![];
+    if !instructions.is_empty() {
+        worklist.push((0, 0, vec![]));
+    }
+    while let Some((index, depth, mut handlers)) = worklist.pop() {
+        let instruction = &instructions[index];
+        let error = |kind| Error {
+            offset: instruction.offset,
+            kind,
+        };
+        match &states[index] {
+            Some((expected, _)) if *expected != depth => {
+                return Err(error(ErrorKind::InconsistentStackDepth {
+                    expected: *expected,
+                    found: depth,
+                }))
+            }
+            Some((_, expected)) if *expected != handlers => {
+                return Err(error(ErrorKind::InconsistentHandlers))
+            }
+            Some(_) => continue,
+            None => states[index] = Some((depth, handlers.clone())),
+        }
+
+        let (needed, change) = stack_effect(&instruction.op);
+        if depth < needed {
+            return Err(error(ErrorKind::StackUnderflow { needed, depth }));
+        }
+        if let Operand::Byte(slot) = instruction.operand {
+            let needed = match instruction.op {
+                // Value to store has to sit above the slot
+                Opcode::SetLocal => slot as usize + 2,
+                _ => slot as usize + 1,
+            };
+            if depth < needed {
+                return Err(error(ErrorKind::LocalOutOfRange { slot, depth }));
+            }
+        }
+        let after = (depth as isize + change) as usize;
+        if let (Some(&handler), true) =
+            (handlers.last(), can_throw(&instruction.op))
+        {
+            // Operands are already popped when instruction fails
+            if depth - needed < handler {
+                return Err(error(ErrorKind::StackBelowHandler {
+                    handler,
+                    depth: depth - needed,
+                }));
+            }
+        }
+
+        let falls_through = !matches!(
+            instruction.op,
+            Opcode::Jump | Opcode::Throw | Opcode::Return
+        );
+        // Handler isn't active in its own code
+        let outer = handlers.clone();
+        match instruction.op {
+            Opcode::Try => handlers.push(depth),
+            Opcode::PopHandler => drop(handlers.pop()),
+            _ => {}
+        }
+        if falls_through {
+            if index + 1 == instructions.len() {
+                return Err(error(ErrorKind::MissingReturn));
+            }
+            worklist.push((index + 1, after, handlers));
+        }
+        if let Operand::Jump(target) = instruction.operand {
+            let target_index = index_of(target, instruction.offset)?;
+            // Handler starts with exception pushed on top of the stack
+            // as it was at `TRY`
+            let target_depth = match instruction.op {
+                Opcode::Try => depth + 1,
+                _ => after,
+            };
+            worklist.push((target_index, target_depth, outer));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{compiler::compile, scanner::Span},
+        *,
+    };
+
+    fn chunk(code: &[u8], constants: Vec<Value>) -> Chunk {
+        let mut chunk = Chunk::default();
+        for value in constants {
+            chunk.push_constant(value);
+        }
+        for &byte in code {
+            chunk.write(byte, Span::default());
+        }
+        chunk
+    }
+
+    fn error(code: &[u8], constants: Vec<Value>) -> Error {
+        verify(&chunk(code, constants)).unwrap_err()
+    }
+
+    #[test]
+    fn compiler_output() {
+        let source = "var a = 1; { var b = a; print b >= 2; }
+            try { throw a; } catch (e) { print e.message; } finally { a; }";
+        verify(&compile(source).unwrap()).unwrap();
+    }
+
+    #[test]
+    fn operands() {
+        assert_eq!(
+            error(&[0xff], vec![]),
+            Error {
+                offset: 0,
+                kind: ErrorKind::UnknownOpcode(0xff)
+            }
+        );
+        assert_eq!(
+            error(&[Opcode::NIL, Opcode::JUMP, 0], vec![]).kind,
+            ErrorKind::TruncatedOperand
+        );
+        assert_eq!(
+            error(&[Opcode::CONSTANT_LONG, 0x80], vec![]).kind,
+            ErrorKind::TruncatedOperand
+        );
+        assert_eq!(
+            error(&[Opcode::CONSTANT, 1, Opcode::RETURN], vec![Value::nil()]),
+            Error {
+                offset: 0,
+                kind: ErrorKind::ConstantOutOfRange(1)
+            }
+        );
+        assert_eq!(
+            error(
+                &[Opcode::GET_GLOBAL, 0, Opcode::RETURN],
+                vec![Value::number(1.0)]
+            )
+            .kind,
+            ErrorKind::ExpectedStringConstant(0)
+        );
+    }
+
+    #[test]
+    fn jumps() {
+        assert_eq!(
+            error(
+                &[Opcode::JUMP, 1, 0, Opcode::CONSTANT, 0, Opcode::RETURN],
+                vec![Value::nil()]
+            )
+            .kind,
+            ErrorKind::JumpIntoInstruction(4)
+        );
+        assert_eq!(
+            error(&[Opcode::JUMP, 1, 0, Opcode::RETURN], vec![]).kind,
+            ErrorKind::JumpOutOfRange(4)
+        );
+        assert_eq!(
+            error(&[Opcode::NIL], vec![]).kind,
+            ErrorKind::MissingReturn
+        );
+    }
+
+    #[test]
+    fn stack_depth() {
+        assert_eq!(
+            error(&[Opcode::NIL, Opcode::ADD, Opcode::RETURN], vec![]),
+            Error {
+                offset: 1,
+                kind: ErrorKind::StackUnderflow {
+                    needed: 2,
+                    depth: 1
+                }
+            }
+        );
+        assert_eq!(
+            error(&[Opcode::NIL, Opcode::GET_LOCAL, 1, Opcode::RETURN], vec![])
+                .kind,
+            ErrorKind::LocalOutOfRange { slot: 1, depth: 1 }
+        );
+        // Both paths meet at RETURN, one with exception on the stack
+        let code = [Opcode::TRY, 1, 0, Opcode::POP_HANDLER, Opcode::RETURN];
+        assert_eq!(
+            error(&code, vec![]),
+            Error {
+                offset: 4,
+                kind: ErrorKind::InconsistentStackDepth {
+                    expected: 1,
+                    found: 0
+                }
+            }
+        );
+    }
+
+    #[test]
+    fn handlers() {
+        // Handler restores stack of size 2, but throw leaves it with 0
+        let code = [
+            Opcode::CONSTANT,
+            0,
+            Opcode::CONSTANT,
+            0,
+            Opcode::TRY,
+            5,
+            0,
+            Opcode::POP,
+            Opcode::POP,
+            Opcode::CONSTANT,
+            0,
+            Opcode::THROW,
+            Opcode::GET_LOCAL,
+            2,
+            Opcode::RETURN,
+        ];
+        assert_eq!(
+            error(&code, vec![Value::nil()]),
+            Error {
+                offset: 11,
+                kind: ErrorKind::StackBelowHandler {
+                    handler: 2,
+                    depth: 0
+                }
+            }
+        );
+        // Handler is still active when falling through into its code
+        let code = [Opcode::TRY, 1, 0, Opcode::NIL, Opcode::RETURN];
+        assert_eq!(error(&code, vec![]).kind, ErrorKind::InconsistentHandlers);
+    }
+}
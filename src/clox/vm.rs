@@ -1,8 +1,21 @@
+use std::{
+    cmp::Ordering::{Greater, Less},
+    collections::HashMap,
+    fmt, io,
+};
+
+use crate::{
+    coverage::{Coverage, NO_FILE},
+    profile::Profiler,
+};
+
 use super::{
-    chunk::{Chunk, Opcode},
+    chunk::{self, Chunk, Opcode},
     debug,
+    scanner::Span,
     table::Table,
-    value::Value,
+    trace::Tracer,
+    value::{Obj, ObjError, Value},
 };
 
 pub struct Vm<'chunk, 'state> {
@@ -10,17 +23,42 @@ pub struct Vm<'chunk, 'state> {
     state: &'state mut VmState,
     ip: usize,
     stack: Vec<Value>,
+    handlers: Vec<Handler>,
+    // Where exceptions put on the stack by handlers were thrown, by slot, so
+    // `finally` rethrows them from there
+    throw_sites: HashMap<usize, Span>,
+}
+
+// Installed by `OP_TRY`, used to unwind the stack when exception is thrown
+struct Handler {
+    ip: usize,
+    stack_len: usize,
 }
 
 #[derive(Default)]
 pub struct VmState {
     globals: Table<Value>,
+    pub file: Option<String>,
+}
+
+/// Optional observers of executed instructions
+#[derive(Default)]
+pub struct Hooks<'a> {
+    pub tracer: Option<&'a mut Tracer>,
+    pub profiler: Option<&'a mut Profiler>,
+    pub coverage: Option<&'a mut Coverage>,
 }
 
 #[derive(Debug, thiserror::Error)]
 pub enum ErrorKind {
     #[error("Stack underflow")]
     StackUnderflow,
+    #[error("Local slot {0} is out of range")]
+    LocalOutOfRange(usize),
+    #[error("Constant index {0} is out of range")]
+    ConstantOutOfRange(usize),
+    #[error("Constant index is cut off by end of code")]
+    TruncatedConstantIndex,
     #[error("Undefined variable {0}.")]
     UndefinedVariable(String),
     #[error("Global name isn't a string")]
@@ -29,15 +67,78 @@ pub enum ErrorKind {
     ExpectedNumber,
     #[error("Operands must be a numbers or strings.")]
     ExpectedNumbersOrStrings,
+    #[error("Only errors have properties.")]
+    ExpectedError,
+    #[error("Undefined property '{0}'.")]
+    UndefinedProperty(String),
+    #[error("Uncaught exception: {0}")]
+    UncaughtException(String),
     #[error("Unknown opcode: {0:#x}")]
     UnknownOpcode(u8),
+    #[error("Failed to write trace: {0}")]
+    Trace(io::Error),
+}
+
+impl ErrorKind {
+    // Errors caused by malformed bytecode can't be caught by user code
+    fn is_catchable(&self) -> bool {
+        !matches!(
+            self,
+            Self::StackUnderflow
+                | Self::LocalOutOfRange(_)
+                | Self::ConstantOutOfRange(_)
+                | Self::TruncatedConstantIndex
+                | Self::NonStringGlobalName
+                | Self::UncaughtException(_)
+                | Self::UnknownOpcode(_)
+                | Self::Trace(_)
+        )
+    }
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("[line {line}] {kind}")]
+#[error("[line {}:{}] {kind}", .span.line, .span.column)]
 pub struct Error {
     kind: ErrorKind,
-    line: usize,
+    span: Span,
+    trace: Vec<TraceFrame>,
+}
+
+impl Error {
+    /// Call stack at the time of the error, innermost frame first
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.trace
+    }
+
+    pub fn traceback(&self) -> String {
+        self.trace
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub function: String,
+    pub file: Option<String>,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}:{}] in ", self.line, self.column)?;
+        match self.function.as_str() {
+            "script" => write!(f, "script")?,
+            name => write!(f, "{}()", name)?,
+        }
+        if let Some(file) = &self.file {
+            write!(f, " ({})", file)?;
+        }
+        Ok(())
+    }
 }
 
 type Result<T = ()> = std::result::Result<T, Error>;
@@ -53,6 +154,8 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
             state,
             ip: 0,
             stack: vec![],
+            handlers: vec![],
+            throw_sites: HashMap::new(),
         }
     }
 
@@ -62,17 +165,29 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
         byte
     }
 
-    fn read_constant(&mut self) -> Value {
-        self.chunk.constants.values[self.read_byte() as usize].clone()
+    fn read_short(&mut self) -> u16 {
+        u16::from_le_bytes([self.read_byte(), self.read_byte()])
     }
 
-    fn read_constant_long(&mut self) -> Value {
-        let mut bytes = [0; std::mem::size_of::<usize>()];
-        for b in &mut bytes[..3] {
-            *b = self.read_byte();
-        }
-        let index = usize::from_le_bytes(bytes);
-        self.chunk.constants.values[index].clone()
+    fn read_constant(&mut self) -> Result<Value> {
+        let index = self.read_byte() as usize;
+        self.constant(index)
+    }
+
+    fn read_constant_long(&mut self) -> Result<Value> {
+        let (index, len) = chunk::decode_index(&self.chunk.code[self.ip..])
+            .ok_or_else(|| self.report(ErrorKind::TruncatedConstantIndex))?;
+        self.ip += len;
+        self.constant(index)
+    }
+
+    fn constant(&self, index: usize) -> Result<Value> {
+        self.chunk
+            .constants
+            .values
+            .get(index)
+            .cloned()
+            .ok_or_else(|| self.report(ErrorKind::ConstantOutOfRange(index)))
     }
 
     fn push(&mut self, value: Value) {
@@ -91,6 +206,13 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
             .ok_or_else(|| self.report(ErrorKind::StackUnderflow))
     }
 
+    fn local(&mut self, slot: usize) -> Result<&mut Value> {
+        if slot >= self.stack.len() {
+            return Err(self.report(ErrorKind::LocalOutOfRange(slot)));
+        }
+        Ok(&mut self.stack[slot])
+    }
+
     fn get_global(&mut self, name: Value) -> Result {
         if let Some(name) = name.into_obj_string() {
             match self.state.globals.get(&name) {
@@ -135,6 +257,60 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
         }
     }
 
+    fn get_property(&mut self, name: Value) -> Result {
+        let object = self.pop()?;
+        let name = match name.into_string() {
+            Some(name) => name,
+            None => return Err(self.report(ErrorKind::NonStringGlobalName)),
+        };
+        let error = match object {
+            Value::Obj(obj) => match *obj {
+                Obj::ObjError(error) => error,
+                _ => return Err(self.report(ErrorKind::ExpectedError)),
+            },
+            _ => return Err(self.report(ErrorKind::ExpectedError)),
+        };
+        let ObjError { message, line } = error;
+        match &*name {
+            "message" => self.push(Value::string(message)),
+            "line" => self.push(Value::number(line as f64)),
+            _ => return Err(self.report(ErrorKind::UndefinedProperty(name))),
+        }
+        Ok(())
+    }
+
+    // Transfers control to the innermost handler, giving value back if there
+    // is none
+    fn unwind(
+        &mut self,
+        value: Value,
+        site: Span,
+    ) -> std::result::Result<(), Value> {
+        match self.handlers.pop() {
+            Some(Handler { ip, stack_len }) => {
+                self.stack.truncate(stack_len);
+                self.throw_sites.insert(stack_len, site);
+                self.push(value);
+                self.ip = ip;
+                Ok(())
+            }
+            None => Err(value),
+        }
+    }
+
+    fn throw(&mut self, value: Value, site: Span) -> Result {
+        self.unwind(value, site).map_err(|value| {
+            let message = match value {
+                Value::Obj(obj) => match *obj {
+                    Obj::ObjString(s) => s.0.into_string(),
+                    Obj::ObjError(e) => e.message,
+                },
+                value => format!("{:?}", value),
+            };
+            self.report_span(site, ErrorKind::UncaughtException(message))
+        })
+    }
+
     fn bin_op(&mut self, op: impl Fn(f64, f64) -> Value) -> Result {
         let b = self.pop()?;
         let a = self.pop()?;
@@ -148,22 +324,94 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
     }
 
     fn report(&self, kind: ErrorKind) -> Error {
-        let line = self.chunk.get_line(self.ip - 1).unwrap_or(0);
-        Error { kind, line }
+        self.report_at(self.ip - 1, kind)
+    }
+
+    fn report_at(&self, offset: usize, kind: ErrorKind) -> Error {
+        self.report_span(self.span_at(offset), kind)
+    }
+
+    fn report_span(&self, span: Span, kind: ErrorKind) -> Error {
+        // Top-level script is the only frame until functions are compiled
+        let trace = vec![TraceFrame {
+            function: "script".into(),
+            file: self.state.file.clone(),
+            line: span.line,
+            column: span.column,
+        }];
+        Error { kind, span, trace }
+    }
+
+    fn span_at(&self, offset: usize) -> Span {
+        self.chunk.get_span(offset).unwrap_or_default()
     }
 
-    pub fn interpret(&mut self, debug: bool) -> Result {
-        if debug {
-            debug::disassembly_chunk(self.chunk, "code");
-            println!("---- execution ----");
+    // Trace is written before instruction at `ip` is read
+    fn trace_error(&self, error: io::Error) -> Error {
+        self.report_at(self.ip, ErrorKind::Trace(error))
+    }
+
+    /// Runs chunk to completion, reporting every executed instruction to
+    /// given hooks
+    pub fn interpret(&mut self, mut hooks: Hooks) -> Result {
+        if let Some(tracer) = hooks.tracer.as_deref_mut() {
+            tracer
+                .chunk(self.chunk, "code")
+                .and_then(|_| tracer.start())
+                .map_err(|e| self.trace_error(e))?;
+        }
+        if let Some(profiler) = hooks.profiler.as_deref_mut() {
+            profiler.enter("script");
+        }
+        if let Some(coverage) = hooks.coverage.as_deref_mut() {
+            let file = self.state.file.as_deref().unwrap_or(NO_FILE);
+            for (_, span) in self.chunk.span_runs().filter(|(_, s)| s.line > 0)
+            {
+                coverage.add_line(file, span.line);
+            }
         }
+        let result = self.run(&mut hooks);
+        if let Some(profiler) = hooks.profiler {
+            profiler.exit();
+        }
+        if let Some(tracer) = hooks.tracer {
+            tracer.flush().map_err(|e| self.trace_error(e))?;
+        }
+        result
+    }
+
+    fn run(&mut self, hooks: &mut Hooks) -> Result {
         loop {
-            if debug {
-                println!("{:?}", self.stack);
-                debug::disassembly_instruction(self.chunk, self.ip);
+            if let Some(profiler) = hooks.profiler.as_deref_mut() {
+                let name = match Opcode::check(self.chunk.code[self.ip]) {
+                    Some(opcode) => debug::opcode_info(&opcode).0,
+                    None => "<unknown>",
+                };
+                profiler.hit(self.chunk.get_line(self.ip), name);
             }
-            if let Some(ControlFlow::Return) = self.step()? {
-                return Ok(());
+            if let Some(coverage) = hooks.coverage.as_deref_mut() {
+                if let Some(line) = self.chunk.get_line(self.ip) {
+                    let file = self.state.file.as_deref().unwrap_or(NO_FILE);
+                    coverage.hit(file, line);
+                }
+            }
+            if let Some(tracer) = hooks.tracer.as_deref_mut() {
+                // Top-level script is the only function for now
+                tracer
+                    .instruction(self.chunk, self.ip, "script", &self.stack)
+                    .map_err(|e| self.trace_error(e))?;
+            }
+            match self.step() {
+                Ok(Some(ControlFlow::Return)) => return Ok(()),
+                Ok(None) => {}
+                Err(error) if error.kind.is_catchable() => {
+                    let value =
+                        Value::error(error.kind.to_string(), error.span.line);
+                    if self.unwind(value, error.span).is_err() {
+                        return Err(error);
+                    }
+                }
+                Err(error) => return Err(error),
             }
         }
     }
@@ -172,11 +420,11 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
         let instruction = self.read_byte();
         match Opcode::check(instruction) {
             Some(Opcode::Constant) => {
-                let constant = self.read_constant();
+                let constant = self.read_constant()?;
                 self.push(constant);
             }
             Some(Opcode::ConstantLong) => {
-                let constant = self.read_constant_long();
+                let constant = self.read_constant_long()?;
                 self.push(constant);
             }
             Some(Opcode::Nil) => self.push(Value::nil()),
@@ -186,29 +434,52 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
                 self.pop()?;
             }
             Some(Opcode::GetGlobal) => {
-                let name = self.read_constant();
+                let name = self.read_constant()?;
                 self.get_global(name)?;
             }
             Some(Opcode::GetGlobalLong) => {
-                let name = self.read_constant_long();
+                let name = self.read_constant_long()?;
                 self.get_global(name)?;
             }
             Some(Opcode::DefineGlobal) => {
-                let name = self.read_constant();
+                let name = self.read_constant()?;
                 self.define_global(name)?;
             }
             Some(Opcode::DefineGlobalLong) => {
-                let name = self.read_constant_long();
+                let name = self.read_constant_long()?;
                 self.define_global(name)?;
             }
             Some(Opcode::SetGlobal) => {
-                let name = self.read_constant();
+                let name = self.read_constant()?;
                 self.set_global(name)?;
             }
             Some(Opcode::SetGlobalLong) => {
-                let name = self.read_constant_long();
+                let name = self.read_constant_long()?;
                 self.set_global(name)?;
             }
+            Some(Opcode::GetLocal) => {
+                let slot = self.read_byte() as usize;
+                let value = self.local(slot)?.clone();
+                self.push(value);
+            }
+            Some(Opcode::SetLocal) => {
+                let slot = self.read_byte() as usize;
+                let value = self.top()?.clone();
+                *self.local(slot)? = value;
+                // Catch body's exception becomes pending for `finally`
+                match self.throw_sites.get(&(self.stack.len() - 1)) {
+                    Some(&site) => self.throw_sites.insert(slot, site),
+                    None => self.throw_sites.remove(&slot),
+                };
+            }
+            Some(Opcode::GetProperty) => {
+                let name = self.read_constant()?;
+                self.get_property(name)?;
+            }
+            Some(Opcode::GetPropertyLong) => {
+                let name = self.read_constant_long()?;
+                self.get_property(name)?;
+            }
             Some(Opcode::Equal) => {
                 let b = self.pop()?;
                 let a = self.pop()?;
@@ -216,6 +487,18 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
             }
             Some(Opcode::Greater) => self.bin_op(|l, r| Value::bool(l > r))?,
             Some(Opcode::Less) => self.bin_op(|l, r| Value::bool(l < r))?,
+            Some(Opcode::NotEqual) => {
+                let b = self.pop()?;
+                let a = self.pop()?;
+                self.push(Value::bool(a != b))
+            }
+            // Fused `LESS, NOT` and `GREATER, NOT`, so comparisons with NaN
+            // give the same results as unoptimized code
+            Some(Opcode::GreaterEqual) => self
+                .bin_op(|l, r| Value::bool(l.partial_cmp(&r) != Some(Less)))?,
+            Some(Opcode::LessEqual) => self.bin_op(|l, r| {
+                Value::bool(l.partial_cmp(&r) != Some(Greater))
+            })?,
             Some(Opcode::Add) => {
                 let b = self.pop()?;
                 let a = self.pop()?;
@@ -254,6 +537,35 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
             Some(Opcode::Print) => {
                 println!("{:?}", self.pop()?)
             }
+            Some(Opcode::Jump) => {
+                let offset = self.read_short();
+                self.ip += offset as usize;
+            }
+            Some(Opcode::Try) => {
+                let offset = self.read_short();
+                self.handlers.push(Handler {
+                    ip: self.ip + offset as usize,
+                    stack_len: self.stack.len(),
+                });
+            }
+            Some(Opcode::PopHandler) => {
+                self.handlers.pop();
+            }
+            Some(Opcode::Throw) => {
+                let value = self.pop()?;
+                self.throw(value, self.span_at(self.ip - 1))?;
+            }
+            Some(Opcode::EndFinally) => {
+                let pending = self.pop()?;
+                let exception = self.pop()?;
+                if !pending.is_falsey() {
+                    let site = match self.throw_sites.get(&self.stack.len()) {
+                        Some(&site) => site,
+                        None => self.span_at(self.ip - 1),
+                    };
+                    self.throw(exception, site)?;
+                }
+            }
             Some(Opcode::Return) => return Ok(Some(ControlFlow::Return)),
             None => {
                 return Err(self.report(ErrorKind::UnknownOpcode(instruction)))
@@ -262,3 +574,6 @@ impl<'chunk, 'state> Vm<'chunk, 'state> {
         Ok(None)
     }
 }
+
+#[cfg(test)]
+mod tests;
@@ -0,0 +1,209 @@
+use crate::{coverage::Coverage, profile::Profiler};
+
+use super::{
+    super::{
+        assembler::assemble,
+        compiler::compile,
+        optimizer::{optimize, OptLevel},
+        verifier::verify,
+        vm::VmState,
+    },
+    *,
+};
+
+// Runs both unoptimized and optimized code, checking they agree
+#[track_caller]
+fn run(source: &str) -> Result {
+    let chunk = compile(source).unwrap();
+    let optimized = optimize(&chunk, OptLevel::O1);
+    verify(&chunk).unwrap();
+    verify(&optimized).unwrap();
+    let result =
+        Vm::new(&chunk, &mut VmState::default()).interpret(Hooks::default());
+    let optimized_result = Vm::new(&optimized, &mut VmState::default())
+        .interpret(Hooks::default());
+    assert_eq!(
+        result.as_ref().map_err(ToString::to_string),
+        optimized_result.as_ref().map_err(ToString::to_string)
+    );
+    result
+}
+
+#[test]
+fn exceptions() {
+    assert!(run("try { throw 1; } catch (e) {}").is_ok());
+    assert!(run("try { -nil; } catch (e) { e.message; }").is_ok());
+    assert!(run("try { throw 1; } finally {}").is_err());
+
+    let error = run("{ var a = 1; try { throw a; } catch (e) { throw e; } }")
+        .unwrap_err();
+    assert_eq!(error.to_string(), "[line 1:43] Uncaught exception: 1.0");
+
+    let error = run("try {} catch (e) { e.missing; }\nnil + 1;").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 2:5] Operands must be a numbers or strings."
+    );
+}
+
+#[test]
+fn rethrow_from_finally() {
+    let error =
+        run("try {\n  throw 1;\n} finally {\n  print 2;\n}").unwrap_err();
+    assert_eq!(error.to_string(), "[line 2:3] Uncaught exception: 1.0");
+
+    let error = run(
+        "try {\n  throw 1;\n} catch (e) {\n  throw 2;\n} finally {\n  \
+         try { throw 3; } catch (e) {}\n}",
+    )
+    .unwrap_err();
+    assert_eq!(error.to_string(), "[line 4:3] Uncaught exception: 2.0");
+
+    let error = run("try {\n  -nil;\n} finally {}").unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 2:3] Uncaught exception: Operand must be a number."
+    );
+}
+
+#[test]
+fn comparisons() {
+    assert!(run("try { throw 1 >= 2 != nil <= 0; } catch (e) {}").is_ok());
+
+    let error =
+        run("var nan = 0 / 0;\nthrow nan >= 1 == !(nan < 1);").unwrap_err();
+    assert_eq!(error.to_string(), "[line 2:1] Uncaught exception: true");
+
+    let error = run("print 1 +\n(2 <= \"a\");").unwrap_err();
+    assert_eq!(error.to_string(), "[line 2:4] Operand must be a number.");
+}
+
+#[test]
+fn error_positions() {
+    let error = run("var a = \"one\ntwo\";\n  print -a;").unwrap_err();
+    assert_eq!(error.to_string(), "[line 3:9] Operand must be a number.");
+
+    let error = compile("var a = \"one\ntwo\";\n  print a +;")
+        .err()
+        .unwrap();
+    assert_eq!(
+        error.to_string(),
+        "[Line 3:12] Parser error at ';': Expect expression"
+    );
+}
+
+#[test]
+fn assembled() {
+    let chunk = assemble(
+        "
+        .line 1
+            OP_NIL
+            OP_TRY handler
+            OP_TRUE
+            OP_CONSTANT '1.0'
+            OP_NEGATE
+            OP_ADD
+            OP_POP_HANDLER
+        handler:
+        .line 2
+            OP_GET_PROPERTY '\"line\"'
+            OP_THROW
+        ",
+    )
+    .unwrap();
+    verify(&chunk).unwrap();
+    let error = Vm::new(&chunk, &mut VmState::default())
+        .interpret(Hooks::default())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "[line 2:0] Uncaught exception: 1.0");
+}
+
+#[test]
+fn local_out_of_range() {
+    let chunk = assemble("OP_GET_LOCAL 0\nOP_RETURN").unwrap();
+    let error = Vm::new(&chunk, &mut VmState::default())
+        .interpret(Hooks::default())
+        .unwrap_err();
+    assert_eq!(error.to_string(), "[line 1:0] Local slot 0 is out of range");
+}
+
+#[test]
+fn constant_out_of_range() {
+    let mut chunk = Chunk::default();
+    for &byte in &[Opcode::CONSTANT, 1, Opcode::RETURN] {
+        chunk.write(byte, Span::default());
+    }
+    let error = Vm::new(&chunk, &mut VmState::default())
+        .interpret(Hooks::default())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 0:0] Constant index 1 is out of range"
+    );
+
+    let mut chunk = Chunk::default();
+    for &byte in &[Opcode::CONSTANT_LONG, 0x80] {
+        chunk.write(byte, Span::default());
+    }
+    let error = Vm::new(&chunk, &mut VmState::default())
+        .interpret(Hooks::default())
+        .unwrap_err();
+    assert_eq!(
+        error.to_string(),
+        "[line 0:0] Constant index is cut off by end of code"
+    );
+}
+
+#[test]
+fn profiler() {
+    let chunk = compile("var a = 1;\na + 2;\na + 3;\n").unwrap();
+    let mut profiler = Profiler::new("opcode");
+    Vm::new(&chunk, &mut VmState::default())
+        .interpret(Hooks {
+            profiler: Some(&mut profiler),
+            ..Hooks::default()
+        })
+        .unwrap();
+
+    assert_eq!(
+        profiler.name_counts(),
+        vec![
+            ("OP_CONSTANT", 3),
+            ("OP_ADD", 2),
+            ("OP_GET_GLOBAL", 2),
+            ("OP_POP", 2),
+            ("OP_DEFINE_GLOBAL", 1),
+            ("OP_RETURN", 1),
+        ]
+    );
+    assert_eq!(profiler.line_counts(), vec![(3, 5), (2, 4), (1, 2)]);
+    let functions = profiler.function_stats();
+    assert_eq!(functions.len(), 1);
+    assert_eq!(functions[0].0, "script");
+    assert_eq!(functions[0].1.calls, 1);
+    assert_eq!(functions[0].1.inclusive, functions[0].1.exclusive);
+}
+
+#[test]
+fn coverage() {
+    let chunk =
+        compile("var a = 1;\ntry {\n  throw a;\n  a;\n} catch (e) {}").unwrap();
+    let mut state = VmState {
+        file: Some("test.lox".into()),
+        ..VmState::default()
+    };
+    let mut coverage = Coverage::new();
+    Vm::new(&chunk, &mut state)
+        .interpret(Hooks {
+            coverage: Some(&mut coverage),
+            ..Hooks::default()
+        })
+        .unwrap();
+
+    let mut lcov = vec![];
+    coverage.write_lcov(&mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.contains("SF:test.lox\n"));
+    assert!(lcov.contains("DA:3,2\nDA:4,0\n"));
+    assert_eq!(coverage.file_summary("test.lox"), Some((5, 4)));
+}
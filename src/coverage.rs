@@ -0,0 +1,175 @@
+use std::{
+    collections::BTreeMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+};
+
+/// Name used for scripts that don't come from a file
+pub const NO_FILE: &str = "<script>";
+
+/// Executed source lines per file, shared by both backends
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Coverage {
+    // Hit count of every line that has code on it, including ones that were
+    // never executed
+    files: BTreeMap<String, BTreeMap<usize, u64>>,
+}
+
+impl Coverage {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    fn lines(&mut self, file: &str) -> &mut BTreeMap<usize, u64> {
+        // Avoids allocating file name on every hit
+        if !self.files.contains_key(file) {
+            self.files.insert(file.to_owned(), BTreeMap::new());
+        }
+        self.files.get_mut(file).unwrap()
+    }
+
+    /// Marks `line` as having code, so it's reported even if never executed
+    pub fn add_line(&mut self, file: &str, line: usize) {
+        self.lines(file).entry(line).or_insert(0);
+    }
+
+    pub fn hit(&mut self, file: &str, line: usize) {
+        *self.lines(file).entry(line).or_insert(0) += 1;
+    }
+
+    /// Number of lines with code and number of those that were executed
+    pub fn file_summary(&self, file: &str) -> Option<(usize, usize)> {
+        let lines = self.files.get(file)?;
+        let hit = lines.values().filter(|&&hits| hits > 0).count();
+        Some((lines.len(), hit))
+    }
+
+    /// Writes report in lcov tracefile format, as read by `genhtml` and
+    /// most coverage tools
+    pub fn write_lcov(&self, out: &mut dyn Write) -> io::Result<()> {
+        for (file, lines) in &self.files {
+            writeln!(out, "TN:")?;
+            writeln!(out, "SF:{}", file)?;
+            for (line, hits) in lines {
+                writeln!(out, "DA:{},{}", line, hits)?;
+            }
+            let (found, hit) = self.file_summary(file).unwrap();
+            writeln!(out, "LF:{}", found)?;
+            writeln!(out, "LH:{}", hit)?;
+            writeln!(out, "end_of_record")?;
+        }
+        Ok(())
+    }
+
+    /// Writes lcov report to `path` and prints summary to stderr
+    pub fn report(&self, path: &Path) -> io::Result<()> {
+        self.write_summary(&mut io::stderr())?;
+        let mut file = BufWriter::new(File::create(path)?);
+        self.write_lcov(&mut file)?;
+        file.flush()
+    }
+
+    pub fn write_summary(&self, out: &mut dyn Write) -> io::Result<()> {
+        let percent = |hit: usize, found: usize| match found {
+            0 => 100.0,
+            _ => 100.0 * hit as f64 / found as f64,
+        };
+        let width = self
+            .files
+            .keys()
+            .map(|file| file.len())
+            .chain([5])
+            .max()
+            .unwrap();
+
+        writeln!(out, "== coverage ==")?;
+        writeln!(
+            out,
+            "{:<width$} {:>8} {:>8} {:>8}",
+            "file",
+            "lines",
+            "hit",
+            "cover",
+            width = width
+        )?;
+        let (mut total_found, mut total_hit) = (0, 0);
+        for file in self.files.keys() {
+            let (found, hit) = self.file_summary(file).unwrap();
+            total_found += found;
+            total_hit += hit;
+            writeln!(
+                out,
+                "{:<width$} {:>8} {:>8} {:>7.1}%",
+                file,
+                found,
+                hit,
+                percent(hit, found),
+                width = width
+            )?;
+        }
+        writeln!(
+            out,
+            "{:<width$} {:>8} {:>8} {:>7.1}%",
+            "total",
+            total_found,
+            total_hit,
+            percent(total_hit, total_found),
+            width = width
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports() {
+        let mut coverage = Coverage::new();
+        for line in 1..=4 {
+            coverage.add_line("a.lox", line);
+        }
+        coverage.hit("a.lox", 1);
+        coverage.hit("a.lox", 3);
+        coverage.hit("a.lox", 3);
+        coverage.hit("b.lox", 2);
+        assert_eq!(coverage.file_summary("a.lox"), Some((4, 2)));
+
+        let mut lcov = vec![];
+        coverage.write_lcov(&mut lcov).unwrap();
+        assert_eq!(
+            String::from_utf8(lcov).unwrap(),
+            "\
+TN:
+SF:a.lox
+DA:1,1
+DA:2,0
+DA:3,2
+DA:4,0
+LF:4
+LH:2
+end_of_record
+TN:
+SF:b.lox
+DA:2,1
+LF:1
+LH:1
+end_of_record
+"
+        );
+
+        let mut summary = vec![];
+        coverage.write_summary(&mut summary).unwrap();
+        assert_eq!(
+            String::from_utf8(summary).unwrap(),
+            "\
+== coverage ==
+file     lines      hit    cover
+a.lox        4        2    50.0%
+b.lox        1        1   100.0%
+total        5        3    60.0%
+"
+        );
+    }
+}
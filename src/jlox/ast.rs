@@ -1,8 +1,26 @@
+use std::{
+    collections::BTreeSet,
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
 use super::{tokens::Token, types::Value};
 
+/// Key for resolved variable references, unique across all parsed trees so
+/// separately parsed REPL lines never collide
+#[derive(Clone, Copy, Debug, Hash, Eq, PartialEq)]
+pub struct NodeId(usize);
+
+impl NodeId {
+    pub fn next() -> Self {
+        static NEXT: AtomicUsize = AtomicUsize::new(0);
+        Self(NEXT.fetch_add(1, Ordering::Relaxed))
+    }
+}
+
 #[derive(Clone, Debug, Hash, Eq, PartialEq)]
 pub enum Expr {
     Assign {
+        id: NodeId,
         name: Token,
         value: Box<Expr>,
     },
@@ -25,6 +43,7 @@ pub enum Expr {
     },
     Literal {
         value: Value,
+        token: Token,
     },
     Set {
         object: Box<Expr>,
@@ -32,10 +51,12 @@ pub enum Expr {
         value: Box<Expr>,
     },
     Super {
+        id: NodeId,
         keyword: Token,
         method: Token,
     },
     This {
+        id: NodeId,
         keyword: Token,
     },
     Unary {
@@ -43,6 +64,7 @@ pub enum Expr {
         right: Box<Expr>,
     },
     Variable {
+        id: NodeId,
         name: Token,
     },
 }
@@ -50,6 +72,7 @@ pub enum Expr {
 impl Expr {
     pub fn assign(name: Token, value: Expr) -> Self {
         Self::Assign {
+            id: NodeId::next(),
             name,
             value: Box::new(value),
         }
@@ -88,8 +111,8 @@ impl Expr {
         }
     }
 
-    pub fn literal(value: Value) -> Self {
-        Self::Literal { value }
+    pub fn literal(value: Value, token: Token) -> Self {
+        Self::Literal { value, token }
     }
 
     pub fn set(object: Expr, name: Token, value: Expr) -> Self {
@@ -101,11 +124,18 @@ impl Expr {
     }
 
     pub fn super_(keyword: Token, method: Token) -> Self {
-        Self::Super { keyword, method }
+        Self::Super {
+            id: NodeId::next(),
+            keyword,
+            method,
+        }
     }
 
     pub fn this(keyword: Token) -> Self {
-        Self::This { keyword }
+        Self::This {
+            id: NodeId::next(),
+            keyword,
+        }
     }
 
     pub fn unary(op: Token, right: Expr) -> Self {
@@ -116,7 +146,31 @@ impl Expr {
     }
 
     pub fn variable(name: Token) -> Self {
-        Self::Variable { name }
+        Self::Variable {
+            id: NodeId::next(),
+            name,
+        }
+    }
+
+    /// First token found in expression
+    pub fn first_token(&self) -> &Token {
+        match self {
+            Self::Assign { name, .. } | Self::Variable { name, .. } => name,
+            Self::Binary { left, .. } => left.first_token(),
+            Self::Call { callee, .. } => callee.first_token(),
+            Self::Get { object, .. } | Self::Set { object, .. } => {
+                object.first_token()
+            }
+            Self::Grouping { expr } => expr.first_token(),
+            Self::Literal { token, .. } => token,
+            Self::Super { keyword, .. } | Self::This { keyword, .. } => keyword,
+            Self::Unary { op, .. } => op,
+        }
+    }
+
+    /// Line of the first token found in expression
+    pub fn line(&self) -> Option<u32> {
+        Some(self.first_token().pos.0)
     }
 }
 
@@ -127,7 +181,8 @@ pub enum Stmt {
     },
     Class {
         name: Token,
-        superclass: Option<Token>,
+        /// Always `Expr::Variable`
+        superclass: Option<Expr>,
         methods: Vec<Function>,
     },
     Expression {
@@ -146,8 +201,18 @@ pub enum Stmt {
         keyword: Token,
         value: Option<Expr>,
     },
+    Throw {
+        keyword: Token,
+        value: Expr,
+    },
+    Try {
+        body: Vec<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Stmt>>,
+    },
     Var {
         name: Token,
+        type_: Option<Token>,
         init: Option<Expr>,
     },
     While {
@@ -160,6 +225,15 @@ pub enum Stmt {
 pub struct Function {
     pub name: Token,
     pub params: Vec<Token>,
+    /// Annotated types of `params`, `None` where left out
+    pub param_types: Vec<Option<Token>>,
+    pub return_type: Option<Token>,
+    pub body: Vec<Stmt>,
+}
+
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct Catch {
+    pub name: Token,
     pub body: Vec<Stmt>,
 }
 
@@ -170,7 +244,7 @@ impl Stmt {
 
     pub fn class(
         name: Token,
-        superclass: Option<Token>,
+        superclass: Option<Expr>,
         methods: Vec<Function>,
     ) -> Self {
         Self::Class {
@@ -185,7 +259,13 @@ impl Stmt {
     }
 
     pub fn function(name: Token, params: Vec<Token>, body: Vec<Stmt>) -> Self {
-        Self::Function(Function { name, params, body })
+        Self::Function(Function {
+            name,
+            param_types: vec![None; params.len()],
+            params,
+            return_type: None,
+            body,
+        })
     }
 
     pub fn if_(
@@ -208,8 +288,24 @@ impl Stmt {
         Self::Return { keyword, value }
     }
 
-    pub fn var(name: Token, init: Option<Expr>) -> Self {
-        Self::Var { name, init }
+    pub fn throw(keyword: Token, value: Expr) -> Self {
+        Self::Throw { keyword, value }
+    }
+
+    pub fn try_(
+        body: Vec<Stmt>,
+        catch: Option<Catch>,
+        finally: Option<Vec<Stmt>>,
+    ) -> Self {
+        Self::Try {
+            body,
+            catch,
+            finally,
+        }
+    }
+
+    pub fn var(name: Token, type_: Option<Token>, init: Option<Expr>) -> Self {
+        Self::Var { name, type_, init }
     }
 
     pub fn while_(condition: Expr, body: Stmt) -> Self {
@@ -218,4 +314,557 @@ impl Stmt {
             body: Box::new(body),
         }
     }
+
+    /// First token found in statement, `None` for empty blocks
+    pub fn first_token(&self) -> Option<&Token> {
+        fn first(stmts: &[Stmt]) -> Option<&Token> {
+            stmts.iter().find_map(Stmt::first_token)
+        }
+        match self {
+            Self::Block { statements } => first(statements),
+            Self::Class { name, .. }
+            | Self::Function(Function { name, .. })
+            | Self::Var { name, .. } => Some(name),
+            Self::Expression { expr } | Self::PrintStmt { expr } => {
+                Some(expr.first_token())
+            }
+            Self::If { condition, .. } | Self::While { condition, .. } => {
+                Some(condition.first_token())
+            }
+            Self::Return { keyword, .. } | Self::Throw { keyword, .. } => {
+                Some(keyword)
+            }
+            Self::Try { body, .. } => first(body),
+        }
+    }
+
+    /// Line statement starts at, as far as it can be told from its tokens
+    pub fn line(&self) -> Option<u32> {
+        self.first_token().map(|token| token.pos.0)
+    }
+
+    /// Short name of statement kind, as written in source
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Block { .. } => "block",
+            Self::Class { .. } => "class",
+            Self::Expression { .. } => "expression",
+            Self::Function(_) => "fun",
+            Self::If { .. } => "if",
+            Self::PrintStmt { .. } => "print",
+            Self::Return { .. } => "return",
+            Self::Throw { .. } => "throw",
+            Self::Try { .. } => "try",
+            Self::Var { .. } => "var",
+            Self::While { .. } => "while",
+        }
+    }
+}
+
+/// Lines of all statements, including ones nested in blocks and functions
+pub fn statement_lines(statements: &[Stmt]) -> BTreeSet<u32> {
+    struct Lines(BTreeSet<u32>);
+
+    impl Visitor for Lines {
+        fn visit_stmt(&mut self, stmt: &Stmt) {
+            self.0.extend(stmt.line());
+            walk_stmt(self, stmt);
+        }
+
+        // Only statements have lines
+        fn visit_expr(&mut self, _: &Expr) {}
+    }
+
+    let mut lines = Lines(BTreeSet::new());
+    for stmt in statements {
+        lines.visit_stmt(stmt);
+    }
+    lines.0
+}
+
+/// Walks tree by reference. Every method defaults to visiting children of
+/// the node, overriding one without calling `walk_*` skips them.
+pub trait Visitor {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        walk_stmt(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        walk_expr(self, expr);
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        walk_function(self, function);
+    }
+}
+
+pub fn walk_stmt<V: Visitor + ?Sized>(visitor: &mut V, stmt: &Stmt) {
+    match stmt {
+        Stmt::Block { statements } => {
+            statements.iter().for_each(|s| visitor.visit_stmt(s))
+        }
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr(superclass);
+            }
+            methods.iter().for_each(|m| visitor.visit_function(m))
+        }
+        Stmt::Expression { expr }
+        | Stmt::PrintStmt { expr }
+        | Stmt::Throw { value: expr, .. } => visitor.visit_expr(expr),
+        Stmt::Function(function) => visitor.visit_function(function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            body.iter().for_each(|s| visitor.visit_stmt(s));
+            if let Some(catch) = catch {
+                catch.body.iter().for_each(|s| visitor.visit_stmt(s));
+            }
+            if let Some(finally) = finally {
+                finally.iter().for_each(|s| visitor.visit_stmt(s));
+            }
+        }
+        Stmt::Var { init, .. } => {
+            if let Some(init) = init {
+                visitor.visit_expr(init);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+    }
+}
+
+pub fn walk_expr<V: Visitor + ?Sized>(visitor: &mut V, expr: &Expr) {
+    match expr {
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(callee);
+            arguments.iter().for_each(|a| visitor.visit_expr(a));
+        }
+        Expr::Get { object, .. } => visitor.visit_expr(object),
+        Expr::Grouping { expr } => visitor.visit_expr(expr),
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(value);
+        }
+        Expr::Unary { right, .. } => visitor.visit_expr(right),
+        Expr::Literal { .. }
+        | Expr::Super { .. }
+        | Expr::This { .. }
+        | Expr::Variable { .. } => {}
+    }
+}
+
+pub fn walk_function<V: Visitor + ?Sized>(
+    visitor: &mut V,
+    function: &Function,
+) {
+    function.body.iter().for_each(|s| visitor.visit_stmt(s));
+}
+
+/// Walks tree rewriting nodes in place, defaults are the same as in
+/// [`Visitor`]
+pub trait MutVisitor {
+    fn visit_stmt(&mut self, stmt: &mut Stmt) {
+        walk_stmt_mut(self, stmt);
+    }
+
+    fn visit_expr(&mut self, expr: &mut Expr) {
+        walk_expr_mut(self, expr);
+    }
+
+    fn visit_function(&mut self, function: &mut Function) {
+        walk_function_mut(self, function);
+    }
+}
+
+pub fn walk_stmt_mut<V: MutVisitor + ?Sized>(visitor: &mut V, stmt: &mut Stmt) {
+    match stmt {
+        Stmt::Block { statements } => {
+            statements.iter_mut().for_each(|s| visitor.visit_stmt(s))
+        }
+        Stmt::Class {
+            superclass,
+            methods,
+            ..
+        } => {
+            if let Some(superclass) = superclass {
+                visitor.visit_expr(superclass);
+            }
+            methods.iter_mut().for_each(|m| visitor.visit_function(m))
+        }
+        Stmt::Expression { expr }
+        | Stmt::PrintStmt { expr }
+        | Stmt::Throw { value: expr, .. } => visitor.visit_expr(expr),
+        Stmt::Function(function) => visitor.visit_function(function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(then_branch);
+            if let Some(else_branch) = else_branch {
+                visitor.visit_stmt(else_branch);
+            }
+        }
+        Stmt::Return { value, .. } => {
+            if let Some(value) = value {
+                visitor.visit_expr(value);
+            }
+        }
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            body.iter_mut().for_each(|s| visitor.visit_stmt(s));
+            if let Some(catch) = catch {
+                catch.body.iter_mut().for_each(|s| visitor.visit_stmt(s));
+            }
+            if let Some(finally) = finally {
+                finally.iter_mut().for_each(|s| visitor.visit_stmt(s));
+            }
+        }
+        Stmt::Var { init, .. } => {
+            if let Some(init) = init {
+                visitor.visit_expr(init);
+            }
+        }
+        Stmt::While { condition, body } => {
+            visitor.visit_expr(condition);
+            visitor.visit_stmt(body);
+        }
+    }
+}
+
+pub fn walk_expr_mut<V: MutVisitor + ?Sized>(visitor: &mut V, expr: &mut Expr) {
+    match expr {
+        Expr::Assign { value, .. } => visitor.visit_expr(value),
+        Expr::Binary { left, right, .. } => {
+            visitor.visit_expr(left);
+            visitor.visit_expr(right);
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => {
+            visitor.visit_expr(callee);
+            arguments.iter_mut().for_each(|a| visitor.visit_expr(a));
+        }
+        Expr::Get { object, .. } => visitor.visit_expr(object),
+        Expr::Grouping { expr } => visitor.visit_expr(expr),
+        Expr::Set { object, value, .. } => {
+            visitor.visit_expr(object);
+            visitor.visit_expr(value);
+        }
+        Expr::Unary { right, .. } => visitor.visit_expr(right),
+        Expr::Literal { .. }
+        | Expr::Super { .. }
+        | Expr::This { .. }
+        | Expr::Variable { .. } => {}
+    }
+}
+
+pub fn walk_function_mut<V: MutVisitor + ?Sized>(
+    visitor: &mut V,
+    function: &mut Function,
+) {
+    function.body.iter_mut().for_each(|s| visitor.visit_stmt(s));
+}
+
+/// Rebuilds tree from owned nodes, so they can be replaced with nodes of
+/// another kind. Defaults fold children and keep the node itself.
+pub trait Folder {
+    fn fold_stmt(&mut self, stmt: Stmt) -> Stmt {
+        fold_stmt(self, stmt)
+    }
+
+    fn fold_expr(&mut self, expr: Expr) -> Expr {
+        fold_expr(self, expr)
+    }
+
+    fn fold_function(&mut self, function: Function) -> Function {
+        fold_function(self, function)
+    }
+}
+
+fn fold_stmts<F: Folder + ?Sized>(
+    folder: &mut F,
+    stmts: Vec<Stmt>,
+) -> Vec<Stmt> {
+    stmts.into_iter().map(|s| folder.fold_stmt(s)).collect()
+}
+
+fn fold_box<F: Folder + ?Sized>(folder: &mut F, expr: Box<Expr>) -> Box<Expr> {
+    Box::new(folder.fold_expr(*expr))
+}
+
+pub fn fold_stmt<F: Folder + ?Sized>(folder: &mut F, stmt: Stmt) -> Stmt {
+    match stmt {
+        Stmt::Block { statements } => Stmt::Block {
+            statements: fold_stmts(folder, statements),
+        },
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => Stmt::Class {
+            name,
+            superclass: superclass.map(|s| folder.fold_expr(s)),
+            methods: methods
+                .into_iter()
+                .map(|m| folder.fold_function(m))
+                .collect(),
+        },
+        Stmt::Expression { expr } => Stmt::Expression {
+            expr: folder.fold_expr(expr),
+        },
+        Stmt::Function(function) => {
+            Stmt::Function(folder.fold_function(function))
+        }
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => Stmt::If {
+            condition: folder.fold_expr(condition),
+            then_branch: Box::new(folder.fold_stmt(*then_branch)),
+            else_branch: else_branch.map(|s| Box::new(folder.fold_stmt(*s))),
+        },
+        Stmt::PrintStmt { expr } => Stmt::PrintStmt {
+            expr: folder.fold_expr(expr),
+        },
+        Stmt::Return { keyword, value } => Stmt::Return {
+            keyword,
+            value: value.map(|v| folder.fold_expr(v)),
+        },
+        Stmt::Throw { keyword, value } => Stmt::Throw {
+            keyword,
+            value: folder.fold_expr(value),
+        },
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => Stmt::Try {
+            body: fold_stmts(folder, body),
+            catch: catch.map(|Catch { name, body }| Catch {
+                name,
+                body: fold_stmts(folder, body),
+            }),
+            finally: finally.map(|f| fold_stmts(folder, f)),
+        },
+        Stmt::Var { name, type_, init } => Stmt::Var {
+            name,
+            type_,
+            init: init.map(|i| folder.fold_expr(i)),
+        },
+        Stmt::While { condition, body } => Stmt::While {
+            condition: folder.fold_expr(condition),
+            body: Box::new(folder.fold_stmt(*body)),
+        },
+    }
+}
+
+pub fn fold_expr<F: Folder + ?Sized>(folder: &mut F, expr: Expr) -> Expr {
+    match expr {
+        Expr::Assign { id, name, value } => Expr::Assign {
+            id,
+            name,
+            value: fold_box(folder, value),
+        },
+        Expr::Binary { op, left, right } => Expr::Binary {
+            op,
+            left: fold_box(folder, left),
+            right: fold_box(folder, right),
+        },
+        Expr::Call {
+            callee,
+            right_paren,
+            arguments,
+        } => Expr::Call {
+            callee: fold_box(folder, callee),
+            right_paren,
+            arguments: arguments
+                .into_iter()
+                .map(|a| folder.fold_expr(a))
+                .collect(),
+        },
+        Expr::Get { object, name } => Expr::Get {
+            object: fold_box(folder, object),
+            name,
+        },
+        Expr::Grouping { expr } => Expr::Grouping {
+            expr: fold_box(folder, expr),
+        },
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => Expr::Set {
+            object: fold_box(folder, object),
+            name,
+            value: fold_box(folder, value),
+        },
+        Expr::Unary { op, right } => Expr::Unary {
+            op,
+            right: fold_box(folder, right),
+        },
+        expr @ (Expr::Literal { .. }
+        | Expr::Super { .. }
+        | Expr::This { .. }
+        | Expr::Variable { .. }) => expr,
+    }
+}
+
+pub fn fold_function<F: Folder + ?Sized>(
+    folder: &mut F,
+    function: Function,
+) -> Function {
+    Function {
+        body: fold_stmts(folder, function.body),
+        ..function
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{parser::Parser, printer, tokenizer::Tokenizer},
+        *,
+    };
+
+    fn parse(source: &str) -> Vec<Stmt> {
+        let tokens = Tokenizer::new(source)
+            .map(Result::unwrap)
+            .filter(|t| !t.can_skip())
+            .collect();
+        Parser::new(tokens).parse().unwrap()
+    }
+
+    const SOURCE: &str = "\
+fun f(a) { return a + 1 * 2; }
+class A { m() { print this.x = -(b - 3); } }
+while (c) { var d = e(1 + 1); }
+";
+
+    #[test]
+    fn visitor() {
+        #[derive(Default)]
+        struct Names(Vec<String>);
+
+        impl Visitor for Names {
+            fn visit_expr(&mut self, expr: &Expr) {
+                if let Expr::Variable { name, .. } = expr {
+                    self.0.push(name.lexeme.clone());
+                }
+                walk_expr(self, expr);
+            }
+        }
+
+        let mut names = Names::default();
+        parse(SOURCE).iter().for_each(|stmt| names.visit_stmt(stmt));
+        assert_eq!(names.0, ["a", "b", "c", "e"]);
+    }
+
+    #[test]
+    fn mut_visitor() {
+        struct Rename;
+
+        impl MutVisitor for Rename {
+            fn visit_expr(&mut self, expr: &mut Expr) {
+                if let Expr::Variable { name, .. } = expr {
+                    name.lexeme = name.lexeme.to_uppercase();
+                }
+                walk_expr_mut(self, expr);
+            }
+        }
+
+        let mut program = parse(SOURCE);
+        program.iter_mut().for_each(|stmt| Rename.visit_stmt(stmt));
+        assert_eq!(
+            printer::program(&program, printer::Format::Sexp),
+            "\
+(fun f(a) (return (+ A (* 1 2))))
+(class A (fun m() (print (= this x (- (group (- B 3)))))))
+(while C (block (var d = (call E (+ 1 1)))))"
+        );
+    }
+
+    #[test]
+    fn folder() {
+        // Folds arithmetic on number literals
+        struct Constants;
+
+        impl Folder for Constants {
+            fn fold_expr(&mut self, expr: Expr) -> Expr {
+                let number = |expr: &Expr| match expr {
+                    Expr::Literal {
+                        value: Value::Number(n),
+                        ..
+                    } => Some(*n),
+                    _ => None,
+                };
+                match fold_expr(self, expr) {
+                    Expr::Binary { op, left, right } => {
+                        let value = match (number(&left), number(&right)) {
+                            (Some(l), Some(r)) => match op.lexeme.as_str() {
+                                "+" => Some(l + r),
+                                "*" => Some(l * r),
+                                _ => None,
+                            },
+                            _ => None,
+                        };
+                        match value {
+                            Some(n) => Expr::literal(Value::Number(n), op),
+                            None => Expr::Binary { op, left, right },
+                        }
+                    }
+                    expr => expr,
+                }
+            }
+        }
+
+        let program: Vec<_> = parse(SOURCE)
+            .into_iter()
+            .map(|stmt| Constants.fold_stmt(stmt))
+            .collect();
+        assert_eq!(
+            printer::program(&program, printer::Format::Sexp),
+            "\
+(fun f(a) (return (+ a 2)))
+(class A (fun m() (print (= this x (- (group (- b 3)))))))
+(while c (block (var d = (call e 2))))"
+        );
+    }
 }
@@ -0,0 +1,877 @@
+//! Static checker for optional type annotations, run by `lox check`.
+//! Anything not annotated is inferred, values of unknown type are `Any` and
+//! never reported.
+
+use std::{collections::HashMap, fmt};
+
+use super::{
+    ast::{walk_stmt, Expr, Function, Stmt, Visitor},
+    errors::TypeError,
+    tokens::{Token, TokenType},
+    types::Value,
+};
+
+// Declarations are told apart by span of their name
+type Key = (usize, usize);
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Any,
+    Nil,
+    Bool,
+    Number,
+    String,
+    /// Function or method declared under given name
+    Function(Token),
+    /// Class object, called to create instances
+    Class(String),
+    Instance(String),
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Any => f.write_str("Any"),
+            Type::Nil => f.write_str("Nil"),
+            Type::Bool => f.write_str("Bool"),
+            Type::Number => f.write_str("Number"),
+            Type::String => f.write_str("String"),
+            Type::Function(name) => write!(f, "fun {}", name.lexeme),
+            Type::Class(name) => write!(f, "class {}", name),
+            Type::Instance(name) => f.write_str(name),
+        }
+    }
+}
+
+impl Type {
+    // Type of value that's either of `self` or `other`
+    fn join(self, other: Type) -> Type {
+        if self == other {
+            self
+        } else {
+            Type::Any
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Signature {
+    params: Vec<Type>,
+    // Annotated return type, otherwise it's inferred from body
+    returns: Option<Type>,
+    // Initializers return new instance
+    initializer: Option<String>,
+}
+
+#[derive(Debug, Default)]
+struct ClassInfo {
+    superclass: Option<String>,
+    methods: HashMap<String, Token>,
+    // Joined types of everything assigned to every field
+    fields: HashMap<String, Type>,
+}
+
+#[derive(Debug)]
+struct Variable {
+    key: Key,
+    // Annotated type, along with the annotation
+    annotated: Option<(Type, Token)>,
+}
+
+#[derive(Debug)]
+struct FunctionContext {
+    key: Key,
+    returns: Option<Type>,
+}
+
+struct Checker {
+    scopes: Vec<HashMap<String, Variable>>,
+    // Every class declared anywhere, so annotations can name them before
+    // their declaration
+    classes: HashMap<String, ClassInfo>,
+    signatures: HashMap<Key, Signature>,
+    // Joined types of everything assigned to variables without annotation
+    inferred: HashMap<Key, Type>,
+    // Joined types of everything returned by functions without annotation
+    returns: HashMap<Key, Type>,
+    function: Option<FunctionContext>,
+    class: Option<String>,
+    report: bool,
+    errors: Vec<TypeError>,
+}
+
+fn key(token: &Token) -> Key {
+    token.span
+}
+
+// Collects classes declared anywhere in program
+#[derive(Default)]
+struct Classes(HashMap<String, ClassInfo>);
+
+impl Visitor for Classes {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        if let Stmt::Class { name, .. } = stmt {
+            self.0.entry(name.lexeme.clone()).or_default();
+        }
+        walk_stmt(self, stmt);
+    }
+
+    // Classes can't be declared in expressions
+    fn visit_expr(&mut self, _: &Expr) {}
+}
+
+// Whether control can't get past `statements`
+fn exits(statements: &[Stmt]) -> bool {
+    statements.iter().any(exits_statement)
+}
+
+fn exits_statement(stmt: &Stmt) -> bool {
+    match stmt {
+        Stmt::Return { .. } | Stmt::Throw { .. } => true,
+        Stmt::Block { statements } => exits(statements),
+        Stmt::If {
+            then_branch,
+            else_branch: Some(else_branch),
+            ..
+        } => exits_statement(then_branch) && exits_statement(else_branch),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            let caught = catch.as_ref().is_none_or(|c| exits(&c.body));
+            (exits(body) && caught) || finally.as_deref().is_some_and(exits)
+        }
+        _ => false,
+    }
+}
+
+impl Checker {
+    fn new(program: &[Stmt]) -> Self {
+        let mut classes = Classes::default();
+        program.iter().for_each(|stmt| classes.visit_stmt(stmt));
+        Self {
+            scopes: vec![],
+            classes: classes.0,
+            signatures: HashMap::new(),
+            inferred: HashMap::new(),
+            returns: HashMap::new(),
+            function: None,
+            class: None,
+            report: false,
+            errors: vec![],
+        }
+    }
+
+    fn error(&mut self, error: TypeError) {
+        if self.report {
+            self.errors.push(error);
+        }
+    }
+
+    fn mismatch(&mut self, expected: &Type, got: &Type, at: &Token) {
+        let message = format!("Expected {} but got {}.", expected, got);
+        self.error(TypeError::new(Some(at), message));
+    }
+
+    // Type named by annotation
+    fn annotation(&mut self, name: &Token) -> Type {
+        match name.lexeme.as_str() {
+            "Any" => Type::Any,
+            "Nil" => Type::Nil,
+            "Bool" => Type::Bool,
+            "Number" => Type::Number,
+            "String" => Type::String,
+            class if self.classes.contains_key(class) => {
+                Type::Instance(class.to_owned())
+            }
+            _ => {
+                let message = format!("Unknown type '{}'.", name.lexeme);
+                self.error(TypeError::new(Some(name), message));
+                Type::Any
+            }
+        }
+    }
+
+    // Class followed by its superclasses
+    fn ancestors(&self, class: &str) -> Vec<String> {
+        let mut ancestors = vec![class.to_owned()];
+        // Superclasses can't form a cycle at runtime, but names could
+        while ancestors.len() <= self.classes.len() {
+            let superclass = self
+                .classes
+                .get(ancestors.last().unwrap())
+                .and_then(|info| info.superclass.clone());
+            match superclass {
+                Some(superclass) => ancestors.push(superclass),
+                None => break,
+            }
+        }
+        ancestors
+    }
+
+    // First thing `f` finds in class or its superclasses
+    fn find<T>(
+        &self,
+        class: &str,
+        f: impl Fn(&ClassInfo) -> Option<T>,
+    ) -> Option<T> {
+        self.ancestors(class)
+            .iter()
+            .filter_map(|class| self.classes.get(class))
+            .find_map(f)
+    }
+
+    fn assignable(&self, from: &Type, to: &Type) -> bool {
+        match (from, to) {
+            (Type::Any, _) | (_, Type::Any) => true,
+            (Type::Instance(class), Type::Instance(superclass)) => {
+                self.ancestors(class).contains(superclass)
+            }
+            _ => from == to,
+        }
+    }
+
+    fn declare(&mut self, name: &Token, annotated: Option<(Type, Token)>) {
+        let variable = Variable {
+            key: key(name),
+            annotated,
+        };
+        self.scopes
+            .last_mut()
+            .unwrap()
+            .insert(name.lexeme.clone(), variable);
+    }
+
+    fn lookup(&self, name: &str) -> Option<&Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name))
+    }
+
+    fn variable_type(&self, name: &str) -> Type {
+        match self.lookup(name) {
+            Some(Variable {
+                annotated: Some((type_, _)),
+                ..
+            }) => type_.clone(),
+            Some(variable) => self
+                .inferred
+                .get(&variable.key)
+                .cloned()
+                .unwrap_or(Type::Any),
+            None => Type::Any,
+        }
+    }
+
+    // Records `type_` as assigned to variable declared at `key`
+    fn infer(&mut self, key: Key, type_: Type) {
+        let joined = match self.inferred.remove(&key) {
+            Some(previous) => previous.join(type_),
+            None => type_,
+        };
+        self.inferred.insert(key, joined);
+    }
+
+    fn return_type(&self, name: &Token) -> Type {
+        let signature = &self.signatures[&key(name)];
+        if let Some(class) = &signature.initializer {
+            return Type::Instance(class.clone());
+        }
+        signature
+            .returns
+            .clone()
+            .or_else(|| self.returns.get(&key(name)).cloned())
+            .unwrap_or(Type::Any)
+    }
+
+    // Declares names of globals, which functions can use before they're
+    // declared
+    fn declare_globals(&mut self, program: &[Stmt]) {
+        self.scopes = vec![HashMap::new()];
+        // Annotations are reported once their declaration is checked
+        let report = std::mem::replace(&mut self.report, false);
+        for stmt in program {
+            match stmt {
+                Stmt::Class { name, .. } => {
+                    self.declare(name, None);
+                    self.infer(key(name), Type::Class(name.lexeme.clone()));
+                }
+                Stmt::Function(function) => {
+                    self.signature(function, None);
+                    self.declare(&function.name, None);
+                    let type_ = Type::Function(function.name.clone());
+                    self.infer(key(&function.name), type_);
+                }
+                Stmt::Var { name, type_, .. } => {
+                    let annotated =
+                        type_.as_ref().map(|t| (self.annotation(t), t.clone()));
+                    self.declare(name, annotated);
+                }
+                _ => {}
+            }
+        }
+        self.report = report;
+    }
+
+    fn signature(&mut self, function: &Function, class: Option<&str>) {
+        let params = function
+            .param_types
+            .iter()
+            .map(|type_| match type_ {
+                Some(type_) => self.annotation(type_),
+                None => Type::Any,
+            })
+            .collect();
+        let returns = function.return_type.as_ref().map(|t| self.annotation(t));
+        let initializer = class
+            .filter(|_| function.name.lexeme == "init")
+            .map(ToOwned::to_owned);
+        self.signatures.insert(
+            key(&function.name),
+            Signature {
+                params,
+                returns,
+                initializer,
+            },
+        );
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.statement(stmt);
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.scopes.push(HashMap::new());
+        self.statements(statements);
+        self.scopes.pop();
+    }
+
+    fn function(&mut self, function: &Function) {
+        let signature = &self.signatures[&key(&function.name)];
+        let params = signature.params.clone();
+        let initializer = signature.initializer.is_some();
+        let context = FunctionContext {
+            key: key(&function.name),
+            returns: signature.returns.clone(),
+        };
+        let enclosing = self.function.replace(context);
+
+        self.scopes.push(HashMap::new());
+        let annotations = function.params.iter().zip(&function.param_types);
+        for ((param, annotation), type_) in annotations.zip(params) {
+            let annotated = annotation.clone().map(|t| (type_, t));
+            self.declare(param, annotated);
+        }
+        self.statements(&function.body);
+        let returns = self.function.as_ref().and_then(|f| f.returns.clone());
+        match returns {
+            _ if exits(&function.body) => {}
+            Some(expected)
+                if !initializer && !self.assignable(&Type::Nil, &expected) =>
+            {
+                let message = format!(
+                    "Missing return in function returning {}.",
+                    expected
+                );
+                self.error(TypeError::new(Some(&function.name), message));
+            }
+            _ => self.returned(Type::Nil, None),
+        }
+        self.scopes.pop();
+
+        self.function = enclosing;
+    }
+
+    // Checks value returned from current function
+    fn returned(&mut self, type_: Type, at: Option<&Token>) {
+        let context = match &self.function {
+            Some(context) => context,
+            None => return,
+        };
+        match (&context.returns, at) {
+            (Some(expected), Some(at)) => {
+                if !self.assignable(&type_, expected) {
+                    let expected = expected.clone();
+                    self.mismatch(&expected, &type_, at);
+                }
+            }
+            (Some(_), None) => {}
+            (None, _) => {
+                let key = context.key;
+                let joined = match self.returns.remove(&key) {
+                    Some(previous) => previous.join(type_),
+                    None => type_,
+                };
+                self.returns.insert(key, joined);
+            }
+        }
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.declare(name, None);
+                self.infer(key(name), Type::Class(name.lexeme.clone()));
+                if let Some(superclass) = superclass {
+                    match self.expr(superclass) {
+                        Type::Class(_) | Type::Any => {}
+                        _ => self.error(TypeError::new(
+                            Some(superclass.first_token()),
+                            "Superclass must be a class.",
+                        )),
+                    }
+                }
+
+                let class = name.lexeme.clone();
+                let info = self.classes.entry(class.clone()).or_default();
+                info.superclass =
+                    superclass.as_ref().map(|e| e.first_token().lexeme.clone());
+                for method in methods {
+                    info.methods.insert(
+                        method.name.lexeme.clone(),
+                        method.name.clone(),
+                    );
+                }
+                for method in methods {
+                    self.signature(method, Some(&class));
+                }
+
+                let enclosing = self.class.replace(class);
+                for method in methods {
+                    self.function(method);
+                }
+                self.class = enclosing;
+            }
+            Stmt::Expression { expr }
+            | Stmt::PrintStmt { expr }
+            | Stmt::Throw { value: expr, .. } => {
+                self.expr(expr);
+            }
+            Stmt::Function(function) => {
+                self.signature(function, None);
+                self.declare(&function.name, None);
+                let type_ = Type::Function(function.name.clone());
+                self.infer(key(&function.name), type_);
+                self.function(function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.expr(condition);
+                self.statement(then_branch);
+                if let Some(else_branch) = else_branch {
+                    self.statement(else_branch);
+                }
+            }
+            Stmt::Return { keyword, value } => match value {
+                Some(value) => {
+                    let type_ = self.expr(value);
+                    self.returned(type_, Some(value.first_token()));
+                }
+                None => self.returned(Type::Nil, Some(keyword)),
+            },
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.scopes.push(HashMap::new());
+                    self.declare(
+                        &catch.name,
+                        Some((Type::Any, catch.name.clone())),
+                    );
+                    self.statements(&catch.body);
+                    self.scopes.pop();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+            Stmt::Var { name, type_, init } => {
+                let value = init.as_ref().map(|init| (self.expr(init), init));
+                let annotated =
+                    type_.as_ref().map(|t| (self.annotation(t), t.clone()));
+                match (&annotated, value) {
+                    (Some((expected, _)), Some((type_, init))) => {
+                        if !self.assignable(&type_, expected) {
+                            self.mismatch(expected, &type_, init.first_token());
+                        }
+                    }
+                    // Variables can be declared before they're assigned
+                    (Some(_), None) => {}
+                    (None, value) => {
+                        let type_ = value.map_or(Type::Nil, |(type_, _)| type_);
+                        self.infer(key(name), type_);
+                    }
+                }
+                self.declare(name, annotated);
+            }
+            Stmt::While { condition, body } => {
+                self.expr(condition);
+                self.statement(body);
+            }
+        }
+    }
+
+    fn numbers(&mut self, op: &Token, operands: &[&Type]) -> bool {
+        let numbers = operands
+            .iter()
+            .all(|t| matches!(t, Type::Number | Type::Any));
+        if !numbers {
+            let message = match operands.len() {
+                1 => "Operand must be a number.",
+                _ => "Operands must be numbers.",
+            };
+            self.error(TypeError::new(Some(op), message));
+        }
+        numbers
+    }
+
+    fn call(
+        &mut self,
+        callee: Type,
+        arguments: &[Expr],
+        paren: &Token,
+    ) -> Type {
+        let arguments: Vec<_> = arguments
+            .iter()
+            .map(|argument| (self.expr(argument), argument.first_token()))
+            .collect();
+        let function = match &callee {
+            Type::Any => return Type::Any,
+            Type::Function(name) => Some(name.clone()),
+            Type::Class(class) => {
+                let init =
+                    self.find(class, |info| info.methods.get("init").cloned());
+                // Without initializer class takes no arguments
+                if init.is_none() && !arguments.is_empty() {
+                    let message = format!(
+                        "Expected 0 arguments but got {}.",
+                        arguments.len()
+                    );
+                    self.error(TypeError::new(Some(paren), message));
+                }
+                init
+            }
+            _ => {
+                self.error(TypeError::new(
+                    Some(paren),
+                    "Can only call functions and classes.",
+                ));
+                return Type::Any;
+            }
+        };
+
+        if let Some(function) = &function {
+            let params = self.signatures[&key(function)].params.clone();
+            if params.len() != arguments.len() {
+                let message = format!(
+                    "Expected {} arguments but got {}.",
+                    params.len(),
+                    arguments.len()
+                );
+                let error = TypeError::new(Some(paren), message)
+                    .with_note(Some(function), "declared here");
+                self.error(error);
+            } else {
+                for (param, (argument, at)) in params.iter().zip(&arguments) {
+                    if !self.assignable(argument, param) {
+                        self.mismatch(param, argument, at);
+                    }
+                }
+            }
+        }
+        match (callee, function) {
+            (Type::Class(class), _) => Type::Instance(class),
+            (_, Some(function)) => self.return_type(&function),
+            _ => Type::Any,
+        }
+    }
+
+    fn property(&self, class: &str, name: &str) -> Type {
+        let field = self.find(class, |info| info.fields.get(name).cloned());
+        let method = || {
+            self.find(class, |info| info.methods.get(name).cloned())
+                .map(Type::Function)
+        };
+        field.or_else(method).unwrap_or(Type::Any)
+    }
+
+    fn expr(&mut self, expr: &Expr) -> Type {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                let type_ = self.expr(value);
+                let variable = match self.lookup(&name.lexeme) {
+                    Some(variable) => variable,
+                    None => return type_,
+                };
+                match variable.annotated.clone() {
+                    Some((expected, annotation)) => {
+                        if !self.assignable(&type_, &expected) {
+                            let message = format!(
+                                "Expected {} but got {}.",
+                                expected, type_
+                            );
+                            self.error(
+                                TypeError::new(
+                                    Some(value.first_token()),
+                                    message,
+                                )
+                                .with_note(
+                                    Some(&annotation),
+                                    "variable annotated here",
+                                ),
+                            );
+                        }
+                    }
+                    None => self.infer(variable.key, type_.clone()),
+                }
+                type_
+            }
+            Expr::Binary { op, left, right } => {
+                let left = self.expr(left);
+                let right = self.expr(right);
+                match op.type_ {
+                    TokenType::And | TokenType::Or => left.join(right),
+                    TokenType::EqualEqual | TokenType::BangEqual => Type::Bool,
+                    TokenType::Greater
+                    | TokenType::GreaterEqual
+                    | TokenType::Less
+                    | TokenType::LessEqual => {
+                        self.numbers(op, &[&left, &right]);
+                        Type::Bool
+                    }
+                    TokenType::Plus => match (left, right) {
+                        (Type::Number, Type::Number) => Type::Number,
+                        (Type::String, Type::String) => Type::String,
+                        (Type::Any, Type::Any) => Type::Any,
+                        (Type::Any, t @ (Type::Number | Type::String))
+                        | (t @ (Type::Number | Type::String), Type::Any) => t,
+                        _ => {
+                            self.error(TypeError::new(
+                                Some(op),
+                                "Operands must be two numbers or two strings.",
+                            ));
+                            Type::Any
+                        }
+                    },
+                    _ => {
+                        self.numbers(op, &[&left, &right]);
+                        Type::Number
+                    }
+                }
+            }
+            Expr::Call {
+                callee,
+                right_paren,
+                arguments,
+            } => {
+                let callee = self.expr(callee);
+                self.call(callee, arguments, right_paren)
+            }
+            Expr::Get { object, name } => match self.expr(object) {
+                Type::Instance(class) => self.property(&class, &name.lexeme),
+                Type::Any => Type::Any,
+                _ => {
+                    self.error(TypeError::new(
+                        Some(name),
+                        "Only instances have properties.",
+                    ));
+                    Type::Any
+                }
+            },
+            Expr::Grouping { expr } => self.expr(expr),
+            Expr::Literal { value, .. } => match value {
+                Value::Nil => Type::Nil,
+                Value::Bool(_) => Type::Bool,
+                Value::Number(_) => Type::Number,
+                Value::String(_) => Type::String,
+                _ => Type::Any,
+            },
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                let type_ = self.expr(value);
+                match self.expr(object) {
+                    Type::Instance(class) => {
+                        let info = self.classes.entry(class).or_default();
+                        let field = name.lexeme.clone();
+                        let joined = match info.fields.remove(&field) {
+                            Some(previous) => previous.join(type_.clone()),
+                            None => type_.clone(),
+                        };
+                        info.fields.insert(field, joined);
+                    }
+                    Type::Any => {}
+                    _ => self.error(TypeError::new(
+                        Some(name),
+                        "Only instances have fields.",
+                    )),
+                }
+                type_
+            }
+            Expr::Super { method, .. } => {
+                let superclass = self
+                    .class
+                    .as_ref()
+                    .and_then(|class| self.classes.get(class))
+                    .and_then(|info| info.superclass.clone());
+                match superclass {
+                    Some(superclass) => self
+                        .find(&superclass, |info| {
+                            info.methods.get(&method.lexeme).cloned()
+                        })
+                        .map_or(Type::Any, Type::Function),
+                    None => Type::Any,
+                }
+            }
+            Expr::This { .. } => match &self.class {
+                Some(class) => Type::Instance(class.clone()),
+                None => Type::Any,
+            },
+            Expr::Unary { op, right } => {
+                let right = self.expr(right);
+                match op.type_ {
+                    TokenType::Bang => Type::Bool,
+                    _ => {
+                        self.numbers(op, &[&right]);
+                        Type::Number
+                    }
+                }
+            }
+            Expr::Variable { name, .. } => self.variable_type(&name.lexeme),
+        }
+    }
+}
+
+/// Checks types of resolved program against its annotations, annotations
+/// don't have any effect at runtime
+pub fn check(program: &[Stmt]) -> Vec<TypeError> {
+    let mut checker = Checker::new(program);
+    // First pass only infers types, so the second one knows everything
+    // assigned to variables, fields and returned from functions
+    for report in [false, true] {
+        checker.report = report;
+        checker.declare_globals(program);
+        checker.statements(program);
+    }
+    let mut errors = checker.errors;
+    errors.sort_by_key(|error| error.0 .0.as_ref().map(|t| t.span));
+    errors
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{parser::Parser, tokenizer::Tokenizer},
+        *,
+    };
+
+    fn run(source: &str) -> Vec<(u32, String)> {
+        let tokens = Tokenizer::new(source)
+            .map(Result::unwrap)
+            .filter(|t| !t.can_skip())
+            .collect();
+        let program = Parser::new(tokens).parse().unwrap();
+        check(&program)
+            .into_iter()
+            .map(|e| (e.0 .0.unwrap().pos.0, e.0 .1))
+            .collect()
+    }
+
+    #[test]
+    fn annotations() {
+        let source = "\
+fun greet(name: String): String { return \"hi \" + name; }
+var n: Number = greet(\"a\");
+greet(1);
+greet();
+var b: Bool;
+b = nil;
+fun f(): Number { return; }
+var x: Foo;
+fun noret(): Number { }
+fun branches(a): Number { if (a) return 1; else { throw 2; } }
+fun maybe(a): Number { if (a) return 1; }
+fun caught(): String { try { return \"a\"; } catch (e) { print e; } }
+";
+        assert_eq!(
+            run(source),
+            [
+                (2, "Expected Number but got String.".into()),
+                (3, "Expected String but got Number.".into()),
+                (4, "Expected 1 arguments but got 0.".into()),
+                (6, "Expected Bool but got Nil.".into()),
+                (7, "Expected Number but got Nil.".into()),
+                (8, "Unknown type 'Foo'.".into()),
+                (9, "Missing return in function returning Number.".into()),
+                (11, "Missing return in function returning Number.".into()),
+                (12, "Missing return in function returning String.".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn inference() {
+        let source = "\
+fun twice(x) { return x + x; }
+fun one() { return 1; }
+var s = \"s\";
+print -s;
+print one() + \"a\";
+var m = 1;
+m = \"m\";
+print -m;
+print twice(1) - 1;
+print s.length;
+s();
+";
+        assert_eq!(
+            run(source),
+            [
+                (4, "Operand must be a number.".into()),
+                (5, "Operands must be two numbers or two strings.".into()),
+                (10, "Only instances have properties.".into()),
+                (11, "Can only call functions and classes.".into()),
+            ]
+        );
+    }
+
+    #[test]
+    fn classes() {
+        let source = "\
+class A {
+  init(n: Number) { this.n = n; this.name = \"a\"; }
+  get(): Number { return this.n; }
+}
+class B < A {}
+var a: A = B(1);
+var b: B = A(1);
+print a.get() + a.name;
+a.n = 2;
+A(\"x\");
+B();
+";
+        assert_eq!(
+            run(source),
+            [
+                (7, "Expected B but got A.".into()),
+                (8, "Operands must be two numbers or two strings.".into()),
+                (10, "Expected Number but got String.".into()),
+                (11, "Expected 1 arguments but got 0.".into()),
+            ]
+        );
+    }
+}
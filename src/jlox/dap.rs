@@ -0,0 +1,614 @@
+//! Debug Adapter Protocol server, lets editors drive jlox debugger over
+//! stdio. Lox scripts have no imports, so breakpoints of every source are
+//! treated as breakpoints of the launched program.
+
+use std::{
+    cell::RefCell,
+    collections::BTreeSet,
+    fs,
+    io::{self, BufRead, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    ast,
+    debugger::{Debugger, Frontend, Resume, Session, StopReason},
+    diagnostics::Color,
+    parser::Parser,
+    tokenizer::Tokenizer,
+    transport,
+    types::ValueRef,
+};
+use crate::{JLox, Lox};
+
+// Lox has no threads, so there's only this one
+const THREAD_ID: u64 = 1;
+
+#[derive(Debug, Deserialize)]
+struct Request {
+    command: String,
+    seq: u64,
+    #[serde(default)]
+    arguments: Value,
+}
+
+struct Connection {
+    input: Box<dyn BufRead>,
+    output: Box<dyn Write>,
+    seq: u64,
+    // Set when client asked to stop program while it was paused
+    terminated: bool,
+    disconnected: bool,
+}
+
+type Shared = Rc<RefCell<Connection>>;
+
+impl Connection {
+    /// Reads next request, returns `None` at end of input
+    fn read(&mut self) -> io::Result<Option<Request>> {
+        transport::read(&mut self.input)
+    }
+
+    fn send(&mut self, mut message: Value) -> io::Result<()> {
+        self.seq += 1;
+        message["seq"] = self.seq.into();
+        transport::write(&mut self.output, &message)
+    }
+
+    fn respond(
+        &mut self,
+        request: &Request,
+        result: Result<Value, String>,
+    ) -> io::Result<()> {
+        let mut response = json!({
+            "type": "response",
+            "request_seq": request.seq,
+            "command": request.command,
+            "success": result.is_ok(),
+        });
+        match result {
+            Ok(Value::Null) => {}
+            Ok(body) => response["body"] = body,
+            Err(message) => response["message"] = message.into(),
+        }
+        self.send(response)
+    }
+
+    fn event(&mut self, event: &str, body: Value) -> io::Result<()> {
+        let mut message = json!({ "type": "event", "event": event });
+        if !body.is_null() {
+            message["body"] = body;
+        }
+        self.send(message)
+    }
+}
+
+/// Sends program output to client as `output` events, one per line
+struct OutputEvents {
+    connection: Shared,
+    buffer: Vec<u8>,
+}
+
+impl Write for OutputEvents {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buffer.extend_from_slice(buf);
+        if let Some(end) = self.buffer.iter().rposition(|&b| b == b'\n') {
+            let lines: Vec<_> = self.buffer.drain(..=end).collect();
+            send_output(&self.connection, "stdout", &lines)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.buffer.is_empty() {
+            let rest = std::mem::take(&mut self.buffer);
+            send_output(&self.connection, "stdout", &rest)?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for OutputEvents {
+    fn drop(&mut self) {
+        self.flush().ok();
+    }
+}
+
+fn send_output(
+    connection: &Shared,
+    category: &str,
+    text: &[u8],
+) -> io::Result<()> {
+    connection.borrow_mut().event(
+        "output",
+        json!({
+            "category": category,
+            "output": String::from_utf8_lossy(text),
+        }),
+    )
+}
+
+/// Lines breakpoints can be set on, empty if file can't be parsed
+fn statement_lines(path: &Path) -> BTreeSet<u32> {
+    let source = fs::read_to_string(path).unwrap_or_default();
+    Tokenizer::new(&source)
+        .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
+        .collect::<Result<Vec<_>, _>>()
+        .ok()
+        .and_then(|tokens| Parser::new(tokens).parse().ok())
+        .map(|program| ast::statement_lines(&program))
+        .unwrap_or_default()
+}
+
+fn set_breakpoints(
+    arguments: &Value,
+    breakpoints: &mut BTreeSet<u32>,
+) -> Result<Value, String> {
+    let path = arguments["source"]["path"]
+        .as_str()
+        .ok_or("Missing source path")?;
+    let lines = statement_lines(path.as_ref());
+    let requested = arguments["breakpoints"].as_array().cloned();
+    breakpoints.clear();
+    let verified: Vec<_> = requested
+        .unwrap_or_default()
+        .iter()
+        .filter_map(|breakpoint| breakpoint["line"].as_u64())
+        .map(|line| {
+            let line = line as u32;
+            let verified = lines.contains(&line);
+            if verified {
+                breakpoints.insert(line);
+            }
+            json!({ "verified": verified, "line": line })
+        })
+        .collect();
+    Ok(json!({ "breakpoints": verified }))
+}
+
+/// Answers requests while program is stopped
+struct Adapter {
+    connection: Shared,
+    source: Value,
+    // Scopes handed out by `scopes` request, `variablesReference` is index
+    // plus one, valid until program resumes
+    references: Vec<Vec<(String, ValueRef)>>,
+}
+
+impl Adapter {
+    fn stack_trace(&self, session: &Session) -> Value {
+        let frames: Vec<_> = session
+            .frames()
+            .iter()
+            .enumerate()
+            .rev()
+            .map(|(i, frame)| {
+                json!({
+                    "id": i + 1,
+                    "name": frame.function,
+                    "line": frame.line,
+                    "column": 1,
+                    "source": self.source,
+                })
+            })
+            .collect();
+        json!({ "stackFrames": frames, "totalFrames": frames.len() })
+    }
+
+    // Frame given by `frameId` argument, innermost one if it's missing
+    fn frame(arguments: &Value, session: &Session) -> Result<usize, String> {
+        let frames = session.frames().len();
+        match arguments["frameId"].as_u64() {
+            Some(id) if id >= 1 && id as usize <= frames => Ok(id as usize - 1),
+            Some(id) => Err(format!("No frame {}", id)),
+            None => Ok(frames.saturating_sub(1)),
+        }
+    }
+
+    fn scopes(&mut self, arguments: &Value, session: &Session) -> Value {
+        let scopes = Self::frame(arguments, session)
+            .map(|frame| session.scopes(frame))
+            .unwrap_or_default();
+        let globals = scopes.len().saturating_sub(1);
+        let scopes: Vec<_> = scopes
+            .into_iter()
+            .enumerate()
+            .map(|(i, variables)| {
+                let name = match i {
+                    _ if i == globals => "Globals",
+                    0 => "Locals",
+                    _ => "Closure",
+                };
+                self.references.push(variables);
+                json!({
+                    "name": name,
+                    "variablesReference": self.references.len(),
+                    "expensive": false,
+                })
+            })
+            .collect();
+        json!({ "scopes": scopes })
+    }
+
+    fn variables(&self, arguments: &Value) -> Result<Value, String> {
+        let reference = arguments["variablesReference"].as_u64().unwrap_or(0);
+        let variables = (reference as usize)
+            .checked_sub(1)
+            .and_then(|i| self.references.get(i))
+            .ok_or_else(|| format!("No variables reference {}", reference))?;
+        let variables: Vec<_> = variables
+            .iter()
+            .map(|(name, value)| {
+                json!({
+                    "name": name,
+                    "value": value.value().to_string(),
+                    "variablesReference": 0,
+                })
+            })
+            .collect();
+        Ok(json!({ "variables": variables }))
+    }
+
+    fn evaluate(
+        arguments: &Value,
+        session: &mut Session,
+    ) -> Result<Value, String> {
+        let frame = Self::frame(arguments, session)?;
+        let expression = arguments["expression"].as_str().unwrap_or("");
+        let value = session.evaluate(expression, frame)?;
+        let result = value.value().to_string();
+        Ok(json!({ "result": result, "variablesReference": 0 }))
+    }
+
+    // Returns `Some` when request resumes execution
+    fn request(
+        &mut self,
+        request: &Request,
+        session: &mut Session,
+    ) -> io::Result<Option<Resume>> {
+        let arguments = &request.arguments;
+        let (result, resume) = match request.command.as_str() {
+            "threads" => (Ok(threads()), None),
+            "stackTrace" => (Ok(self.stack_trace(session)), None),
+            "scopes" => (Ok(self.scopes(arguments, session)), None),
+            "variables" => (self.variables(arguments), None),
+            "evaluate" => (Self::evaluate(arguments, session), None),
+            "setBreakpoints" => {
+                (set_breakpoints(arguments, session.breakpoints), None)
+            }
+            "continue" => (
+                Ok(json!({ "allThreadsContinued": true })),
+                Some(Resume::Continue),
+            ),
+            "next" => (Ok(Value::Null), Some(Resume::StepOver)),
+            "stepIn" => (Ok(Value::Null), Some(Resume::StepIn)),
+            "stepOut" => (Ok(Value::Null), Some(Resume::StepOut)),
+            "terminate" => {
+                self.connection.borrow_mut().terminated = true;
+                (Ok(Value::Null), Some(Resume::Quit))
+            }
+            "disconnect" => {
+                let mut connection = self.connection.borrow_mut();
+                connection.terminated = true;
+                connection.disconnected = true;
+                (Ok(Value::Null), Some(Resume::Quit))
+            }
+            command => (Err(format!("Unsupported request {}", command)), None),
+        };
+        self.connection.borrow_mut().respond(request, result)?;
+        Ok(resume)
+    }
+}
+
+impl Frontend for Adapter {
+    fn stopped(&mut self, reason: StopReason, session: &mut Session) -> Resume {
+        self.references.clear();
+        let reason = match reason {
+            StopReason::Entry => "entry",
+            StopReason::Breakpoint => "breakpoint",
+            StopReason::Step => "step",
+        };
+        let stopped = self.connection.borrow_mut().event(
+            "stopped",
+            json!({
+                "reason": reason,
+                "threadId": THREAD_ID,
+                "allThreadsStopped": true,
+            }),
+        );
+        if stopped.is_err() {
+            return Resume::Quit;
+        }
+        loop {
+            let request = self.connection.borrow_mut().read();
+            let resume = match request {
+                Ok(Some(request)) => self.request(&request, session),
+                _ => return Resume::Quit,
+            };
+            match resume {
+                Ok(Some(resume)) => return resume,
+                Ok(None) => {}
+                Err(_) => return Resume::Quit,
+            }
+        }
+    }
+}
+
+fn threads() -> Value {
+    json!({ "threads": [{ "id": THREAD_ID, "name": "main" }] })
+}
+
+struct Launch {
+    program: PathBuf,
+    stop_on_entry: bool,
+    no_debug: bool,
+}
+
+/// Serves single debugging session, returns when client disconnects or
+/// input ends
+pub fn serve(
+    input: impl BufRead + 'static,
+    output: impl Write + 'static,
+) -> io::Result<()> {
+    let connection = Rc::new(RefCell::new(Connection {
+        input: Box::new(input),
+        output: Box::new(output),
+        seq: 0,
+        terminated: false,
+        disconnected: false,
+    }));
+    let mut breakpoints = BTreeSet::new();
+    let mut launch = None;
+    let mut configured = false;
+
+    // Configuration, until both launch and configurationDone arrive
+    while launch.is_none() || !configured {
+        let request = match connection.borrow_mut().read()? {
+            Some(request) => request,
+            None => return Ok(()),
+        };
+        let arguments = &request.arguments;
+        let result = match request.command.as_str() {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsTerminateRequest": true,
+            })),
+            "setBreakpoints" => set_breakpoints(arguments, &mut breakpoints),
+            "threads" => Ok(threads()),
+            "launch" => match arguments["program"].as_str() {
+                Some(program) if Path::new(program).is_file() => {
+                    launch = Some(Launch {
+                        program: program.into(),
+                        stop_on_entry: arguments["stopOnEntry"] == true,
+                        no_debug: arguments["noDebug"] == true,
+                    });
+                    Ok(Value::Null)
+                }
+                Some(program) => Err(format!("Can't open {}", program)),
+                None => Err("Missing program".into()),
+            },
+            "configurationDone" => {
+                configured = true;
+                Ok(Value::Null)
+            }
+            "disconnect" => {
+                connection.borrow_mut().respond(&request, Ok(Value::Null))?;
+                return Ok(());
+            }
+            command => Err(format!("Unsupported request {}", command)),
+        };
+        let mut connection = connection.borrow_mut();
+        connection.respond(&request, result)?;
+        if request.command == "initialize" {
+            connection.event("initialized", Value::Null)?;
+        }
+    }
+
+    let launch = launch.unwrap();
+    let events = OutputEvents {
+        connection: connection.clone(),
+        buffer: vec![],
+    };
+    let mut jlox = JLox::with_output(events, Color::Never);
+    if !launch.no_debug {
+        let name = launch.program.file_name().map(|n| n.to_string_lossy());
+        let source = json!({
+            "name": name,
+            "path": launch.program.display().to_string(),
+        });
+        let mut debugger = Debugger::new(Adapter {
+            connection: connection.clone(),
+            source,
+            references: vec![],
+        });
+        debugger.breakpoints = breakpoints;
+        debugger.stop_on_entry(launch.stop_on_entry);
+        jlox.set_debugger(debugger);
+    }
+    let result = jlox.run_file(&launch.program);
+    // Flushes remaining output
+    drop(jlox);
+
+    if connection.borrow().disconnected {
+        return Ok(());
+    }
+    let exit_code = match result {
+        Err(error) if !connection.borrow().terminated => {
+            send_output(
+                &connection,
+                "stderr",
+                format!("{}\n", error).as_bytes(),
+            )?;
+            1
+        }
+        _ => 0,
+    };
+    let mut connection = connection.borrow_mut();
+    connection.event("exited", json!({ "exitCode": exit_code }))?;
+    connection.event("terminated", Value::Null)?;
+
+    // Program is done, wait for client to disconnect
+    while let Some(request) = connection.read()? {
+        let result = match request.command.as_str() {
+            "disconnect" => Ok(Value::Null),
+            "threads" => Ok(json!({ "threads": [] })),
+            _ => Err("Program has terminated".into()),
+        };
+        connection.respond(&request, result)?;
+        if request.command == "disconnect" {
+            break;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[derive(Clone, Default)]
+    struct Buffer(Rc<RefCell<Vec<u8>>>);
+
+    impl Write for Buffer {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.borrow_mut().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // Sends all requests up front, adapter reads each when it's ready
+    fn session(requests: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for (seq, request) in requests.iter().enumerate() {
+            let mut request = request.clone();
+            request["seq"] = (seq + 1).into();
+            request["type"] = "request".into();
+            transport::write(&mut input, &request).unwrap();
+        }
+        let output = Buffer::default();
+        serve(Cursor::new(input), output.clone()).unwrap();
+
+        let output = output.0.take();
+        let mut output = &output[..];
+        let mut messages = vec![];
+        while let Some(message) = transport::read(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn response(messages: &[Value], request_seq: u64) -> &Value {
+        messages
+            .iter()
+            .find(|m| {
+                m["type"] == "response" && m["request_seq"] == request_seq
+            })
+            .unwrap()
+    }
+
+    #[test]
+    fn debugging_session() {
+        let program = std::env::temp_dir().join("lox_dap_session.lox");
+        fs::write(
+            &program,
+            "fun add(a, b) {\n  var sum = a + b;\n  return sum;\n}\n\
+             var x = add(1, 2);\nprint x;\n",
+        )
+        .unwrap();
+        let path = program.display().to_string();
+
+        let messages = session(&[
+            json!({ "command": "initialize", "arguments": {} }),
+            json!({ "command": "setBreakpoints", "arguments": {
+                "source": { "path": path },
+                "breakpoints": [{ "line": 2 }, { "line": 4 }],
+            }}),
+            json!({ "command": "launch", "arguments": { "program": path } }),
+            json!({ "command": "configurationDone" }),
+            json!({ "command": "stackTrace", "arguments": { "threadId": 1 } }),
+            json!({ "command": "scopes", "arguments": { "frameId": 2 } }),
+            json!({ "command": "variables", "arguments": {
+                "variablesReference": 1,
+            }}),
+            json!({ "command": "evaluate", "arguments": {
+                "expression": "a * 10", "frameId": 2,
+            }}),
+            json!({ "command": "next", "arguments": { "threadId": 1 } }),
+            json!({ "command": "evaluate", "arguments": {
+                "expression": "sum",
+            }}),
+            json!({ "command": "evaluate", "arguments": {
+                "expression": "missing",
+            }}),
+            json!({ "command": "continue", "arguments": { "threadId": 1 } }),
+            json!({ "command": "disconnect" }),
+        ]);
+        fs::remove_file(&program).ok();
+
+        assert_eq!(
+            messages[0]["body"]["supportsConfigurationDoneRequest"],
+            true
+        );
+        assert_eq!(messages[1]["event"], "initialized");
+        assert_eq!(
+            response(&messages, 2)["body"]["breakpoints"],
+            json!([
+                { "verified": true, "line": 2 },
+                { "verified": false, "line": 4 },
+            ])
+        );
+
+        let stopped: Vec<_> = messages
+            .iter()
+            .filter(|m| m["event"] == "stopped")
+            .map(|m| m["body"]["reason"].as_str().unwrap())
+            .collect();
+        assert_eq!(stopped, ["breakpoint", "step"]);
+
+        let frames = &response(&messages, 5)["body"]["stackFrames"];
+        assert_eq!(frames[0]["name"], "add");
+        assert_eq!(frames[0]["line"], 2);
+        assert_eq!(frames[0]["source"]["path"], path);
+        assert_eq!(frames[1]["name"], "script");
+        assert_eq!(frames[1]["line"], 5);
+
+        let scopes = &response(&messages, 6)["body"]["scopes"];
+        assert_eq!(scopes[0]["name"], "Locals");
+        assert_eq!(scopes[0]["variablesReference"], 1);
+        assert_eq!(
+            scopes.as_array().unwrap().last().unwrap()["name"],
+            "Globals"
+        );
+        assert_eq!(
+            response(&messages, 7)["body"]["variables"],
+            json!([
+                { "name": "a", "value": "1", "variablesReference": 0 },
+                { "name": "b", "value": "2", "variablesReference": 0 },
+            ])
+        );
+
+        assert_eq!(response(&messages, 8)["body"]["result"], "10");
+        assert_eq!(response(&messages, 10)["body"]["result"], "3");
+        assert_eq!(response(&messages, 11)["success"], false);
+
+        let output: String = messages
+            .iter()
+            .filter(|m| m["event"] == "output")
+            .map(|m| m["body"]["output"].as_str().unwrap())
+            .collect();
+        assert_eq!(output, "3\n");
+        let exited = messages.iter().find(|m| m["event"] == "exited").unwrap();
+        assert_eq!(exited["body"]["exitCode"], 0);
+        assert_eq!(response(&messages, 13)["success"], true);
+        assert_eq!(messages.last().unwrap()["command"], "disconnect");
+    }
+}
@@ -0,0 +1,361 @@
+use std::{collections::BTreeSet, io::Write};
+
+use super::{
+    environment::Environment,
+    errors::{ControlFlow, RuntimeResult},
+    interpreter::Interpreter,
+    parser::Parser,
+    tokenizer::Tokenizer,
+    types::ValueRef,
+};
+
+/// Function being executed, as shown in the call stack
+#[derive(Debug, Clone)]
+pub struct Frame {
+    pub function: String,
+    /// Line of the statement frame is executing, or calling from
+    pub line: u32,
+    pub(super) environment: Environment,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StopReason {
+    Entry,
+    Breakpoint,
+    Step,
+}
+
+/// How to continue after execution stopped
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Resume {
+    Continue,
+    StepIn,
+    StepOver,
+    StepOut,
+    Quit,
+}
+
+/// User interface of debugger, asked what to do whenever execution stops
+pub trait Frontend {
+    fn stopped(&mut self, reason: StopReason, session: &mut Session) -> Resume;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Mode {
+    Continue,
+    StepIn,
+    // Stop only in frames at most this deep
+    StepOver(usize),
+    StepOut(usize),
+}
+
+/// Stops execution on breakpoints and steps, it's driven by
+/// `Interpreter::visit_stmt`
+pub struct Debugger {
+    pub breakpoints: BTreeSet<u32>,
+    mode: Mode,
+    // Frame depth and line of last executed statement, so statements
+    // sharing a line only stop once per pass through it
+    last: Option<(usize, u32)>,
+    started: bool,
+    source: Vec<String>,
+    frontend: Box<dyn Frontend>,
+}
+
+impl Debugger {
+    /// Creates debugger that stops before first statement
+    pub fn new(frontend: impl Frontend + 'static) -> Self {
+        Self {
+            breakpoints: BTreeSet::new(),
+            mode: Mode::StepIn,
+            last: None,
+            started: false,
+            source: vec![],
+            frontend: Box::new(frontend),
+        }
+    }
+
+    pub fn stop_on_entry(&mut self, stop: bool) {
+        self.mode = if stop { Mode::StepIn } else { Mode::Continue };
+    }
+
+    /// Source of the script, so frontends can show it
+    pub fn set_source(&mut self, source: &str) {
+        self.source = source.lines().map(ToOwned::to_owned).collect();
+    }
+
+    /// Called when loop starts next iteration, so its first line counts as
+    /// new even when it's the same as the last one
+    pub(super) fn jumped_back(&mut self) {
+        self.last = None;
+    }
+
+    pub(super) fn statement(
+        &mut self,
+        interpreter: &mut Interpreter,
+        line: u32,
+    ) -> RuntimeResult<()> {
+        let depth = interpreter.frames.len();
+        if self.last.replace((depth, line)) == Some((depth, line)) {
+            return Ok(());
+        }
+        let entry = !std::mem::replace(&mut self.started, true);
+        let reason = match self.mode {
+            Mode::StepIn if entry => StopReason::Entry,
+            _ if self.breakpoints.contains(&line) => StopReason::Breakpoint,
+            Mode::StepIn => StopReason::Step,
+            Mode::StepOver(max) if depth <= max => StopReason::Step,
+            Mode::StepOut(max) if depth < max => StopReason::Step,
+            _ => return Ok(()),
+        };
+
+        let mut session = Session {
+            interpreter,
+            breakpoints: &mut self.breakpoints,
+            source: &self.source,
+        };
+        self.mode = match self.frontend.stopped(reason, &mut session) {
+            Resume::Continue => Mode::Continue,
+            Resume::StepIn => Mode::StepIn,
+            Resume::StepOver => Mode::StepOver(depth),
+            Resume::StepOut => Mode::StepOut(depth),
+            Resume::Quit => return Err(ControlFlow::Stop),
+        };
+        Ok(())
+    }
+}
+
+/// View of stopped program given to frontends
+pub struct Session<'s, 'a> {
+    interpreter: &'s mut Interpreter<'a>,
+    pub breakpoints: &'s mut BTreeSet<u32>,
+    source: &'s [String],
+}
+
+impl Session<'_, '_> {
+    /// Call stack, innermost frame last
+    pub fn frames(&self) -> &[Frame] {
+        &self.interpreter.frames
+    }
+
+    pub fn source_line(&self, line: u32) -> Option<&str> {
+        let index = (line as usize).checked_sub(1)?;
+        self.source.get(index).map(String::as_str)
+    }
+
+    /// Variables of every scope visible from `frame`, innermost first, with
+    /// globals last
+    pub fn scopes(&self, frame: usize) -> Vec<Vec<(String, ValueRef)>> {
+        let mut scopes = vec![];
+        let mut environment = self
+            .interpreter
+            .frames
+            .get(frame)
+            .map(|frame| frame.environment.clone());
+        while let Some(env) = environment {
+            scopes.push(env.variables());
+            environment = env.enclosing();
+        }
+        scopes
+    }
+
+    /// Evaluates expression in scope of `frame`, which can use and assign
+    /// any variable visible from it
+    pub fn evaluate(
+        &mut self,
+        source: &str,
+        frame: usize,
+    ) -> Result<ValueRef, String> {
+        let tokens = Tokenizer::new(source)
+            .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|e| e.to_string())?;
+        let expr = Parser::new(tokens)
+            .parse_expression()
+            .map_err(|e| e.to_string())?;
+        let environment = match self.interpreter.frames.get(frame) {
+            Some(frame) => frame.environment.clone(),
+            None => return Err(format!("No frame {}", frame)),
+        };
+        self.interpreter
+            .evaluate(&expr, environment)
+            .map_err(|e| e.into_error().to_string())
+    }
+}
+
+type Input = Box<dyn FnMut(&str) -> Option<String>>;
+
+/// Command line frontend
+pub struct Console {
+    input: Input,
+    output: Box<dyn Write>,
+    // Frame shown by `vars` and used by `print`, counted from innermost
+    selected: usize,
+}
+
+const HELP: &str = "\
+Commands:
+  break, b LINE      set breakpoint
+  delete, d LINE     remove breakpoint
+  breakpoints        list breakpoints
+  continue, c        run until next breakpoint
+  step, s            step into calls
+  next, n            step over calls
+  finish, f          step out of current function
+  backtrace, bt      print call stack
+  frame N            select frame N of call stack
+  vars, v            print variables visible from selected frame
+  print, p EXPR      evaluate expression in selected frame
+  list, l            show source around current line
+  quit, q            stop program";
+
+impl Console {
+    /// `input` is called with prompt and returns next line of input, or
+    /// `None` when there's none left
+    pub fn new(
+        input: impl FnMut(&str) -> Option<String> + 'static,
+        output: impl Write + 'static,
+    ) -> Self {
+        Self {
+            input: Box::new(input),
+            output: Box::new(output),
+            selected: 0,
+        }
+    }
+
+    fn location(&mut self, session: &Session, line: u32) {
+        let text = session.source_line(line).unwrap_or("").trim();
+        writeln!(self.output, "{:>4} | {}", line, text).ok();
+    }
+
+    // Returns `Some` when command resumes execution
+    fn command(&mut self, line: &str, session: &mut Session) -> Option<Resume> {
+        let (command, argument) = match line.trim().split_once(' ') {
+            Some((command, argument)) => (command, argument.trim()),
+            None => (line.trim(), ""),
+        };
+        let frames = session.frames().len();
+        let frame = frames.saturating_sub(self.selected + 1);
+        let out = &mut self.output;
+        match command {
+            "" => {}
+            "continue" | "c" => return Some(Resume::Continue),
+            "step" | "s" => return Some(Resume::StepIn),
+            "next" | "n" => return Some(Resume::StepOver),
+            "finish" | "f" => return Some(Resume::StepOut),
+            "quit" | "q" => return Some(Resume::Quit),
+            "break" | "b" | "delete" | "d" => match argument.parse() {
+                Ok(line) if command.starts_with('b') => {
+                    session.breakpoints.insert(line);
+                    writeln!(out, "Breakpoint at line {}", line).ok();
+                }
+                Ok(line) => {
+                    if !session.breakpoints.remove(&line) {
+                        writeln!(out, "No breakpoint at line {}", line).ok();
+                    }
+                }
+                Err(_) => {
+                    writeln!(out, "Expected line number").ok();
+                }
+            },
+            "breakpoints" => {
+                for line in session.breakpoints.iter() {
+                    writeln!(out, "line {}", line).ok();
+                }
+            }
+            "backtrace" | "bt" => {
+                for (i, frame) in session.frames().iter().rev().enumerate() {
+                    let marker = if i == self.selected { '>' } else { ' ' };
+                    writeln!(
+                        out,
+                        "{} #{} [line {}] in {}",
+                        marker, i, frame.line, frame.function
+                    )
+                    .ok();
+                }
+            }
+            "frame" => match argument.parse() {
+                Ok(selected) if selected < frames => {
+                    self.selected = selected;
+                    let line = session.frames()[frames - selected - 1].line;
+                    self.location(session, line);
+                }
+                _ => {
+                    writeln!(out, "Expected frame number below {}", frames)
+                        .ok();
+                }
+            },
+            "vars" | "v" => {
+                let scopes = session.scopes(frame);
+                let globals = scopes.len().saturating_sub(1);
+                for (i, scope) in scopes.iter().enumerate() {
+                    let name = if i == globals { "globals" } else { "scope" };
+                    writeln!(out, "{} {}:", name, i).ok();
+                    for (name, value) in scope {
+                        writeln!(out, "  {} = {}", name, value.value()).ok();
+                    }
+                }
+            }
+            "print" | "p" => match session.evaluate(argument, frame) {
+                Ok(value) => {
+                    writeln!(out, "{}", value.value()).ok();
+                }
+                Err(error) => {
+                    writeln!(out, "{}", error).ok();
+                }
+            },
+            "list" | "l" => {
+                let line = session.frames()[frame].line;
+                for line in line.saturating_sub(2).max(1)..=line + 2 {
+                    let text = match session.source_line(line) {
+                        Some(text) => text,
+                        None => break,
+                    };
+                    let marker = if line == session.frames()[frame].line {
+                        '>'
+                    } else {
+                        ' '
+                    };
+                    writeln!(out, "{} {:>4} | {}", marker, line, text).ok();
+                }
+            }
+            "help" | "h" => {
+                writeln!(out, "{}", HELP).ok();
+            }
+            _ => {
+                writeln!(out, "Unknown command {:?}, try `help`", command).ok();
+            }
+        }
+        None
+    }
+}
+
+impl Frontend for Console {
+    fn stopped(&mut self, reason: StopReason, session: &mut Session) -> Resume {
+        self.selected = 0;
+        let frame = session.frames().last().cloned();
+        if let Some(frame) = frame {
+            let reason = match reason {
+                StopReason::Entry => "entry",
+                StopReason::Breakpoint => "breakpoint",
+                StopReason::Step => "step",
+            };
+            writeln!(
+                self.output,
+                "Stopped at {} in {}",
+                reason, frame.function
+            )
+            .ok();
+            self.location(session, frame.line);
+        }
+        loop {
+            let line = match (self.input)("(debug) ") {
+                Some(line) => line,
+                None => return Resume::Quit,
+            };
+            if let Some(resume) = self.command(&line, session) {
+                return resume;
+            }
+        }
+    }
+}
@@ -0,0 +1,267 @@
+use std::{fmt::Write, io::IsTerminal, str::FromStr};
+
+use super::{
+    errors::{Diagnostic, GenericError, Note},
+    lint::Warning,
+    tokens::{Token, TokenType},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Color {
+    #[default]
+    Auto,
+    Always,
+    Never,
+}
+
+impl FromStr for Color {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Self::Auto),
+            "always" => Ok(Self::Always),
+            "never" => Ok(Self::Never),
+            _ => {
+                Err("Unsupported color choice\nAvailable: auto, always, never")
+            }
+        }
+    }
+}
+
+/// Wraps text in terminal escapes, if colors are enabled
+#[derive(Debug, Clone, Copy)]
+pub struct Palette {
+    enabled: bool,
+}
+
+impl Palette {
+    pub fn new(color: Color) -> Self {
+        let enabled = match color {
+            Color::Auto => std::io::stderr().is_terminal(),
+            Color::Always => true,
+            Color::Never => false,
+        };
+        Self { enabled }
+    }
+
+    fn paint(&self, code: &str, text: &str) -> String {
+        if self.enabled {
+            format!("\x1b[{}m{}\x1b[m", code, text)
+        } else {
+            text.to_owned()
+        }
+    }
+
+    pub fn red(&self, text: &str) -> String {
+        self.paint("31", text)
+    }
+
+    pub fn green(&self, text: &str) -> String {
+        self.paint("32", text)
+    }
+
+    pub fn yellow(&self, text: &str) -> String {
+        self.paint("33", text)
+    }
+
+    pub fn blue(&self, text: &str) -> String {
+        self.paint("34", text)
+    }
+
+    pub fn bold(&self, text: &str) -> String {
+        self.paint("1", text)
+    }
+}
+
+/// Renders errors together with the source line they point to:
+///
+/// ```text
+/// Resolve Error at 'a': Already variable with this name in this scope.
+///  --> test.lox:3:9
+///   |
+/// 3 |     var a = 2;
+///   |         ^
+/// note: variable declared here
+///  --> test.lox:2:9
+///   |
+/// 2 |     var a = 1;
+///   |         ^
+/// ```
+pub struct Renderer<'s> {
+    source: &'s str,
+    file: Option<&'s str>,
+    palette: Palette,
+}
+
+impl<'s> Renderer<'s> {
+    pub fn new(
+        source: &'s str,
+        file: Option<&'s str>,
+        palette: Palette,
+    ) -> Self {
+        Self {
+            source,
+            file,
+            palette,
+        }
+    }
+
+    pub fn render(&self, diagnostic: &impl Diagnostic) -> String {
+        let GenericError(token, message, notes) = diagnostic.error();
+        let mut out = String::new();
+
+        let header = match token {
+            Some(token) => {
+                let lexeme = match token.type_ {
+                    TokenType::Eof => "end",
+                    _ => &token.lexeme,
+                };
+                format!("{} Error at '{}'", diagnostic.kind(), lexeme)
+            }
+            None => format!("{} Error", diagnostic.kind()),
+        };
+        writeln!(
+            out,
+            "{}{}",
+            self.palette.red(&self.palette.bold(&header)),
+            self.palette.bold(&format!(": {}", message))
+        )
+        .unwrap();
+        if let Some(token) = token {
+            self.snippet(&mut out, token, |text| self.palette.red(text));
+        }
+
+        self.notes(&mut out, notes);
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    /// Renders lint warning, with its name in place of error kind
+    pub fn render_warning(&self, warning: &Warning) -> String {
+        let mut out = String::new();
+        let header = format!("warning[{}]", warning.lint);
+        writeln!(
+            out,
+            "{}{}",
+            self.palette.yellow(&self.palette.bold(&header)),
+            self.palette.bold(&format!(": {}", warning.message))
+        )
+        .unwrap();
+        self.snippet(&mut out, &warning.token, |text| {
+            self.palette.yellow(text)
+        });
+        self.notes(&mut out, &warning.notes);
+        out.truncate(out.trim_end().len());
+        out
+    }
+
+    fn notes(&self, out: &mut String, notes: &[Note]) {
+        for note in notes {
+            writeln!(
+                out,
+                "{}: {}",
+                self.palette.blue(&self.palette.bold("note")),
+                note.message
+            )
+            .unwrap();
+            if let Some(token) = &note.token {
+                self.snippet(out, token, |text| self.palette.blue(text));
+            }
+        }
+    }
+
+    fn snippet(
+        &self,
+        out: &mut String,
+        token: &Token,
+        paint: impl Fn(&str) -> String,
+    ) {
+        let (start, end) = token.span;
+        let line_start = self.source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = self.source[start..]
+            .find('\n')
+            .map_or(self.source.len(), |i| start + i);
+        let line = self.source[line_start..line_end].trim_end_matches('\r');
+
+        // Token positions are recorded at their end, so recompute the start
+        let line_no =
+            (self.source[..start].matches('\n').count() + 1).to_string();
+        let column = self.source[line_start..start].chars().count() + 1;
+
+        // Keep tabs, so caret lines up with the source line
+        let indent: String = self.source[line_start..start]
+            .chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        let width =
+            self.source[start..end.min(line_end)].chars().count().max(1);
+
+        let gutter = " ".repeat(line_no.len());
+        let bar = self.palette.blue("|");
+        writeln!(
+            out,
+            "{}{} {}:{}:{}",
+            gutter,
+            self.palette.blue("-->"),
+            self.file.unwrap_or("<input>"),
+            line_no,
+            column
+        )
+        .unwrap();
+        writeln!(out, "{} {}", gutter, bar).unwrap();
+        writeln!(out, "{} {} {}", self.palette.blue(&line_no), bar, line)
+            .unwrap();
+        writeln!(
+            out,
+            "{} {} {}{}",
+            gutter,
+            bar,
+            indent,
+            paint(&"^".repeat(width))
+        )
+        .unwrap();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{
+            errors::ResolveError, interpreter::Interpreter, parser::Parser,
+            resolver::Resolver, tokenizer::Tokenizer,
+        },
+        *,
+    };
+
+    #[test]
+    fn resolver_note() {
+        let source = "{\n    var a = 1;\n\tvar a = 2;\n}";
+        let tokens = Tokenizer::new(source)
+            .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
+            .collect::<Result<Vec<_>, _>>()
+            .unwrap();
+        let ast = Parser::new(tokens).parse().unwrap();
+        let mut interpreter = Interpreter::new(vec![]);
+        let error: ResolveError = Resolver::new(&mut interpreter.locals)
+            .resolve(&ast)
+            .unwrap_err();
+
+        let renderer =
+            Renderer::new(source, Some("test.lox"), Palette::new(Color::Never));
+        assert_eq!(
+            renderer.render(&error),
+            "Resolve Error at 'a': \
+                Already variable with this name in this scope.
+ --> test.lox:3:6
+  |
+3 | \tvar a = 2;
+  | \t    ^
+note: variable declared here
+ --> test.lox:2:9
+  |
+2 |     var a = 1;
+  |         ^"
+        );
+    }
+}
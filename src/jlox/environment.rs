@@ -2,7 +2,10 @@ use std::{
     collections::BTreeMap,
     fmt,
     hash::{Hash, Hasher},
-    sync::{Arc, RwLock, RwLockReadGuard, RwLockWriteGuard},
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc, RwLock, RwLockReadGuard, RwLockWriteGuard,
+    },
 };
 
 use super::{
@@ -11,17 +14,18 @@ use super::{
     types::ValueRef,
 };
 
-#[derive(Clone)]
+#[derive(Clone, Default)]
 pub struct Environment {
-    inner: Arc<RwLock<Inner>>,
+    inner: Arc<Inner>,
 }
 
 impl fmt::Debug for Environment {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        let inner = self.inner.read().unwrap();
+        let slots = self.read();
         f.debug_struct("Environment")
-            .field("enclosing", &inner.enclosing)
-            .field("values", &inner.values)
+            .field("enclosing", &self.inner.enclosing)
+            .field("names", &slots.names)
+            .field("values", &slots.values)
             .finish()
     }
 }
@@ -36,63 +40,102 @@ impl Eq for Environment {}
 
 impl Hash for Environment {
     fn hash<H: Hasher>(&self, state: &mut H) {
+        if let Some(en) = &self.inner.enclosing {
+            en.hash(state);
+        }
         self.read().hash(state);
     }
 }
 
 #[derive(Default)]
 struct Inner {
+    // Never changes, so walking the chain doesn't take any locks
     enclosing: Option<Environment>,
-    values: BTreeMap<String, ValueRef>,
+    // Whether locals keep their names, which only debugger looks up
+    named: AtomicBool,
+    slots: RwLock<Slots>,
 }
 
-impl Hash for Inner {
-    fn hash<H: Hasher>(&self, state: &mut H) {
-        if let Some(en) = &self.enclosing {
-            en.hash(state);
-        }
-        self.values.hash(state);
-    }
+/// Locals live in slots assigned by the resolver, globals are looked up by
+/// name
+#[derive(Default, Hash)]
+struct Slots {
+    values: Vec<ValueRef>,
+    // Names of `values`, for globals and for locals of named environments
+    names: Vec<String>,
+    // Slots by name, only in the global environment where variables can be
+    // redefined
+    globals: BTreeMap<String, usize>,
 }
 
-impl Inner {
-    fn new(enclosing: Environment) -> Self {
+impl Environment {
+    pub fn enclose(&self) -> Self {
+        let named = self.inner.named.load(Ordering::Relaxed);
         Self {
-            enclosing: Some(enclosing),
-            ..Default::default()
+            inner: Arc::new(Inner {
+                enclosing: Some(self.clone()),
+                named: AtomicBool::new(named),
+                slots: Default::default(),
+            }),
         }
     }
-}
 
-impl Default for Environment {
-    fn default() -> Self {
-        Self {
-            inner: Default::default(),
-        }
+    pub fn enclosing(&self) -> Option<Self> {
+        self.inner.enclosing.clone()
     }
-}
 
-impl Environment {
-    pub fn enclose(&self) -> Self {
-        Self {
-            inner: Arc::new(RwLock::new(Inner::new(self.clone()))),
-        }
+    /// Makes environments enclosed from now on keep names of locals, so
+    /// debugger can list and evaluate them
+    pub fn keep_names(&self) {
+        self.inner.named.store(true, Ordering::Relaxed);
     }
 
-    pub fn enclosing(&self) -> Option<Self> {
-        self.read().enclosing.as_ref().cloned()
+    /// Variables defined directly in this environment, sorted by name, only
+    /// globals and locals of named environments are listed
+    pub fn variables(&self) -> Vec<(String, ValueRef)> {
+        let slots = self.read();
+        let mut variables: Vec<_> = slots
+            .names
+            .iter()
+            .cloned()
+            .zip(slots.values.iter().cloned())
+            .collect();
+        variables.sort_by(|a, b| a.0.cmp(&b.0));
+        variables
     }
 
-    fn read(&self) -> RwLockReadGuard<Inner> {
-        self.inner.try_read().unwrap()
+    fn read(&self) -> RwLockReadGuard<'_, Slots> {
+        self.inner.slots.try_read().unwrap()
     }
 
-    fn write(&mut self) -> RwLockWriteGuard<Inner> {
-        self.inner.try_write().unwrap()
+    fn write(&self) -> RwLockWriteGuard<'_, Slots> {
+        self.inner.slots.try_write().unwrap()
     }
 
-    pub fn define(&mut self, name: String, value: ValueRef) {
-        self.write().values.insert(name, value);
+    // Slot of variable looked up by name, `None` for locals without names
+    fn slot(&self, slots: &Slots, name: &str) -> Option<usize> {
+        match self.inner.enclosing {
+            None => slots.globals.get(name).copied(),
+            Some(_) => slots.names.iter().rposition(|n| n == name),
+        }
+    }
+
+    /// Defines variable in next free slot, which is the one resolver gave
+    /// it, redefined globals reuse their slot
+    pub fn define(&mut self, name: &str, value: ValueRef) {
+        let mut slots = self.write();
+        if self.inner.enclosing.is_none() {
+            if let Some(&slot) = slots.globals.get(name) {
+                slots.values[slot] = value;
+                return;
+            }
+            let slot = slots.values.len();
+            slots.globals.insert(name.to_owned(), slot);
+            slots.names.push(name.to_owned());
+        } else if self.inner.named.load(Ordering::Relaxed) {
+            slots.names.push(name.to_owned());
+        }
+        slots.values.push(value);
     }
 
     pub fn assign(
@@ -100,12 +143,12 @@ impl Environment {
         name: &Token,
         value: ValueRef,
     ) -> RuntimeResult<()> {
-        let mut write = self.write();
-        if let Some(v) = write.values.get_mut(&name.lexeme) {
-            *v = value;
+        let mut slots = self.write();
+        if let Some(slot) = self.slot(&slots, &name.lexeme) {
+            slots.values[slot] = value;
             Ok(())
-        } else if let Some(ref mut en) = write.enclosing {
-            en.assign(name, value)
+        } else if let Some(en) = &self.inner.enclosing {
+            en.clone().assign(name, value)
         } else {
             Err(RuntimeError::wrapped(
                 Some(name),
@@ -117,30 +160,29 @@ impl Environment {
     pub fn assign_at(
         &self,
         distance: usize,
+        slot: usize,
         name: &Token,
         value: ValueRef,
     ) -> RuntimeResult<()> {
-        *self
-            .ancestor(distance)
-            .ok_or_else(|| {
-                RuntimeError::wrapped(Some(name), "Non-existent env ancestor")
-            })?
-            .write()
-            .values
-            .get_mut(&name.lexeme)
-            .ok_or_else(|| {
-                RuntimeError::wrapped(
-                    Some(name),
-                    format!("Missing variable at {} dist", distance),
-                )
-            })? = value;
+        let env = self.ancestor(distance).ok_or_else(|| {
+            RuntimeError::wrapped(Some(name), "Non-existent env ancestor")
+        })?;
+        let mut slots = env.write();
+        let variable = slots.values.get_mut(slot).ok_or_else(|| {
+            RuntimeError::wrapped(
+                Some(name),
+                format!("Missing variable at {} dist", distance),
+            )
+        })?;
+        *variable = value;
         Ok(())
     }
 
     pub fn get(&self, name: &Token) -> RuntimeResult<ValueRef> {
-        if let Some(value) = self.read().values.get(&name.lexeme) {
-            Ok(value.clone())
-        } else if let Some(en) = &self.read().enclosing {
+        let slots = self.read();
+        if let Some(slot) = self.slot(&slots, &name.lexeme) {
+            Ok(slots.values[slot].clone())
+        } else if let Some(en) = &self.inner.enclosing {
             en.get(name)
         } else {
             Err(RuntimeError::wrapped(
@@ -153,23 +195,7 @@ impl Environment {
     pub fn get_at(
         &self,
         distance: usize,
-        name: &Token,
-    ) -> RuntimeResult<ValueRef> {
-        self.get_at_raw(distance, &name.lexeme, Some(name))
-    }
-
-    pub fn get_at_str(
-        &self,
-        distance: usize,
-        name: &str,
-    ) -> RuntimeResult<ValueRef> {
-        self.get_at_raw(distance, name, None)
-    }
-
-    fn get_at_raw(
-        &self,
-        distance: usize,
-        name: &str,
+        slot: usize,
         token: Option<&Token>,
     ) -> RuntimeResult<ValueRef> {
         self.ancestor(distance)
@@ -178,21 +204,20 @@ impl Environment {
             })?
             .read()
             .values
-            .get(name)
+            .get(slot)
+            .cloned()
             .ok_or_else(|| {
                 RuntimeError::wrapped(
                     token,
                     format!("Missing variable at {} dist", distance),
                 )
             })
-            .map(Clone::clone)
     }
 
-    fn ancestor(&self, distance: usize) -> Option<Environment> {
-        let mut env = self.clone();
+    fn ancestor(&self, distance: usize) -> Option<&Environment> {
+        let mut env = self;
         for _ in 0..distance {
-            let environment = env.read().enclosing.as_ref()?.clone();
-            env = environment;
+            env = env.inner.enclosing.as_ref()?;
         }
         Some(env)
     }
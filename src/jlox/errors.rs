@@ -1,10 +1,19 @@
+use std::fmt;
+
 use super::{
     tokens::{Token, TokenType},
-    types::ValueRef,
+    types::{Value, ValueRef},
 };
 
 #[derive(Debug)]
-pub struct GenericError(pub Option<Token>, pub String);
+pub struct GenericError(pub Option<Token>, pub String, pub Vec<Note>);
+
+/// Additional message attached to an error, e.g. pointing to a declaration
+#[derive(Debug)]
+pub struct Note {
+    pub token: Option<Token>,
+    pub message: String,
+}
 
 impl GenericError {
     fn to_string(&self, kind: &'static str) -> String {
@@ -30,36 +39,127 @@ pub enum ControlFlow {
     Return(ValueRef),
     #[error("Unexpected break")]
     Break,
+    /// Thrown value, along with error reported if it's never caught
+    #[error("Uncaught exception")]
+    Throw(ValueRef, RuntimeError),
     #[error("{0}")]
     Error(RuntimeError),
+    /// Debugger quit, unwinds without running anything else
+    #[error("Stopped by debugger")]
+    Stop,
 }
 
 #[derive(Debug, thiserror::Error)]
-#[error("{}", _0.to_string("Runtime "))]
-pub struct RuntimeError(GenericError);
+#[error("{}", .error.to_string("Runtime "))]
+pub struct RuntimeError {
+    error: Box<GenericError>,
+    trace: Vec<TraceFrame>,
+    // Position in the function that error is currently propagating through
+    pos: Option<(u32, u32)>,
+}
 
 pub type RuntimeResult<T> = Result<T, ControlFlow>;
 
 impl RuntimeError {
+    fn new(token: Option<Token>, message: String) -> Self {
+        let pos = token.as_ref().map(|t| t.start);
+        Self {
+            error: Box::new(GenericError(token, message, vec![])),
+            trace: vec![],
+            pos,
+        }
+    }
+
     pub fn wrapped<S: Into<String>>(
         token: Option<&Token>,
         message: S,
     ) -> ControlFlow {
-        ControlFlow::Error(Self(GenericError(token.cloned(), message.into())))
+        ControlFlow::Error(Self::new(token.cloned(), message.into()))
+    }
+
+    /// Error reported when `value`, thrown by `keyword`, is never caught
+    pub(super) fn uncaught(keyword: &Token, value: &ValueRef) -> Self {
+        let field = match &*value.get() {
+            Value::Instance(instance) => instance.field("message"),
+            _ => None,
+        };
+        let message = field.unwrap_or_else(|| value.clone());
+        Self::new(
+            Some(keyword.clone()),
+            format!("Uncaught exception: {}", message.value()),
+        )
+    }
+
+    pub fn message(&self) -> &str {
+        &self.error.1
+    }
+
+    pub fn token(&self) -> Option<&Token> {
+        self.error.0.as_ref()
+    }
+
+    /// Call stack at the time of the error, innermost frame first
+    pub fn trace(&self) -> &[TraceFrame] {
+        &self.trace
+    }
+
+    pub fn traceback(&self) -> String {
+        self.trace
+            .iter()
+            .map(ToString::to_string)
+            .collect::<Vec<_>>()
+            .join("\n")
+    }
+
+    pub(super) fn set_call_site(&mut self, right_paren: &Token) {
+        self.pos = Some(right_paren.start);
+    }
+
+    pub(super) fn push_frame(&mut self, function: &str, file: Option<&str>) {
+        self.trace.push(TraceFrame {
+            function: function.to_owned(),
+            file: file.map(ToOwned::to_owned),
+            pos: self.pos.unwrap_or_default(),
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct TraceFrame {
+    pub function: String,
+    pub file: Option<String>,
+    /// Start of the call, or of the token that failed, in the frame
+    pub pos: (u32, u32),
+}
+
+impl fmt::Display for TraceFrame {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "[line {}:{}] in ", self.pos.0, self.pos.1)?;
+        match self.function.as_str() {
+            "script" => write!(f, "script")?,
+            name => write!(f, "{}()", name)?,
+        }
+        if let Some(file) = &self.file {
+            write!(f, " ({})", file)?;
+        }
+        Ok(())
     }
 }
 
 impl ControlFlow {
     pub fn into_error(self) -> RuntimeError {
         match self {
-            ControlFlow::Return(value) => RuntimeError(GenericError(
+            ControlFlow::Return(value) => RuntimeError::new(
                 None,
                 format!("Unexpected return: {}", value.value()),
-            )),
+            ),
             ControlFlow::Break => {
-                RuntimeError(GenericError(None, "Unexpected break".to_string()))
+                RuntimeError::new(None, "Unexpected break".to_string())
+            }
+            ControlFlow::Throw(_, err) | ControlFlow::Error(err) => err,
+            ControlFlow::Stop => {
+                RuntimeError::new(None, "Stopped by debugger".to_string())
             }
-            ControlFlow::Error(err) => err,
         }
     }
 }
@@ -74,11 +174,11 @@ pub enum TokenizerError {
 
 #[derive(Debug, thiserror::Error)]
 #[error("{}", self.0.to_string("Parse "))]
-pub struct ParseError(pub GenericError);
+pub struct ParseError(pub Box<GenericError>);
 
 impl ParseError {
     pub fn new(token: Option<Token>, msg: String) -> Self {
-        Self(GenericError(token, msg))
+        Self(Box::new(GenericError(token, msg, vec![])))
     }
 }
 
@@ -86,12 +186,100 @@ pub type ParseResult<T> = Result<T, ParseError>;
 
 #[derive(Debug, thiserror::Error)]
 #[error("{}", self.0.to_string("Resolve "))]
-pub struct ResolveError(pub GenericError);
+pub struct ResolveError(pub Box<GenericError>);
 
 impl ResolveError {
     pub fn new(token: Option<&Token>, msg: impl Into<String>) -> Self {
-        Self(GenericError(token.cloned(), msg.into()))
+        Self(Box::new(GenericError(token.cloned(), msg.into(), vec![])))
+    }
+
+    pub fn with_note(
+        mut self,
+        token: Option<&Token>,
+        message: impl Into<String>,
+    ) -> Self {
+        self.0 .2.push(Note {
+            token: token.cloned(),
+            message: message.into(),
+        });
+        self
     }
 }
 
 pub type ResolveResult<T> = Result<T, ResolveError>;
+
+#[derive(Debug, thiserror::Error)]
+#[error("{}", self.0.to_string("Type "))]
+pub struct TypeError(pub Box<GenericError>);
+
+impl TypeError {
+    pub fn new(token: Option<&Token>, msg: impl Into<String>) -> Self {
+        Self(Box::new(GenericError(token.cloned(), msg.into(), vec![])))
+    }
+
+    pub fn with_note(
+        mut self,
+        token: Option<&Token>,
+        message: impl Into<String>,
+    ) -> Self {
+        self.0 .2.push(Note {
+            token: token.cloned(),
+            message: message.into(),
+        });
+        self
+    }
+}
+
+#[derive(Debug, thiserror::Error)]
+pub enum FormatError {
+    #[error(transparent)]
+    Tokenizer(#[from] TokenizerError),
+    #[error(transparent)]
+    Parse(#[from] ParseError),
+}
+
+/// Errors that can be rendered by [`Renderer`](super::diagnostics::Renderer)
+pub trait Diagnostic {
+    fn kind(&self) -> &'static str;
+    fn error(&self) -> &GenericError;
+}
+
+impl Diagnostic for ParseError {
+    fn kind(&self) -> &'static str {
+        "Parse"
+    }
+
+    fn error(&self) -> &GenericError {
+        &self.0
+    }
+}
+
+impl Diagnostic for ResolveError {
+    fn kind(&self) -> &'static str {
+        "Resolve"
+    }
+
+    fn error(&self) -> &GenericError {
+        &self.0
+    }
+}
+
+impl Diagnostic for TypeError {
+    fn kind(&self) -> &'static str {
+        "Type"
+    }
+
+    fn error(&self) -> &GenericError {
+        &self.0
+    }
+}
+
+impl Diagnostic for RuntimeError {
+    fn kind(&self) -> &'static str {
+        "Runtime"
+    }
+
+    fn error(&self) -> &GenericError {
+        &self.error
+    }
+}
@@ -0,0 +1,566 @@
+use super::{
+    ast::{Expr, Function, Stmt},
+    errors::FormatError,
+    parser::Parser,
+    tokenizer::Tokenizer,
+    tokens::{Token, TokenType},
+};
+
+const INDENT: &str = "  ";
+
+/// Formats script in canonical style, keeping its comments
+pub fn format(source: &str) -> Result<String, FormatError> {
+    let mut tokens = vec![];
+    for token in Tokenizer::new(source) {
+        let token = token?;
+        if token.type_ != TokenType::Whitespace {
+            tokens.push(token);
+        }
+    }
+    let code = tokens.iter().filter(|t| !t.can_skip()).cloned().collect();
+    let program = Parser::new(code).parse()?;
+
+    let mut printer = Printer {
+        tokens,
+        next: 0,
+        line: 0,
+        out: String::new(),
+        indent: 0,
+        continued: false,
+    };
+    printer.statements(&program);
+    printer.comments();
+    printer.newline();
+    Ok(printer.out)
+}
+
+/// Prints tree in the same order as source, stepping over source tokens as
+/// it goes, so comments between them can be put back in place
+struct Printer {
+    // Source tokens, without whitespace
+    tokens: Vec<Token>,
+    next: usize,
+    // Line of last printed token
+    line: u32,
+    out: String,
+    indent: usize,
+    // Whether a comment already broke current statement, indenting the rest
+    continued: bool,
+}
+
+impl Printer {
+    fn peek(&self) -> &Token {
+        &self.tokens[self.next]
+    }
+
+    fn at_line_start(&self) -> bool {
+        self.out.is_empty() || self.out.ends_with('\n')
+    }
+
+    fn write(&mut self, text: &str) {
+        if self.at_line_start() {
+            self.out.push_str(&INDENT.repeat(self.indent));
+        }
+        self.out.push_str(text);
+    }
+
+    fn space(&mut self) {
+        if !self.at_line_start() {
+            self.out.push(' ');
+        }
+    }
+
+    fn newline(&mut self) {
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    // Leaves one empty line, except at start of file or block
+    fn blank_line(&mut self) {
+        self.newline();
+        let start = self.out.is_empty() || self.out.ends_with("{\n");
+        if !start && !self.out.ends_with("\n\n") {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints comments found before next token
+    fn comments(&mut self) {
+        while self.peek().type_ == TokenType::Comment {
+            let comment = self.peek().clone();
+            self.next += 1;
+            if comment.pos.0 == self.line && !self.out.is_empty() {
+                // Stays on the line of code it follows
+                let code = self.out.trim_end().len();
+                self.out.truncate(code);
+                self.out.push(' ');
+                self.out.push_str(&comment.lexeme);
+            } else {
+                let code = self.out.trim_end_matches(' ').len();
+                self.out.truncate(code);
+                if comment.pos.0 > self.line + 1 {
+                    self.blank_line();
+                } else {
+                    self.newline();
+                }
+                self.write(&comment.lexeme);
+            }
+            self.out.push('\n');
+            self.line = comment.pos.0;
+        }
+    }
+
+    /// Prints `text` in place of next source token
+    fn token(&mut self, text: &str) {
+        if self.peek().type_ == TokenType::Comment && !self.continued {
+            self.continued = true;
+            self.indent += 1;
+        }
+        self.comments();
+        self.line = self.peek().pos.0;
+        if self.peek().type_ != TokenType::Eof {
+            self.next += 1;
+        }
+        self.write(text);
+    }
+
+    // Starts statement or method on a new line, keeping one empty line if
+    // source had any
+    fn item(&mut self) {
+        self.comments();
+        if self.peek().pos.0 > self.line + 1 {
+            self.blank_line();
+        } else {
+            self.newline();
+        }
+    }
+
+    // Prints statement or method, so lines it continues on after a comment
+    // are indented one level more than its first one
+    fn scoped(&mut self, print: impl FnOnce(&mut Self)) {
+        let indent = self.indent;
+        let continued = std::mem::take(&mut self.continued);
+        print(self);
+        self.indent = indent;
+        self.continued = continued;
+    }
+
+    fn braces(&mut self, empty: bool, body: impl FnOnce(&mut Self)) {
+        // Brace ends continuation, comment before it doesn't start one
+        self.comments();
+        self.token("{");
+        if std::mem::take(&mut self.continued) {
+            self.indent -= 1;
+        }
+        if empty && self.peek().type_ != TokenType::Comment {
+            self.token("}");
+            return;
+        }
+        self.indent += 1;
+        body(self);
+        self.comments();
+        self.indent -= 1;
+        self.newline();
+        self.token("}");
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        for stmt in statements {
+            self.item();
+            self.statement(stmt);
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.braces(statements.is_empty(), |p| p.statements(statements));
+    }
+
+    // Body of loop or branch of `if`
+    fn body(&mut self, stmt: &Stmt) {
+        self.space();
+        self.statement(stmt);
+    }
+
+    // Type written after declared name
+    fn annotation(&mut self, type_: &Option<Token>) {
+        if let Some(type_) = type_ {
+            self.token(":");
+            self.space();
+            self.token(&type_.lexeme);
+        }
+    }
+
+    fn function(&mut self, function: &Function) {
+        self.token(&function.name.lexeme);
+        self.token("(");
+        let params = function.params.iter().zip(&function.param_types);
+        for (i, (param, type_)) in params.enumerate() {
+            if i > 0 {
+                self.token(",");
+                self.space();
+            }
+            self.token(&param.lexeme);
+            self.annotation(type_);
+        }
+        self.token(")");
+        self.annotation(&function.return_type);
+        self.space();
+        self.block(&function.body);
+    }
+
+    fn statement(&mut self, stmt: &Stmt) {
+        self.scoped(|p| p.clauses(stmt));
+    }
+
+    fn clauses(&mut self, stmt: &Stmt) {
+        // Parser turns `for` into `while`, source tells them apart
+        if self.peek().type_ == TokenType::For {
+            return self.for_loop(stmt);
+        }
+        match stmt {
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::Class {
+                name,
+                superclass,
+                methods,
+            } => {
+                self.token("class");
+                self.space();
+                self.token(&name.lexeme);
+                if let Some(superclass) = superclass {
+                    self.space();
+                    self.token("<");
+                    self.space();
+                    self.expr(superclass);
+                }
+                self.space();
+                self.braces(methods.is_empty(), |p| {
+                    for method in methods {
+                        p.item();
+                        p.scoped(|p| p.function(method));
+                    }
+                });
+            }
+            Stmt::Expression { expr } => {
+                self.expr(expr);
+                self.token(";");
+            }
+            Stmt::Function(function) => {
+                self.token("fun");
+                self.space();
+                self.function(function);
+            }
+            Stmt::If {
+                condition,
+                then_branch,
+                else_branch,
+            } => {
+                self.token("if");
+                self.space();
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                self.body(then_branch);
+                if let Some(else_branch) = else_branch {
+                    // Comment after a branch doesn't make next one continue it
+                    self.comments();
+                    if self.out.ends_with('}') {
+                        self.space();
+                    } else {
+                        self.newline();
+                    }
+                    self.token("else");
+                    self.body(else_branch);
+                }
+            }
+            Stmt::PrintStmt { expr } => {
+                self.token("print");
+                self.space();
+                self.expr(expr);
+                self.token(";");
+            }
+            Stmt::Return { value, .. } => {
+                self.token("return");
+                if let Some(value) = value {
+                    self.space();
+                    self.expr(value);
+                }
+                self.token(";");
+            }
+            Stmt::Throw { value, .. } => {
+                self.token("throw");
+                self.space();
+                self.expr(value);
+                self.token(";");
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.token("try");
+                self.space();
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.comments();
+                    self.space();
+                    self.token("catch");
+                    self.space();
+                    self.token("(");
+                    self.token(&catch.name.lexeme);
+                    self.token(")");
+                    self.space();
+                    self.block(&catch.body);
+                }
+                if let Some(finally) = finally {
+                    self.comments();
+                    self.space();
+                    self.token("finally");
+                    self.space();
+                    self.block(finally);
+                }
+            }
+            Stmt::Var { name, type_, init } => {
+                self.token("var");
+                self.space();
+                self.token(&name.lexeme);
+                self.annotation(type_);
+                if let Some(init) = init {
+                    self.space();
+                    self.token("=");
+                    self.space();
+                    self.expr(init);
+                }
+                self.token(";");
+            }
+            Stmt::While { condition, body } => {
+                self.token("while");
+                self.space();
+                self.token("(");
+                self.expr(condition);
+                self.token(")");
+                self.body(body);
+            }
+        }
+    }
+
+    // Undoes desugaring done by `Parser::for_statement`, checking source
+    // for which clauses are there
+    fn for_loop(&mut self, mut stmt: &Stmt) {
+        self.token("for");
+        self.space();
+        self.token("(");
+        if self.peek().type_ == TokenType::Semicolon {
+            self.token(";");
+        } else if let Stmt::Block { statements } = stmt {
+            self.statement(&statements[0]);
+            stmt = &statements[1];
+        }
+
+        let (condition, mut body) = match stmt {
+            Stmt::While { condition, body } => (condition, &**body),
+            _ => unreachable!("for loop is parsed into while loop"),
+        };
+        if self.peek().type_ != TokenType::Semicolon {
+            self.space();
+            self.expr(condition);
+        }
+        self.token(";");
+
+        if self.peek().type_ != TokenType::RightParen {
+            if let Stmt::Block { statements } = body {
+                if let Stmt::Expression { expr } = &statements[1] {
+                    self.space();
+                    self.expr(expr);
+                }
+                body = &statements[0];
+            }
+        }
+        self.token(")");
+        self.body(body);
+    }
+
+    fn expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { name, value, .. } => {
+                self.token(&name.lexeme);
+                self.space();
+                self.token("=");
+                self.space();
+                self.expr(value);
+            }
+            Expr::Binary { op, left, right } => {
+                self.expr(left);
+                self.space();
+                self.token(&op.lexeme);
+                self.space();
+                self.expr(right);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => {
+                self.expr(callee);
+                self.token("(");
+                for (i, argument) in arguments.iter().enumerate() {
+                    if i > 0 {
+                        self.token(",");
+                        self.space();
+                    }
+                    self.expr(argument);
+                }
+                self.token(")");
+            }
+            Expr::Get { object, name } => {
+                self.expr(object);
+                self.token(".");
+                self.token(&name.lexeme);
+            }
+            Expr::Grouping { expr } => {
+                self.token("(");
+                self.expr(expr);
+                self.token(")");
+            }
+            Expr::Literal { token, .. } => self.token(&token.lexeme),
+            Expr::Set {
+                object,
+                name,
+                value,
+            } => {
+                self.expr(object);
+                self.token(".");
+                self.token(&name.lexeme);
+                self.space();
+                self.token("=");
+                self.space();
+                self.expr(value);
+            }
+            Expr::Super { method, .. } => {
+                self.token("super");
+                self.token(".");
+                self.token(&method.lexeme);
+            }
+            Expr::This { .. } => self.token("this"),
+            Expr::Unary { op, right } => {
+                self.token(&op.lexeme);
+                self.expr(right);
+            }
+            Expr::Variable { name, .. } => self.token(&name.lexeme),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+
+    #[test]
+    fn canonical_style() {
+        let source = "\
+// header
+
+var a=1;   var b = a+  2 ; // trailing
+fun f( x,y ){return x*(y-1);}
+fun g(a :Number,b):String{var c:Bool=a;}
+class B<A{
+  init(){ this.x = super.init( 1 ,2) ;}
+
+
+  m() {}
+}
+if (a) print a; else if (b) { print b; } else print -a;
+for (var i = 0; i < 3; i = i + 1) { print i; }
+for (;;) a = !a;
+while (a) {
+  // own line
+  a = a and b or nil;
+
+}
+try { f(1, 2); } catch (e) { print e.message; } finally {}
+";
+        assert_eq!(
+            format(source).unwrap(),
+            "\
+// header
+
+var a = 1;
+var b = a + 2; // trailing
+fun f(x, y) {
+  return x * (y - 1);
+}
+fun g(a: Number, b): String {
+  var c: Bool = a;
+}
+class B < A {
+  init() {
+    this.x = super.init(1, 2);
+  }
+
+  m() {}
+}
+if (a) print a;
+else if (b) {
+  print b;
+} else print -a;
+for (var i = 0; i < 3; i = i + 1) {
+  print i;
+}
+for (;;) a = !a;
+while (a) {
+  // own line
+  a = a and b or nil;
+}
+try {
+  f(1, 2);
+} catch (e) {
+  print e.message;
+} finally {}
+"
+        );
+    }
+
+    #[test]
+    fn comments_in_expressions() {
+        let source = "var a = 1 + // one\n  2;\n{ // block\n}\n";
+        assert_eq!(
+            format(source).unwrap(),
+            "var a = 1 + // one\n  2;\n{ // block\n}\n"
+        );
+
+        let source = "fun f() { while (x) // w\n x = x - 1; }\n";
+        assert_eq!(
+            format(source).unwrap(),
+            "fun f() {\n  while (x) // w\n    x = x - 1;\n}\n"
+        );
+
+        let source =
+            "fun f(x,\n  // c\n  y) { print x; }\nwhile (x) // w\n{}\n";
+        assert_eq!(
+            format(source).unwrap(),
+            "fun f(x,\n  // c\n  y) {\n  print x;\n}\nwhile (x) // w\n{}\n"
+        );
+    }
+
+    #[test]
+    fn idempotent() {
+        let mut scripts: Vec<_> = fs::read_dir("tests")
+            .unwrap()
+            .chain(fs::read_dir("lox").unwrap())
+            .map(|entry| entry.unwrap().path())
+            .collect();
+        scripts.sort();
+        for script in scripts {
+            let source = fs::read_to_string(&script).unwrap();
+            // Some tests check parse errors
+            let formatted = match format(&source) {
+                Ok(formatted) => formatted,
+                Err(_) => continue,
+            };
+            assert_eq!(format(&formatted).unwrap(), formatted, "{:?}", script);
+            let comments = |s: &str| s.matches("//").count();
+            assert_eq!(comments(&formatted), comments(&source), "{:?}", script);
+        }
+    }
+}
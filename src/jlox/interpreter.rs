@@ -5,8 +5,14 @@ use std::{
     time::Instant,
 };
 
+use crate::{
+    coverage::{Coverage, NO_FILE},
+    profile::Profiler,
+};
+
 use super::{
     ast::*,
+    debugger::{Debugger, Frame},
     environment::Environment,
     errors::{ControlFlow, RuntimeError, RuntimeResult},
     tokens::{Token, TokenType},
@@ -18,7 +24,18 @@ pub struct Interpreter<'a> {
     output: Box<dyn Write + 'a>,
     pub global: Environment,
     current: Environment,
-    pub locals: HashMap<Expr, usize>,
+    /// Depth and slot of every resolved local variable
+    pub locals: HashMap<NodeId, (usize, usize)>,
+    pub file: Option<String>,
+    pub profiler: Option<Profiler>,
+    pub coverage: Option<Coverage>,
+    pub debugger: Option<Debugger>,
+    // Call stack, only kept while debugging
+    pub(super) frames: Vec<Frame>,
+    // Look variables up by name through environment chain, instead of using
+    // resolved distances, as debugger expressions aren't resolved
+    dynamic_scope: bool,
+    error_class: Class,
 }
 
 impl fmt::Debug for Interpreter<'_> {
@@ -29,6 +46,12 @@ impl fmt::Debug for Interpreter<'_> {
             .field("global", &self.global)
             .field("current", &self.current)
             .field("locals", &self.locals)
+            .field("file", &self.file)
+            .field("profiler", &self.profiler.is_some())
+            .field("coverage", &self.coverage)
+            .field("debugger", &self.debugger.is_some())
+            .field("frames", &self.frames)
+            .field("error_class", &self.error_class)
             .finish()
     }
 }
@@ -38,8 +61,8 @@ impl<'a> Interpreter<'a> {
         let mut global = Environment::default();
 
         global.define(
-            "clock".into(),
-            ValueRef::fun(0, |interpreter, _| {
+            "clock",
+            ValueRef::fun("clock", 0, |interpreter, _| {
                 let dur = interpreter.start_time.elapsed();
                 Ok(ValueRef::from_value(Value::Number(
                     dur.as_nanos() as f64 * 1e-9,
@@ -48,12 +71,20 @@ impl<'a> Interpreter<'a> {
         );
 
         global.define(
-            "panic".into(),
-            ValueRef::fun(0, |_, _| {
+            "panic",
+            ValueRef::fun("panic", 0, |_, _| {
                 Err(RuntimeError::wrapped(None, "Explicit panic"))
             }),
         );
 
+        // Runtime errors caught by `catch` are turned into instances of
+        // this class, with `message` and `line` fields
+        let error_class = Class::new("Error".into(), None, BTreeMap::new());
+        global.define(
+            "Error",
+            ValueRef::from_value(Value::Class(error_class.clone())),
+        );
+
         let current = global.clone();
         Self {
             start_time: Instant::now(),
@@ -61,26 +92,78 @@ impl<'a> Interpreter<'a> {
             global,
             current,
             locals: HashMap::new(),
+            file: None,
+            profiler: None,
+            coverage: None,
+            debugger: None,
+            frames: vec![],
+            dynamic_scope: false,
+            error_class,
         }
     }
 
-    pub fn interpret(&mut self, statements: &mut [Stmt]) -> RuntimeResult<()> {
+    pub fn interpret(&mut self, statements: &[Stmt]) -> RuntimeResult<()> {
+        if let Some(coverage) = &mut self.coverage {
+            let file = self.file.as_deref().unwrap_or(NO_FILE);
+            for line in statement_lines(statements) {
+                coverage.add_line(file, line as usize);
+            }
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter("script");
+        }
+        let debugging = self.debugger.is_some();
+        if debugging {
+            self.global.keep_names();
+            self.push_frame("script");
+        }
         let result = (|| {
             for statement in statements {
                 self.visit_stmt(statement)?;
             }
             Ok(())
         })();
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        if debugging {
+            self.frames.pop();
+        }
 
         match result {
-            Err(ControlFlow::Error(_)) => result,
-            _ => Ok(()),
+            Err(ControlFlow::Error(mut error)) => {
+                error.push_frame("script", self.file.as_deref());
+                Err(ControlFlow::Error(error))
+            }
+            Err(ControlFlow::Throw(value, mut error)) => {
+                error.push_frame("script", self.file.as_deref());
+                Err(ControlFlow::Throw(value, error))
+            }
+            // Quitting debugger ends script like reaching its end does
+            Err(ControlFlow::Stop) => Ok(()),
+            Ok(()) | Err(ControlFlow::Return(_)) | Err(ControlFlow::Break) => {
+                Ok(())
+            }
         }
     }
 
+    fn error_object(&self, error: &RuntimeError) -> ValueRef {
+        let mut instance = Instance::new(self.error_class.clone());
+        instance.set_field(
+            "message",
+            ValueRef::from_value(Value::String(error.message().to_owned())),
+        );
+        let line = error
+            .token()
+            .map(|token| Value::Number(token.pos.0 as f64))
+            .unwrap_or(Value::Nil);
+        instance.set_field("line", ValueRef::from_value(line));
+        ValueRef::from_value(Value::Instance(instance))
+    }
+
     pub fn execute_block(
         &mut self,
-        statements: &mut [Stmt],
+        statements: &[Stmt],
         environment: Environment,
     ) -> RuntimeResult<()> {
         let previous = self.current.clone();
@@ -95,30 +178,99 @@ impl<'a> Interpreter<'a> {
         result
     }
 
+    fn call(
+        &mut self,
+        function: &mut Fun,
+        arguments: &mut [ValueRef],
+    ) -> RuntimeResult<ValueRef> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.enter(function.name());
+        }
+        let debugging = self.debugger.is_some();
+        if debugging {
+            self.push_frame(function.name());
+        }
+        let result = function.call(self, arguments);
+        if debugging {
+            self.frames.pop();
+        }
+        if let Some(profiler) = &mut self.profiler {
+            profiler.exit();
+        }
+        result
+    }
+
+    fn push_frame(&mut self, function: &str) {
+        let line = self.frames.last().map_or(0, |frame| frame.line);
+        self.frames.push(Frame {
+            function: function.to_owned(),
+            line,
+            environment: self.current.clone(),
+        });
+    }
+
+    fn debug_statement(&mut self, stmt: &Stmt) -> RuntimeResult<()> {
+        let line = match stmt.line() {
+            Some(line) => line,
+            None => return Ok(()),
+        };
+        if let Some(frame) = self.frames.last_mut() {
+            frame.line = line;
+            frame.environment = self.current.clone();
+        }
+        // Taken out while it runs, so expressions it evaluates don't stop
+        let mut debugger = self.debugger.take().unwrap();
+        let result = debugger.statement(self, line);
+        self.debugger = Some(debugger);
+        result
+    }
+
+    /// Evaluates unresolved expression in `environment`
+    pub(super) fn evaluate(
+        &mut self,
+        expr: &Expr,
+        environment: Environment,
+    ) -> RuntimeResult<ValueRef> {
+        let previous = std::mem::replace(&mut self.current, environment);
+        self.dynamic_scope = true;
+        let result = self.visit_expr(expr);
+        self.dynamic_scope = false;
+        self.current = previous;
+        result
+    }
+
     fn lookup_variable(
         &self,
         name: &Token,
-        expr: &Expr,
+        id: NodeId,
     ) -> RuntimeResult<ValueRef> {
-        let get = self.locals.get(expr);
-        match get {
-            Some(&distance) => self.current.get_at(distance, name),
+        if self.dynamic_scope {
+            return self.current.get(name);
+        }
+        match self.locals.get(&id) {
+            Some(&(distance, slot)) => {
+                self.current.get_at(distance, slot, Some(name))
+            }
             None => self.global.get(name),
         }
     }
 
-    fn visit_expr(&mut self, expr: &mut Expr) -> RuntimeResult<ValueRef> {
+    fn visit_expr(&mut self, expr: &Expr) -> RuntimeResult<ValueRef> {
         match expr {
-            Expr::Assign { name, value } => {
-                let name = name.clone();
+            Expr::Assign { id, name, value } => {
                 let value = self.visit_expr(value)?;
-                match self.locals.get(&expr) {
-                    Some(&distance) => self.current.assign_at(
+                if self.dynamic_scope {
+                    self.current.assign(name, value.clone())?;
+                    return Ok(value);
+                }
+                match self.locals.get(id) {
+                    Some(&(distance, slot)) => self.current.assign_at(
                         distance,
-                        &name,
+                        slot,
+                        name,
                         value.clone(),
                     )?,
-                    None => self.global.assign(&name, value.clone())?,
+                    None => self.global.assign(name, value.clone())?,
                 }
                 Ok(value)
             }
@@ -139,25 +291,25 @@ impl<'a> Interpreter<'a> {
                     }
                 }
 
-                let left = self.visit_expr(&mut *left)?;
+                let left = self.visit_expr(left)?;
 
                 match op.type_ {
                     TokenType::Or if left.value().is_truthy() => {
                         return Ok(left)
                     }
                     TokenType::Or if !left.value().is_truthy() => {
-                        return self.visit_expr(&mut *right)
+                        return self.visit_expr(right)
                     }
                     TokenType::And if !left.value().is_truthy() => {
                         return Ok(left)
                     }
                     TokenType::And if left.value().is_truthy() => {
-                        return self.visit_expr(&mut *right)
+                        return self.visit_expr(right)
                     }
                     _ => (),
                 }
 
-                let right = self.visit_expr(&mut *right)?;
+                let right = self.visit_expr(right)?;
 
                 match op.type_ {
                     TokenType::Plus => match (left.value(), right.value()) {
@@ -220,7 +372,7 @@ impl<'a> Interpreter<'a> {
             } => {
                 let callee = self.visit_expr(callee)?;
                 let mut arguments: Vec<ValueRef> = arguments
-                    .iter_mut()
+                    .iter()
                     .map(|e| self.visit_expr(e))
                     .collect::<Result<_, _>>()?;
 
@@ -234,9 +386,9 @@ impl<'a> Interpreter<'a> {
                         ),
                     ))
                 };
-                match callee.value() {
+                let result = match callee.value() {
                     Value::Fun(mut f) if f.arity() == arguments.len() => {
-                        f.call(self, &mut arguments)
+                        self.call(&mut f, &mut arguments)
                     }
                     Value::Fun(f) => wrong_arity(f.arity()),
                     Value::Class(class) => {
@@ -245,8 +397,8 @@ impl<'a> Interpreter<'a> {
                         ));
                         match class.find_method("init") {
                             Some(init) if init.arity() == arguments.len() => {
-                                init.bind(&instance)?
-                                    .call(self, &mut arguments)?;
+                                let mut init = Fun::Lox(init.bind(&instance)?);
+                                self.call(&mut init, &mut arguments)?;
                                 Ok(instance)
                             }
                             None if arguments.is_empty() => Ok(instance),
@@ -258,7 +410,15 @@ impl<'a> Interpreter<'a> {
                         Some(right_paren),
                         "Can only call functions and classes.",
                     )),
-                }
+                };
+                result.map_err(|mut e| {
+                    if let ControlFlow::Error(error)
+                    | ControlFlow::Throw(_, error) = &mut e
+                    {
+                        error.set_call_site(right_paren);
+                    }
+                    e
+                })
             }
 
             Expr::Get { object, name } => {
@@ -276,7 +436,9 @@ impl<'a> Interpreter<'a> {
 
             Expr::Grouping { expr } => self.visit_expr(expr),
 
-            Expr::Literal { value } => Ok(ValueRef::from_value(value.clone())),
+            Expr::Literal { value, .. } => {
+                Ok(ValueRef::from_value(value.clone()))
+            }
 
             Expr::Set {
                 object,
@@ -298,13 +460,19 @@ impl<'a> Interpreter<'a> {
             }
 
             Expr::Super {
-                ref keyword,
-                ref method,
+                id,
+                keyword,
+                method,
             } => {
-                let distance = *self.locals.get(&expr).ok_or_else(|| {
-                    RuntimeError::wrapped(Some(keyword), "Missing superclass")
-                })?;
-                let superclass = self.current.get_at_str(distance, "super")?;
+                let (distance, slot) =
+                    *self.locals.get(id).ok_or_else(|| {
+                        RuntimeError::wrapped(
+                            Some(keyword),
+                            "Missing superclass",
+                        )
+                    })?;
+                let superclass =
+                    self.current.get_at(distance, slot, Some(keyword))?;
                 let class = match superclass.value() {
                     Value::Class(class) => class,
                     _ => {
@@ -314,7 +482,9 @@ impl<'a> Interpreter<'a> {
                         ))
                     }
                 };
-                let object = self.current.get_at_str(distance - 1, "this")?;
+                // 'this' is alone in scope right inside the one of 'super'
+                let object =
+                    self.current.get_at(distance - 1, 0, Some(keyword))?;
                 let method = class
                     .find_method(&method.lexeme)
                     .ok_or_else(|| {
@@ -327,12 +497,10 @@ impl<'a> Interpreter<'a> {
                 Ok(ValueRef::from_value(Value::Fun(Fun::Lox(method))))
             }
 
-            Expr::This { keyword } => {
-                self.lookup_variable(&keyword.clone(), expr)
-            }
+            Expr::This { id, keyword } => self.lookup_variable(keyword, *id),
 
             Expr::Unary { op, right } => {
-                let value = self.visit_expr(&mut *right)?;
+                let value = self.visit_expr(right)?;
                 Ok(match op.type_ {
                     TokenType::Minus => {
                         let value =
@@ -356,13 +524,22 @@ impl<'a> Interpreter<'a> {
                 })
             }
 
-            Expr::Variable { name } => {
-                self.lookup_variable(&name.clone(), expr)
-            }
+            Expr::Variable { id, name } => self.lookup_variable(name, *id),
         }
     }
 
-    fn visit_stmt(&mut self, stmt: &mut Stmt) -> RuntimeResult<()> {
+    fn visit_stmt(&mut self, stmt: &Stmt) -> RuntimeResult<()> {
+        if let Some(profiler) = &mut self.profiler {
+            profiler.hit(stmt.line().map(|l| l as usize), stmt.kind());
+        }
+        if let (Some(coverage), Some(line)) = (&mut self.coverage, stmt.line())
+        {
+            let file = self.file.as_deref().unwrap_or(NO_FILE);
+            coverage.hit(file, line as usize);
+        }
+        if self.debugger.is_some() {
+            self.debug_statement(stmt)?;
+        }
         match stmt {
             Stmt::Block { statements } => {
                 self.execute_block(statements, self.current.enclose())
@@ -377,13 +554,11 @@ impl<'a> Interpreter<'a> {
                 let (superclass_ref, superclass) = superclass
                     .as_ref()
                     .map(|superclass| {
-                        let value = self.visit_expr(&mut Expr::variable(
-                            superclass.clone(),
-                        ))?;
+                        let value = self.visit_expr(superclass)?;
                         match value.value() {
                             Value::Class(class) => Ok((value, class)),
                             _ => Err(RuntimeError::wrapped(
-                                Some(superclass),
+                                Some(superclass.first_token()),
                                 "Superclass must be a class.",
                             )),
                         }
@@ -392,11 +567,9 @@ impl<'a> Interpreter<'a> {
                     .map(|(r, s)| (Some(r), (Some(s))))
                     .unwrap_or_default();
 
-                self.current.define(name.lexeme.clone(), ValueRef::nil());
-
                 if let Some(superclass_ref) = superclass_ref {
                     self.current = self.current.enclose();
-                    self.current.define("super".to_string(), superclass_ref);
+                    self.current.define("super", superclass_ref);
                 }
 
                 let mut methods = BTreeMap::new();
@@ -415,8 +588,10 @@ impl<'a> Interpreter<'a> {
                     self.current = self.current.enclosing().unwrap();
                 }
 
+                // Methods only refer to class once called, so it's defined
+                // after them, in the slot resolver gave it
                 self.current.define(
-                    name.lexeme.clone(),
+                    &name.lexeme,
                     ValueRef::from_value(Value::Class(class)),
                 );
                 Ok(())
@@ -429,8 +604,7 @@ impl<'a> Interpreter<'a> {
                 let function = ValueRef::from_value(Value::Fun(Fun::Lox(
                     LoxFunction::new(declaration.clone(), closure, false),
                 )));
-                self.current
-                    .define(declaration.name.lexeme.clone(), function);
+                self.current.define(&declaration.name.lexeme, function);
                 Ok(())
             }
 
@@ -455,25 +629,65 @@ impl<'a> Interpreter<'a> {
 
             Stmt::Return { keyword: _, value } => Err(ControlFlow::Return(
                 value
-                    .as_mut()
+                    .as_ref()
                     .map(|e| self.visit_expr(e))
                     .transpose()?
                     .unwrap_or_else(ValueRef::nil),
             )),
 
-            Stmt::Var { name, init } => {
+            Stmt::Var { name, init, .. } => {
                 let value = init
-                    .as_mut()
+                    .as_ref()
                     .map(|e| self.visit_expr(e))
                     .transpose()?
                     .unwrap_or_else(ValueRef::nil);
-                self.current.define(name.lexeme.clone(), value);
+                self.current.define(&name.lexeme, value);
                 Ok(())
             }
 
+            Stmt::Throw { keyword, value } => {
+                let value = self.visit_expr(value)?;
+                let error = RuntimeError::uncaught(keyword, &value);
+                Err(ControlFlow::Throw(value, error))
+            }
+
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                let mut result =
+                    self.execute_block(body, self.current.enclose());
+                if let Some(Catch { name, body }) = catch {
+                    let exception = match &result {
+                        Err(ControlFlow::Throw(value, _)) => {
+                            Some(value.clone())
+                        }
+                        Err(ControlFlow::Error(error)) => {
+                            Some(self.error_object(error))
+                        }
+                        _ => None,
+                    };
+                    if let Some(exception) = exception {
+                        let mut environment = self.current.enclose();
+                        environment.define(&name.lexeme, exception);
+                        result = self.execute_block(body, environment);
+                    }
+                }
+                if let Some(finally) = finally {
+                    if !matches!(result, Err(ControlFlow::Stop)) {
+                        self.execute_block(finally, self.current.enclose())?;
+                    }
+                }
+                result
+            }
+
             Stmt::While { condition, body } => {
                 while self.visit_expr(condition)?.value().is_truthy() {
                     self.visit_stmt(body)?;
+                    if let Some(debugger) = &mut self.debugger {
+                        debugger.jumped_back();
+                    }
                 }
                 Ok(())
             }
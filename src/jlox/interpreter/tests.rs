@@ -1,61 +1,59 @@
+use std::{cell::RefCell, io::Write, rc::Rc};
+
 use super::{
     super::{
-        errors::ResolveError, parser::Parser, resolver::Resolver,
+        debugger::{Console, Debugger},
+        diagnostics::{Color, Palette, Renderer},
+        errors::ResolveError,
+        parser::Parser,
+        resolver::Resolver,
         tokenizer::Tokenizer,
     },
     *,
 };
 
 #[track_caller]
-fn run(x: &str) -> String {
-    let tokens = Tokenizer::new(x)
+fn parse(source: &str) -> Vec<Stmt> {
+    let tokens = Tokenizer::new(source)
         .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
         .collect::<Result<Vec<_>, _>>()
         .unwrap();
-    let mut ast = Parser::new(tokens).parse().unwrap();
+    Parser::new(tokens).parse().unwrap()
+}
 
+#[track_caller]
+fn resolve_and_interpret(
+    interpreter: &mut Interpreter,
+    ast: &[Stmt],
+) -> RuntimeResult<()> {
+    Resolver::new(&mut interpreter.locals).resolve(ast).unwrap();
+    interpreter.interpret(ast)
+}
+
+#[track_caller]
+fn run(x: &str) -> String {
     let mut output = vec![];
     let mut interpreter = Interpreter::new(&mut output);
-
-    Resolver::new(&mut interpreter.locals)
-        .resolve(&mut ast)
-        .unwrap();
-
-    interpreter.interpret(&mut ast).unwrap();
+    resolve_and_interpret(&mut interpreter, &parse(x)).unwrap();
     drop(interpreter);
     String::from_utf8(output).unwrap()
 }
 
 #[track_caller]
 fn interpreter_error(x: &str) -> RuntimeError {
-    let tokens = Tokenizer::new(x)
-        .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
-    let mut ast = Parser::new(tokens).parse().unwrap();
-
     let mut output = vec![];
     let mut interpreter = Interpreter::new(&mut output);
-    Resolver::new(&mut interpreter.locals)
-        .resolve(&mut ast)
-        .unwrap();
-
-    let error = interpreter.interpret(&mut ast).unwrap_err().into_error();
-    error
+    resolve_and_interpret(&mut interpreter, &parse(x))
+        .unwrap_err()
+        .into_error()
 }
 
 #[track_caller]
 fn resolver_error(x: &str) -> ResolveError {
-    let tokens = Tokenizer::new(x)
-        .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
-        .collect::<Result<Vec<_>, _>>()
-        .unwrap();
-    let mut ast = Parser::new(tokens).parse().unwrap();
-
     let mut output = vec![];
     let mut interpreter = Interpreter::new(&mut output);
     Resolver::new(&mut interpreter.locals)
-        .resolve(&mut ast)
+        .resolve(&parse(x))
         .unwrap_err()
 }
 
@@ -106,6 +104,36 @@ fn scopes() {
     );
 }
 
+#[test]
+fn slots() {
+    assert_eq!(
+        run("var a = 1;
+            var a = 2;
+            {
+                var b = 3;
+                class A { get() { return b; } }
+                class B < A { get() { return super.get() + this.c; } }
+                var c = B();
+                c.c = 4;
+                print a + c.get();
+            }"),
+        "9\n"
+    );
+}
+
+#[test]
+fn separately_parsed() {
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut output);
+
+    // Both reads of 'a' have the same token, like two REPL lines would
+    for line in ["fun f(a) { print a; }", "var a = 1;{print a;}"] {
+        resolve_and_interpret(&mut interpreter, &parse(line)).unwrap();
+    }
+    drop(interpreter);
+    assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+}
+
 #[test]
 fn scope_error() {
     assert_eq!(
@@ -160,3 +188,247 @@ fn factorial() {
         ((1..=20).map(|x| x as f64).product::<f64>().to_string() + "\n")
     );
 }
+
+#[test]
+fn exceptions() {
+    assert_eq!(
+        run("try {
+                throw 1;
+            } catch (e) {
+                print e;
+            } finally {
+                print 2;
+            }"),
+        "1\n2\n"
+    );
+
+    assert_eq!(
+        run("try {
+                -\"a\";
+            } catch (e) {
+                print e;
+                print e.message;
+            }"),
+        "Error instance\nOperand must be a number.\n"
+    );
+
+    assert_eq!(
+        interpreter_error(
+            "try {
+                nil();
+            } catch (e) {
+                throw e;
+            }"
+        )
+        .to_string(),
+        "[line 4:21] Runtime Error at 'throw': Uncaught exception: Can only call functions and classes."
+    );
+}
+
+#[test]
+fn stack_trace() {
+    let error = interpreter_error(
+        "fun inner() {
+            return nil + 1;
+        }
+        fun outer() {
+            inner();
+        }
+        outer();",
+    );
+    assert_eq!(
+        error.traceback(),
+        "[line 2:24] in inner()\n[line 5:19] in outer()\n[line 7:15] in script"
+    );
+
+    let source = "fun inner() {
+            throw \"oops\";
+        }
+        fun outer() {
+            try { inner(); } finally {}
+        }
+        outer();";
+    let error = interpreter_error(source);
+    assert_eq!(
+        error.to_string(),
+        "[line 2:17] Runtime Error at 'throw': Uncaught exception: oops"
+    );
+    // Frames point at start of token, like the rendered diagnostic
+    assert_eq!(
+        error.traceback(),
+        "[line 2:13] in inner()\n[line 5:25] in outer()\n[line 7:15] in script"
+    );
+    let renderer = Renderer::new(source, None, Palette::new(Color::Never));
+    assert!(renderer.render(&error).contains(" <input>:2:13\n"));
+}
+
+#[test]
+fn profiler() {
+    let source = "fun f(n) {
+        if (n < 2) return n;
+        return f(n - 1) + f(n - 2);
+    }
+    class A { init() { clock(); } }
+    A();
+    print f(5);";
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut output);
+    interpreter.profiler = Some(Profiler::new("statement"));
+    resolve_and_interpret(&mut interpreter, &parse(source)).unwrap();
+
+    let profiler = interpreter.profiler.take().unwrap();
+    let stats = profiler.function_stats();
+    assert_eq!(stats[0].0, "script");
+    assert!(stats
+        .iter()
+        .all(|(_, s)| s.inclusive <= stats[0].1.inclusive));
+    let calls: HashMap<_, _> = profiler
+        .function_stats()
+        .into_iter()
+        .map(|(name, stats)| (name.to_owned(), stats.calls))
+        .collect();
+    let expected: HashMap<_, _> =
+        [("script", 1), ("f", 15), ("init", 1), ("clock", 1)]
+            .iter()
+            .map(|&(name, calls)| (name.to_owned(), calls))
+            .collect();
+    assert_eq!(calls, expected);
+
+    let statements = profiler.counts();
+    assert_eq!(statements[0], (Some(2), "if", 15));
+    assert_eq!(statements[1], (Some(2), "return", 8));
+    assert_eq!(statements[2], (Some(3), "return", 7));
+    assert!(statements.contains(&(Some(7), "print", 1)));
+
+    let mut folded = vec![];
+    profiler.write_folded(&mut folded).unwrap();
+    let folded = String::from_utf8(folded).unwrap();
+    assert!(folded.contains("script;f;f;f;f "));
+    assert!(folded.contains("script;init;clock "));
+}
+
+#[test]
+fn coverage() {
+    let source = "var a = 1;
+    if (a > 2) {
+        print \"big\";
+    } else {
+        print \"small\";
+    }
+    while (false) nil;";
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut output);
+    interpreter.file = Some("test.lox".into());
+    interpreter.coverage = Some(Coverage::new());
+    resolve_and_interpret(&mut interpreter, &parse(source)).unwrap();
+
+    let coverage = interpreter.coverage.take().unwrap();
+    let mut lcov = vec![];
+    coverage.write_lcov(&mut lcov).unwrap();
+    let lcov = String::from_utf8(lcov).unwrap();
+    assert!(lcov.contains("DA:3,0\n"));
+    assert!(lcov.contains("DA:5,2\n"));
+    assert!(lcov.contains("DA:7,1\n"));
+    assert_eq!(coverage.file_summary("test.lox"), Some((5, 4)));
+}
+
+#[derive(Clone, Default)]
+struct Shared(Rc<RefCell<Vec<u8>>>);
+
+impl Write for Shared {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.0.borrow_mut().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn debugger() {
+    let source = "fun f(n) {
+  var m = n * 2;
+  return m;
+}
+var a = f(1);
+for (var i = 0; i < 2; i = i + 1) {
+  a = a + i;
+}
+print a;";
+    let mut commands = vec![
+        "b 7", "s", "s", "n", "bt", "v", "p n + m", "f", "c", "p a = 10", "c",
+        "c",
+    ]
+    .into_iter()
+    .map(String::from);
+    let log = Shared::default();
+    let console = Console::new(move |_: &str| commands.next(), log.clone());
+    let mut debugger = Debugger::new(console);
+    debugger.set_source(source);
+
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut output);
+    interpreter.debugger = Some(debugger);
+    resolve_and_interpret(&mut interpreter, &parse(source)).unwrap();
+    drop(interpreter);
+
+    assert_eq!(String::from_utf8(output).unwrap(), "11\n");
+    let log = String::from_utf8(log.0.take()).unwrap();
+    assert_eq!(
+        log,
+        "\
+Stopped at entry in script
+   1 | fun f(n) {
+Breakpoint at line 7
+Stopped at step in script
+   5 | var a = f(1);
+Stopped at step in f
+   2 | var m = n * 2;
+Stopped at step in f
+   3 | return m;
+> #0 [line 3] in f
+  #1 [line 5] in script
+scope 0:
+  m = 2
+  n = 1
+scope 1:
+globals 2:
+  Error = Error
+  clock = <native fn>
+  f = <fn f>
+  panic = <native fn>
+3
+Stopped at step in script
+   6 | for (var i = 0; i < 2; i = i + 1) {
+Stopped at breakpoint in script
+   7 | a = a + i;
+10
+Stopped at breakpoint in script
+   7 | a = a + i;
+"
+    );
+}
+
+#[test]
+fn debugger_quit() {
+    let source = "print 1;
+try {
+  print 2;
+} finally {
+  print 3;
+}
+print 4;";
+    let mut commands = vec!["b 3", "c", "q"].into_iter().map(String::from);
+    let console = Console::new(move |_: &str| commands.next(), vec![]);
+    let mut debugger = Debugger::new(console);
+    debugger.set_source(source);
+
+    let mut output = vec![];
+    let mut interpreter = Interpreter::new(&mut output);
+    interpreter.debugger = Some(debugger);
+    // Nothing runs after quitting, not even `finally`
+    resolve_and_interpret(&mut interpreter, &parse(source)).unwrap();
+    drop(interpreter);
+    assert_eq!(String::from_utf8(output).unwrap(), "1\n");
+}
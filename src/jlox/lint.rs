@@ -0,0 +1,639 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fmt, io,
+    str::FromStr,
+};
+
+use super::{
+    ast::{walk_expr, walk_stmt, Expr, Function, Stmt, Visitor},
+    errors::Note,
+    interpreter::Interpreter,
+    tokens::{Token, TokenType},
+    types::{Fun, Value},
+};
+
+/// Kind of warning, named in `// lox-allow: name` comments
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Lint {
+    UnusedVariable,
+    UnusedParameter,
+    UnreachableCode,
+    ShadowedVariable,
+    UndeclaredAssignment,
+    SelfComparison,
+    WrongArity,
+}
+
+impl Lint {
+    pub const ALL: [Lint; 7] = [
+        Lint::UnusedVariable,
+        Lint::UnusedParameter,
+        Lint::UnreachableCode,
+        Lint::ShadowedVariable,
+        Lint::UndeclaredAssignment,
+        Lint::SelfComparison,
+        Lint::WrongArity,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Lint::UnusedVariable => "unused-variable",
+            Lint::UnusedParameter => "unused-parameter",
+            Lint::UnreachableCode => "unreachable-code",
+            Lint::ShadowedVariable => "shadowed-variable",
+            Lint::UndeclaredAssignment => "undeclared-assignment",
+            Lint::SelfComparison => "self-comparison",
+            Lint::WrongArity => "wrong-arity",
+        }
+    }
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.name())
+    }
+}
+
+impl FromStr for Lint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Lint::ALL
+            .iter()
+            .copied()
+            .find(|lint| lint.name() == s)
+            .ok_or_else(|| {
+                let names: Vec<_> =
+                    Lint::ALL.iter().map(|l| l.name()).collect();
+                format!("Unknown lint {}\nAvailable: {}", s, names.join(", "))
+            })
+    }
+}
+
+#[derive(Debug)]
+pub struct Warning {
+    pub lint: Lint,
+    pub token: Token,
+    pub message: String,
+    pub notes: Vec<Note>,
+}
+
+impl Warning {
+    fn new(lint: Lint, token: &Token, message: String) -> Self {
+        Self {
+            lint,
+            token: token.clone(),
+            message,
+            notes: vec![],
+        }
+    }
+
+    fn with_note(mut self, token: Option<&Token>, message: &str) -> Self {
+        self.notes.push(Note {
+            token: token.cloned(),
+            message: message.into(),
+        });
+        self
+    }
+}
+
+/// Lints silenced by `// lox-allow: name, ...` comments. Comment placed
+/// before any code applies to whole file, one after code to its own line,
+/// any other to the next line.
+#[derive(Debug, Default)]
+struct Allowed {
+    file: HashSet<Lint>,
+    lines: HashMap<u32, HashSet<Lint>>,
+}
+
+impl Allowed {
+    fn new(tokens: &[Token], allow: &[Lint]) -> Self {
+        let mut allowed = Self {
+            file: allow.iter().copied().collect(),
+            lines: HashMap::new(),
+        };
+        let mut code = false;
+        let mut previous: Option<&Token> = None;
+        for token in tokens {
+            let after = previous.replace(token);
+            if token.type_ != TokenType::Comment {
+                code = true;
+                continue;
+            }
+            let names = match token
+                .lexeme
+                .trim_start_matches('/')
+                .trim()
+                .strip_prefix("lox-allow:")
+            {
+                Some(names) => names,
+                None => continue,
+            };
+            let lints = names
+                .split(',')
+                .filter_map(|n| n.trim().parse::<Lint>().ok());
+            let line = token.pos.0;
+            if !code {
+                allowed.file.extend(lints);
+            } else if after.is_some_and(|t| t.pos.0 == line) {
+                allowed.lines.entry(line).or_default().extend(lints);
+            } else {
+                allowed.lines.entry(line + 1).or_default().extend(lints);
+            }
+        }
+        allowed
+    }
+
+    fn allows(&self, warning: &Warning) -> bool {
+        self.file.contains(&warning.lint)
+            || self
+                .lines
+                .get(&warning.token.pos.0)
+                .is_some_and(|lints| lints.contains(&warning.lint))
+    }
+}
+
+#[derive(Debug)]
+struct Binding {
+    // `None` for native functions
+    declaration: Option<Token>,
+    parameter: bool,
+    used: bool,
+    // Number of parameters of function or class bound to variable, as long
+    // as it's never reassigned
+    arity: Option<usize>,
+}
+
+struct Linter {
+    bindings: Vec<Binding>,
+    // Indices of bindings declared in every scope, globals first
+    scopes: Vec<HashMap<String, usize>>,
+    // Calls of variables, checked against arity once all assignments are
+    // known
+    calls: Vec<(usize, Token, usize)>,
+    warnings: Vec<Warning>,
+}
+
+fn class_arity(methods: &[Function]) -> usize {
+    methods
+        .iter()
+        .find(|method| method.name.lexeme == "init")
+        .map_or(0, |init| init.params.len())
+}
+
+// Whether both expressions read the same thing, so comparing them is
+// pointless
+fn same(left: &Expr, right: &Expr) -> bool {
+    match (left, right) {
+        (Expr::Variable { name: a, .. }, Expr::Variable { name: b, .. }) => {
+            a.lexeme == b.lexeme
+        }
+        (
+            Expr::Get {
+                object: a,
+                name: a_name,
+            },
+            Expr::Get {
+                object: b,
+                name: b_name,
+            },
+        ) => a_name.lexeme == b_name.lexeme && same(a, b),
+        (Expr::This { .. }, Expr::This { .. }) => true,
+        (Expr::Grouping { expr: a }, b) | (b, Expr::Grouping { expr: a }) => {
+            same(a, b)
+        }
+        _ => false,
+    }
+}
+
+impl Linter {
+    fn new(program: &[Stmt]) -> Self {
+        let mut linter = Self {
+            bindings: vec![],
+            scopes: vec![HashMap::new()],
+            calls: vec![],
+            warnings: vec![],
+        };
+        for (name, value) in Interpreter::new(io::sink()).global.variables() {
+            let arity = match value.value() {
+                Value::Fun(Fun::Native { arity, .. }) => Some(arity),
+                _ => None,
+            };
+            linter.bind(name, None, arity);
+        }
+        // Globals can be used before they're declared, as long as it's
+        // inside a function
+        for stmt in program {
+            let (name, arity) = match stmt {
+                Stmt::Var { name, .. } => (name, None),
+                Stmt::Function(function) => {
+                    (&function.name, Some(function.params.len()))
+                }
+                Stmt::Class { name, methods, .. } => {
+                    (name, Some(class_arity(methods)))
+                }
+                _ => continue,
+            };
+            let redeclared = linter.scopes[0].contains_key(&name.lexeme);
+            let index = linter.bind(name.lexeme.clone(), Some(name), arity);
+            if redeclared {
+                linter.bindings[index].arity = None;
+            }
+        }
+        linter
+    }
+
+    fn bind(
+        &mut self,
+        name: String,
+        declaration: Option<&Token>,
+        arity: Option<usize>,
+    ) -> usize {
+        self.bindings.push(Binding {
+            declaration: declaration.cloned(),
+            parameter: false,
+            used: false,
+            arity,
+        });
+        let index = self.bindings.len() - 1;
+        self.scopes.last_mut().unwrap().insert(name, index);
+        index
+    }
+
+    fn lookup(&self, name: &str) -> Option<usize> {
+        self.scopes
+            .iter()
+            .rev()
+            .find_map(|scope| scope.get(name).copied())
+    }
+
+    fn declare(&mut self, name: &Token, arity: Option<usize>) -> usize {
+        // Globals were declared up front
+        if self.scopes.len() == 1 {
+            return self.scopes[0][&name.lexeme];
+        }
+        if let Some(outer) = self.lookup(&name.lexeme) {
+            let declaration = self.bindings[outer].declaration.clone();
+            self.warnings.push(
+                Warning::new(
+                    Lint::ShadowedVariable,
+                    name,
+                    format!(
+                        "Variable '{}' shadows outer variable.",
+                        name.lexeme
+                    ),
+                )
+                .with_note(
+                    declaration.as_ref(),
+                    "outer variable declared here",
+                ),
+            );
+        }
+        self.bind(name.lexeme.clone(), Some(name), arity)
+    }
+
+    fn begin_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    fn end_scope(&mut self) {
+        let scope = self.scopes.pop().unwrap();
+        let mut unused: Vec<_> = scope
+            .into_values()
+            .map(|index| &self.bindings[index])
+            .filter(|binding| !binding.used)
+            .filter_map(|binding| {
+                Some((binding.declaration.as_ref()?, binding))
+            })
+            .map(|(name, binding)| {
+                let (lint, kind) = match binding.parameter {
+                    true => (Lint::UnusedParameter, "Parameter"),
+                    false => (Lint::UnusedVariable, "Local variable"),
+                };
+                let message =
+                    format!("{} '{}' is never used.", kind, name.lexeme);
+                Warning::new(lint, name, message)
+            })
+            .collect();
+        unused.sort_by_key(|warning| warning.token.span);
+        self.warnings.extend(unused);
+    }
+
+    fn statements(&mut self, statements: &[Stmt]) {
+        let mut exit: Option<&Token> = None;
+        for stmt in statements {
+            // Only first unreachable statement is reported
+            if let (Some(keyword), Some(token)) = (exit, stmt.first_token()) {
+                let note = format!(
+                    "any code after this '{}' is unreachable",
+                    keyword.lexeme
+                );
+                self.warnings.push(
+                    Warning::new(
+                        Lint::UnreachableCode,
+                        token,
+                        "Unreachable code.".into(),
+                    )
+                    .with_note(Some(keyword), &note),
+                );
+                exit = None;
+            }
+            if let Stmt::Return { keyword, .. } | Stmt::Throw { keyword, .. } =
+                stmt
+            {
+                exit = Some(keyword);
+            }
+            self.visit_stmt(stmt);
+        }
+    }
+
+    fn block(&mut self, statements: &[Stmt]) {
+        self.begin_scope();
+        self.statements(statements);
+        self.end_scope();
+    }
+
+    fn variable(&mut self, name: &Token) -> Option<usize> {
+        let index = self.lookup(&name.lexeme)?;
+        self.bindings[index].used = true;
+        Some(index)
+    }
+
+    fn check_calls(&mut self) {
+        for (index, name, arguments) in std::mem::take(&mut self.calls) {
+            let binding = &self.bindings[index];
+            let arity = match binding.arity {
+                Some(arity) if arity != arguments => arity,
+                _ => continue,
+            };
+            let declaration = binding.declaration.clone();
+            self.warnings.push(
+                Warning::new(
+                    Lint::WrongArity,
+                    &name,
+                    format!(
+                        "Expected {} arguments but got {}.",
+                        arity, arguments
+                    ),
+                )
+                .with_note(declaration.as_ref(), "declared here"),
+            );
+        }
+    }
+}
+
+impl Visitor for Linter {
+    fn visit_stmt(&mut self, stmt: &Stmt) {
+        match stmt {
+            Stmt::Block { statements } => self.block(statements),
+            Stmt::Class { name, methods, .. } => {
+                self.declare(name, Some(class_arity(methods)));
+                walk_stmt(self, stmt);
+            }
+            Stmt::Function(function) => {
+                self.declare(&function.name, Some(function.params.len()));
+                self.visit_function(function);
+            }
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.block(body);
+                if let Some(catch) = catch {
+                    self.begin_scope();
+                    // Exception doesn't have to be used
+                    let index = self.declare(&catch.name, None);
+                    self.bindings[index].used = true;
+                    self.statements(&catch.body);
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.block(finally);
+                }
+            }
+            Stmt::Var { name, .. } => {
+                walk_stmt(self, stmt);
+                self.declare(name, None);
+            }
+            _ => walk_stmt(self, stmt),
+        }
+    }
+
+    fn visit_function(&mut self, function: &Function) {
+        self.begin_scope();
+        for param in &function.params {
+            let index = self.declare(param, None);
+            self.bindings[index].parameter = true;
+        }
+        self.statements(&function.body);
+        self.end_scope();
+    }
+
+    fn visit_expr(&mut self, expr: &Expr) {
+        match expr {
+            Expr::Assign { name, .. } => {
+                walk_expr(self, expr);
+                match self.lookup(&name.lexeme) {
+                    Some(index) => self.bindings[index].arity = None,
+                    None => self.warnings.push(Warning::new(
+                        Lint::UndeclaredAssignment,
+                        name,
+                        format!(
+                            "Assignment to undeclared variable '{}'.",
+                            name.lexeme
+                        ),
+                    )),
+                }
+            }
+            Expr::Binary { op, left, right } => {
+                let comparison = matches!(
+                    op.type_,
+                    TokenType::EqualEqual
+                        | TokenType::BangEqual
+                        | TokenType::Greater
+                        | TokenType::GreaterEqual
+                        | TokenType::Less
+                        | TokenType::LessEqual
+                );
+                if comparison && same(left, right) {
+                    self.warnings.push(Warning::new(
+                        Lint::SelfComparison,
+                        op,
+                        "Comparison of expression with itself.".into(),
+                    ));
+                }
+                walk_expr(self, expr);
+            }
+            Expr::Call {
+                callee, arguments, ..
+            } => match &**callee {
+                Expr::Variable { name, .. } => {
+                    if let Some(index) = self.variable(name) {
+                        let call = (index, name.clone(), arguments.len());
+                        self.calls.push(call);
+                    }
+                    arguments.iter().for_each(|a| self.visit_expr(a));
+                }
+                _ => walk_expr(self, expr),
+            },
+            Expr::Variable { name, .. } => {
+                self.variable(name);
+            }
+            _ => walk_expr(self, expr),
+        }
+    }
+}
+
+/// Finds likely mistakes in resolved program. `tokens` are its source
+/// tokens, with comments, checked for `// lox-allow: name`, lints in `allow`
+/// are silenced everywhere.
+pub fn lint(
+    program: &[Stmt],
+    tokens: &[Token],
+    allow: &[Lint],
+) -> Vec<Warning> {
+    let allowed = Allowed::new(tokens, allow);
+
+    let mut linter = Linter::new(program);
+    linter.statements(program);
+    linter.check_calls();
+    let mut warnings: Vec<_> = linter
+        .warnings
+        .into_iter()
+        .filter(|warning| !allowed.allows(warning))
+        .collect();
+    warnings.sort_by_key(|warning| warning.token.span);
+    warnings
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{parser::Parser, tokenizer::Tokenizer},
+        *,
+    };
+
+    fn run(source: &str, allow: &[Lint]) -> Vec<(Lint, u32, String)> {
+        let tokens: Vec<_> = Tokenizer::new(source)
+            .map(Result::unwrap)
+            .filter(|t| t.type_ != TokenType::Whitespace)
+            .collect();
+        let code = tokens.iter().filter(|t| !t.can_skip()).cloned().collect();
+        let program = Parser::new(code).parse().unwrap();
+        lint(&program, &tokens, allow)
+            .into_iter()
+            .map(|w| (w.lint, w.token.pos.0, w.message))
+            .collect()
+    }
+
+    #[test]
+    fn lints() {
+        let source = "\
+fun f(a, b) {
+  var unused = 1;
+  var x = a;
+  {
+    var x = 2;
+    print x == x;
+  }
+  return x;
+  print 1;
+}
+undeclared = 1;
+f(1, 2);
+f(1, 2, 3, 4);
+clock(1);
+class A { init(n) {} }
+A();
+try {} catch (e) {}
+";
+        assert_eq!(
+            run(source, &[]),
+            [
+                (
+                    Lint::UnusedParameter,
+                    1,
+                    "Parameter 'b' is never used.".into()
+                ),
+                (
+                    Lint::UnusedVariable,
+                    2,
+                    "Local variable 'unused' is never used.".into()
+                ),
+                (
+                    Lint::ShadowedVariable,
+                    5,
+                    "Variable 'x' shadows outer variable.".into()
+                ),
+                (
+                    Lint::SelfComparison,
+                    6,
+                    "Comparison of expression with itself.".into()
+                ),
+                (Lint::UnreachableCode, 9, "Unreachable code.".into()),
+                (
+                    Lint::UndeclaredAssignment,
+                    11,
+                    "Assignment to undeclared variable 'undeclared'.".into()
+                ),
+                (
+                    Lint::WrongArity,
+                    13,
+                    "Expected 2 arguments but got 4.".into()
+                ),
+                (
+                    Lint::WrongArity,
+                    14,
+                    "Expected 0 arguments but got 1.".into()
+                ),
+                (
+                    Lint::UnusedParameter,
+                    15,
+                    "Parameter 'n' is never used.".into()
+                ),
+                (
+                    Lint::WrongArity,
+                    16,
+                    "Expected 1 arguments but got 0.".into()
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn reassigned_functions_are_not_checked() {
+        let source = "fun f() { g(1); }\nfun g() {}\ng = f;\n";
+        assert!(run(source, &[]).is_empty());
+        assert_eq!(run("fun g() {}\ng(1);", &[]).len(), 1);
+    }
+
+    #[test]
+    fn allow() {
+        let source = "\
+// lox-allow: self-comparison
+var a = 1;
+print a == a;
+fun f(x) {
+  // lox-allow: unused-parameter, unused-variable
+  fun g(y) { var z; }
+  var w; // lox-allow: unused-variable
+  var v;
+}
+";
+        let lints: Vec<_> = run(source, &[])
+            .into_iter()
+            .map(|(lint, line, _)| (lint, line))
+            .collect();
+        assert_eq!(
+            lints,
+            [(Lint::UnusedParameter, 4), (Lint::UnusedVariable, 8)]
+        );
+        assert_eq!(
+            run(source, &[Lint::UnusedParameter, Lint::UnusedVariable]),
+            []
+        );
+        assert_eq!("wrong-arity".parse(), Ok(Lint::WrongArity));
+        assert!("wrong".parse::<Lint>().is_err());
+    }
+}
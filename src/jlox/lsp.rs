@@ -0,0 +1,745 @@
+//! Language Server Protocol server, gives editors diagnostics and
+//! navigation for jlox scripts
+
+use std::{
+    collections::HashMap,
+    io::{self, BufRead, Write},
+};
+
+use serde::Deserialize;
+use serde_json::{json, Value};
+
+use super::{
+    ast::{Function, Stmt},
+    errors::{Diagnostic, GenericError, Note},
+    lint::{self, Warning},
+    parser::Parser,
+    resolver::{Resolver, Symbols},
+    tokenizer::Tokenizer,
+    tokens::{Token, TokenType},
+    transport,
+};
+
+// LSP `SymbolKind` values
+const CLASS: u32 = 5;
+const METHOD: u32 = 6;
+const FUNCTION: u32 = 12;
+
+#[derive(Debug, Deserialize)]
+struct Message {
+    id: Option<Value>,
+    method: Option<String>,
+    #[serde(default)]
+    params: Value,
+}
+
+/// Converts byte offsets to LSP positions, which count lines from 0 and
+/// characters in UTF-16 code units
+struct LineIndex {
+    starts: Vec<usize>,
+}
+
+impl LineIndex {
+    fn new(source: &str) -> Self {
+        let lines = source.match_indices('\n').map(|(i, _)| i + 1);
+        Self {
+            starts: std::iter::once(0).chain(lines).collect(),
+        }
+    }
+
+    fn position(&self, source: &str, offset: usize) -> Value {
+        let line = self.starts.partition_point(|&start| start <= offset) - 1;
+        let start = self.starts[line];
+        let character = source
+            .get(start..offset)
+            .map_or(0, |text| text.encode_utf16().count());
+        json!({ "line": line, "character": character })
+    }
+
+    fn offset(&self, source: &str, position: &Value) -> Option<usize> {
+        let line = position["line"].as_u64()? as usize;
+        let mut character = position["character"].as_u64()? as usize;
+        let start = *self.starts.get(line)?;
+        let end = self.starts.get(line + 1).copied().unwrap_or(source.len());
+        for (i, c) in source[start..end].char_indices() {
+            if character == 0 {
+                return Some(start + i);
+            }
+            character = character.saturating_sub(c.len_utf16());
+        }
+        Some(end)
+    }
+}
+
+/// Declaration of a name, with signature shown on hover
+struct Declaration {
+    name: Token,
+    signature: String,
+}
+
+/// Source of an open document, with results of analyzing it
+struct Document {
+    uri: String,
+    source: String,
+    lines: LineIndex,
+    diagnostics: Vec<Value>,
+    symbols: Symbols,
+    declarations: Vec<Declaration>,
+    outline: Vec<Value>,
+}
+
+// Declared name followed by its type annotation, if any
+fn annotated(name: &Token, type_: &Option<Token>) -> String {
+    match type_ {
+        Some(type_) => format!("{}: {}", name.lexeme, type_.lexeme),
+        None => name.lexeme.clone(),
+    }
+}
+
+fn signature(prefix: &str, function: &Function) -> String {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .zip(&function.param_types)
+        .map(|(param, type_)| annotated(param, type_))
+        .collect();
+    let returns = match &function.return_type {
+        Some(type_) => format!(": {}", type_.lexeme),
+        None => String::new(),
+    };
+    format!(
+        "{}{}({}){}",
+        prefix,
+        function.name.lexeme,
+        params.join(", "),
+        returns
+    )
+}
+
+impl Document {
+    fn new(uri: String, source: String) -> Self {
+        let mut document = Self {
+            uri,
+            lines: LineIndex::new(&source),
+            source,
+            diagnostics: vec![],
+            symbols: Symbols::default(),
+            declarations: vec![],
+            outline: vec![],
+        };
+        document.analyze();
+        document
+    }
+
+    fn range(&self, span: (usize, usize)) -> Value {
+        json!({
+            "start": self.lines.position(&self.source, span.0),
+            "end": self.lines.position(&self.source, span.1),
+        })
+    }
+
+    fn location(&self, name: &Token) -> Value {
+        self.location_of(name.span)
+    }
+
+    fn location_of(&self, span: (usize, usize)) -> Value {
+        json!({ "uri": self.uri, "range": self.range(span) })
+    }
+
+    fn diagnostic(&self, span: (usize, usize), message: String) -> Value {
+        json!({
+            "range": self.range(span),
+            "severity": 1,
+            "source": "lox",
+            "message": message,
+        })
+    }
+
+    fn error(&self, error: &impl Diagnostic) -> Value {
+        let GenericError(token, message, notes) = error.error();
+        let span = token.as_ref().map_or((0, 0), |t| t.span);
+        let diagnostic = self.diagnostic(span, message.clone());
+        self.related(diagnostic, notes)
+    }
+
+    fn warning(&self, warning: &Warning) -> Value {
+        let mut diagnostic =
+            self.diagnostic(warning.token.span, warning.message.clone());
+        diagnostic["severity"] = 2.into();
+        diagnostic["code"] = warning.lint.name().into();
+        self.related(diagnostic, &warning.notes)
+    }
+
+    fn related(&self, mut diagnostic: Value, notes: &[Note]) -> Value {
+        let related: Vec<_> = notes
+            .iter()
+            .map(|note| {
+                let span = note.token.as_ref().map_or((0, 0), |t| t.span);
+                json!({
+                    "location": self.location_of(span),
+                    "message": note.message,
+                })
+            })
+            .collect();
+        if !related.is_empty() {
+            diagnostic["relatedInformation"] = related.into();
+        }
+        diagnostic
+    }
+
+    // Runs tokenizer, parser and resolver, stopping at first stage that
+    // fails, then linter
+    fn analyze(&mut self) {
+        let mut tokens = vec![];
+        let mut tokenizer = Tokenizer::new(&self.source);
+        while let Some(token) = tokenizer.next() {
+            match token {
+                Ok(token) if token.type_ == TokenType::Whitespace => {}
+                Ok(token) => tokens.push(token),
+                Err(error) => {
+                    let diagnostic =
+                        self.diagnostic(tokenizer.span(), error.to_string());
+                    self.diagnostics.push(diagnostic);
+                }
+            }
+        }
+        if !self.diagnostics.is_empty() {
+            return;
+        }
+
+        let code = tokens.iter().filter(|t| !t.can_skip()).cloned().collect();
+        let program = match Parser::new(code).parse() {
+            Ok(program) => program,
+            Err(error) => {
+                self.diagnostics.push(self.error(&error));
+                return;
+            }
+        };
+        let mut outline = vec![];
+        self.declare(&program, &mut outline);
+        self.outline = outline;
+
+        let mut locals = HashMap::new();
+        let result = Resolver::new(&mut locals)
+            .with_symbols(&mut self.symbols)
+            .resolve(&program);
+        if let Err(error) = result {
+            self.diagnostics.push(self.error(&error));
+            return;
+        }
+        for warning in lint::lint(&program, &tokens, &[]) {
+            self.diagnostics.push(self.warning(&warning));
+        }
+    }
+
+    // Collects declarations from `statements`, and symbols of classes and
+    // functions into `outline`
+    fn declare(&mut self, statements: &[Stmt], outline: &mut Vec<Value>) {
+        for stmt in statements {
+            match stmt {
+                Stmt::Block { statements } => self.declare(statements, outline),
+                Stmt::Class {
+                    name,
+                    superclass,
+                    methods,
+                } => {
+                    let signature = match superclass {
+                        Some(superclass) => format!(
+                            "class {} < {}",
+                            name.lexeme,
+                            superclass.first_token().lexeme
+                        ),
+                        None => format!("class {}", name.lexeme),
+                    };
+                    let mut children = vec![];
+                    for method in methods {
+                        let prefix = format!("(method) {}.", name.lexeme);
+                        children.push(self.function(method, &prefix, METHOD));
+                    }
+                    outline
+                        .push(self.symbol(name, &signature, CLASS, children));
+                    self.declarations.push(Declaration {
+                        name: name.clone(),
+                        signature,
+                    });
+                }
+                Stmt::Function(function) => {
+                    outline.push(self.function(function, "fun ", FUNCTION))
+                }
+                Stmt::If {
+                    then_branch,
+                    else_branch,
+                    ..
+                } => {
+                    self.declare(std::slice::from_ref(then_branch), outline);
+                    if let Some(else_branch) = else_branch {
+                        self.declare(
+                            std::slice::from_ref(else_branch),
+                            outline,
+                        );
+                    }
+                }
+                Stmt::Try {
+                    body,
+                    catch,
+                    finally,
+                } => {
+                    self.declare(body, outline);
+                    if let Some(catch) = catch {
+                        self.declarations.push(Declaration {
+                            name: catch.name.clone(),
+                            signature: format!(
+                                "(exception) {}",
+                                catch.name.lexeme
+                            ),
+                        });
+                        self.declare(&catch.body, outline);
+                    }
+                    if let Some(finally) = finally {
+                        self.declare(finally, outline);
+                    }
+                }
+                Stmt::Var { name, type_, .. } => {
+                    self.declarations.push(Declaration {
+                        name: name.clone(),
+                        signature: format!("var {}", annotated(name, type_)),
+                    });
+                }
+                Stmt::While { body, .. } => {
+                    self.declare(std::slice::from_ref(body), outline)
+                }
+                Stmt::Expression { .. }
+                | Stmt::PrintStmt { .. }
+                | Stmt::Return { .. }
+                | Stmt::Throw { .. } => {}
+            }
+        }
+    }
+
+    fn function(
+        &mut self,
+        function: &Function,
+        prefix: &str,
+        kind: u32,
+    ) -> Value {
+        let signature = signature(prefix, function);
+        self.declarations.push(Declaration {
+            name: function.name.clone(),
+            signature: signature.clone(),
+        });
+        for (param, type_) in function.params.iter().zip(&function.param_types)
+        {
+            self.declarations.push(Declaration {
+                name: param.clone(),
+                signature: format!("(parameter) {}", annotated(param, type_)),
+            });
+        }
+        let mut children = vec![];
+        self.declare(&function.body, &mut children);
+        self.symbol(&function.name, &signature, kind, children)
+    }
+
+    fn symbol(
+        &self,
+        name: &Token,
+        signature: &str,
+        kind: u32,
+        children: Vec<Value>,
+    ) -> Value {
+        let range = self.range(name.span);
+        json!({
+            "name": name.lexeme,
+            "detail": signature,
+            "kind": kind,
+            "range": range,
+            "selectionRange": range,
+            "children": children,
+        })
+    }
+
+    /// Declaration of name at `offset`, whether it's the declaration itself
+    /// or a reference to it
+    fn declaration_at(&self, offset: usize) -> Option<&Token> {
+        let contains =
+            |token: &Token| token.span.0 <= offset && offset <= token.span.1;
+        self.declarations
+            .iter()
+            .map(|declaration| &declaration.name)
+            .find(|name| contains(name))
+            .or_else(|| {
+                let reference = self
+                    .symbols
+                    .references
+                    .iter()
+                    .find(|reference| contains(&reference.name))?;
+                self.symbols.declaration(reference)
+            })
+    }
+
+    fn references(&self, declaration: &Token) -> Vec<&Token> {
+        self.symbols
+            .references
+            .iter()
+            .filter(|reference| {
+                self.symbols
+                    .declaration(reference)
+                    .is_some_and(|d| d.span == declaration.span)
+            })
+            .map(|reference| &reference.name)
+            .collect()
+    }
+}
+
+struct Server<W> {
+    output: W,
+    documents: HashMap<String, Document>,
+}
+
+impl<W: Write> Server<W> {
+    fn send(&mut self, message: Value) -> io::Result<()> {
+        transport::write(&mut self.output, &message)
+    }
+
+    fn publish_diagnostics(&mut self, uri: &str) -> io::Result<()> {
+        let diagnostics = match self.documents.get(uri) {
+            Some(document) => document.diagnostics.clone(),
+            None => vec![],
+        };
+        self.send(json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/publishDiagnostics",
+            "params": { "uri": uri, "diagnostics": diagnostics },
+        }))
+    }
+
+    // Document and offset given by `TextDocumentPositionParams`
+    fn position<'s>(
+        &'s self,
+        params: &'s Value,
+    ) -> Option<(&'s Document, usize)> {
+        let uri = params["textDocument"]["uri"].as_str()?;
+        let document = self.documents.get(uri)?;
+        let offset = document
+            .lines
+            .offset(&document.source, &params["position"])?;
+        Some((document, offset))
+    }
+
+    fn definition(&self, params: &Value) -> Value {
+        let location = self.position(params).and_then(|(document, at)| {
+            let declaration = document.declaration_at(at)?;
+            Some(document.location(declaration))
+        });
+        location.unwrap_or(Value::Null)
+    }
+
+    fn references(&self, params: &Value) -> Value {
+        let include_declaration =
+            params["context"]["includeDeclaration"] == true;
+        let locations = self.position(params).and_then(|(document, at)| {
+            let declaration = document.declaration_at(at)?;
+            let mut names = document.references(declaration);
+            if include_declaration {
+                names.insert(0, declaration);
+            }
+            let locations = names
+                .into_iter()
+                .map(|name| document.location(name))
+                .collect::<Vec<_>>();
+            Some(locations)
+        });
+        locations.unwrap_or_default().into()
+    }
+
+    fn hover(&self, params: &Value) -> Value {
+        let hover = self.position(params).and_then(|(document, offset)| {
+            let declaration = document.declaration_at(offset)?;
+            let signature = &document
+                .declarations
+                .iter()
+                .find(|d| d.name.span == declaration.span)?
+                .signature;
+            Some(json!({
+                "contents": {
+                    "kind": "markdown",
+                    "value": format!("```lox\n{}\n```", signature),
+                },
+            }))
+        });
+        hover.unwrap_or(Value::Null)
+    }
+
+    fn document_symbols(&self, params: &Value) -> Value {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        match self.documents.get(uri) {
+            Some(document) => document.outline.clone().into(),
+            None => Value::Null,
+        }
+    }
+
+    // Returns result of request, or `None` for notifications
+    fn handle(
+        &mut self,
+        method: &str,
+        params: &Value,
+    ) -> io::Result<Option<Value>> {
+        let uri = params["textDocument"]["uri"].as_str().unwrap_or("");
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": 1,
+                    "definitionProvider": true,
+                    "referencesProvider": true,
+                    "documentSymbolProvider": true,
+                    "hoverProvider": true,
+                },
+                "serverInfo": { "name": "lox" },
+            }),
+            "shutdown" => Value::Null,
+            "textDocument/definition" => self.definition(params),
+            "textDocument/references" => self.references(params),
+            "textDocument/hover" => self.hover(params),
+            "textDocument/documentSymbol" => self.document_symbols(params),
+            "textDocument/didOpen" => {
+                let source =
+                    params["textDocument"]["text"].as_str().unwrap_or("");
+                self.documents.insert(
+                    uri.into(),
+                    Document::new(uri.into(), source.into()),
+                );
+                self.publish_diagnostics(uri)?;
+                return Ok(None);
+            }
+            "textDocument/didChange" => {
+                // Documents are synced in full, so last change has all text
+                let changes = params["contentChanges"].as_array();
+                if let Some(change) = changes.and_then(|c| c.last()) {
+                    let source = change["text"].as_str().unwrap_or("");
+                    self.documents.insert(
+                        uri.into(),
+                        Document::new(uri.into(), source.into()),
+                    );
+                    self.publish_diagnostics(uri)?;
+                }
+                return Ok(None);
+            }
+            "textDocument/didClose" => {
+                self.documents.remove(uri);
+                self.publish_diagnostics(uri)?;
+                return Ok(None);
+            }
+            _ => return Ok(None),
+        };
+        Ok(Some(result))
+    }
+}
+
+/// Serves requests until client sends `exit` or input ends
+pub fn serve(mut input: impl BufRead, output: impl Write) -> io::Result<()> {
+    let mut server = Server {
+        output,
+        documents: HashMap::new(),
+    };
+    while let Some(message) = transport::read::<Message>(&mut input)? {
+        let method = match &message.method {
+            Some(method) => method.as_str(),
+            // Responses to server requests, which it never sends
+            None => continue,
+        };
+        if method == "exit" {
+            break;
+        }
+        let result = server.handle(method, &message.params)?;
+        if let Some(id) = message.id {
+            let response = match result {
+                Some(result) => {
+                    json!({ "jsonrpc": "2.0", "id": id, "result": result })
+                }
+                None => json!({
+                    "jsonrpc": "2.0",
+                    "id": id,
+                    "error": {
+                        "code": -32601,
+                        "message": format!("Unsupported method {}", method),
+                    },
+                }),
+            };
+            server.send(response)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const URI: &str = "file:///test.lox";
+
+    fn session(messages: &[Value]) -> Vec<Value> {
+        let mut input = vec![];
+        for message in messages {
+            transport::write(&mut input, message).unwrap();
+        }
+        let mut output = vec![];
+        serve(&input[..], &mut output).unwrap();
+
+        let mut output = &output[..];
+        let mut messages = vec![];
+        while let Some(message) = transport::read(&mut output).unwrap() {
+            messages.push(message);
+        }
+        messages
+    }
+
+    fn open(source: &str) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "method": "textDocument/didOpen",
+            "params": { "textDocument": {
+                "uri": URI, "languageId": "lox", "version": 1, "text": source,
+            }},
+        })
+    }
+
+    fn request(id: u64, method: &str, line: u32, character: u32) -> Value {
+        json!({
+            "jsonrpc": "2.0",
+            "id": id,
+            "method": method,
+            "params": {
+                "textDocument": { "uri": URI },
+                "position": { "line": line, "character": character },
+                "context": { "includeDeclaration": true },
+            },
+        })
+    }
+
+    fn result(messages: &[Value], id: u64) -> &Value {
+        &messages.iter().find(|m| m["id"] == id).unwrap()["result"]
+    }
+
+    fn range(line: u32, start: u32, end: u32) -> Value {
+        json!({
+            "start": { "line": line, "character": start },
+            "end": { "line": line, "character": end },
+        })
+    }
+
+    #[test]
+    fn diagnostics() {
+        let messages = session(&[
+            open("var a = 1;\n{\n  var b = 1;\n  var b = 2;\n}\n"),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 2 },
+                    "contentChanges": [{ "text": "print 1 @ 2;" }],
+                },
+            }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 3 },
+                    "contentChanges": [{ "text": "print (1;" }],
+                },
+            }),
+            json!({ "jsonrpc": "2.0", "method": "textDocument/didChange",
+                "params": {
+                    "textDocument": { "uri": URI, "version": 4 },
+                    "contentChanges": [{ "text": "fun f(a) {}" }],
+                },
+            }),
+        ]);
+        let diagnostics: Vec<_> = messages
+            .iter()
+            .map(|m| &m["params"]["diagnostics"][0])
+            .collect();
+
+        assert_eq!(
+            diagnostics[0]["message"],
+            "Already variable with this name in this scope."
+        );
+        assert_eq!(diagnostics[0]["range"], range(3, 6, 7));
+        let related = &diagnostics[0]["relatedInformation"][0];
+        assert_eq!(related["location"]["uri"], URI);
+        assert_eq!(related["location"]["range"], range(2, 6, 7));
+
+        // Only diagnostics with notes have related information
+        assert!(diagnostics[1].get("relatedInformation").is_none());
+        assert!(diagnostics[3].get("relatedInformation").is_none());
+
+        assert_eq!(diagnostics[1]["message"], "Unexpected character.");
+        assert_eq!(diagnostics[1]["range"], range(0, 8, 9));
+        assert_eq!(diagnostics[2]["message"], "Expect ')' after expression.");
+        assert_eq!(diagnostics[2]["range"], range(0, 8, 9));
+        assert_eq!(diagnostics[3]["message"], "Parameter 'a' is never used.");
+        assert_eq!(diagnostics[3]["severity"], 2);
+        assert_eq!(diagnostics[3]["code"], "unused-parameter");
+    }
+
+    #[test]
+    fn navigation() {
+        let source = "\
+class A {
+  m(x) { return x; }
+}
+class B < A {}
+fun f(a, b) {
+  var c = a + b;
+  return c + a;
+}
+print f(1, 2);
+print B().m(3);
+";
+        let messages = session(&[
+            json!({ "jsonrpc": "2.0", "id": 1, "method": "initialize",
+                "params": {} }),
+            open(source),
+            request(2, "textDocument/definition", 6, 13),
+            request(3, "textDocument/references", 4, 6),
+            request(4, "textDocument/hover", 8, 6),
+            request(5, "textDocument/hover", 1, 16),
+            request(6, "textDocument/definition", 9, 6),
+            request(7, "textDocument/documentSymbol", 0, 0),
+            request(8, "textDocument/unknown", 0, 0),
+            json!({ "jsonrpc": "2.0", "id": 9, "method": "shutdown" }),
+            json!({ "jsonrpc": "2.0", "method": "exit" }),
+        ]);
+
+        assert_eq!(result(&messages, 1)["capabilities"]["hoverProvider"], true);
+        assert_eq!(messages[1]["params"]["diagnostics"], json!([]));
+        assert_eq!(result(&messages, 2)["range"], range(4, 6, 7));
+        let references: Vec<_> = result(&messages, 3)
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|location| location["range"].clone())
+            .collect();
+        assert_eq!(
+            references,
+            [range(4, 6, 7), range(5, 10, 11), range(6, 13, 14)]
+        );
+        assert_eq!(
+            result(&messages, 4)["contents"]["value"],
+            "```lox\nfun f(a, b)\n```"
+        );
+        assert_eq!(
+            result(&messages, 5)["contents"]["value"],
+            "```lox\n(parameter) x\n```"
+        );
+        assert_eq!(result(&messages, 6)["range"], range(3, 6, 7));
+
+        let symbols = result(&messages, 7).as_array().unwrap();
+        let names: Vec<_> = symbols
+            .iter()
+            .map(|s| (s["name"].as_str().unwrap(), s["kind"].as_u64().unwrap()))
+            .collect();
+        assert_eq!(names, [("A", 5), ("B", 5), ("f", 12)]);
+        assert_eq!(symbols[0]["children"][0]["name"], "m");
+        assert_eq!(symbols[0]["children"][0]["detail"], "(method) A.m(x)");
+        assert_eq!(symbols[1]["detail"], "class B < A");
+
+        let error = &messages.iter().find(|m| m["id"] == 8).unwrap()["error"];
+        assert_eq!(error["code"], -32601);
+        assert_eq!(result(&messages, 9), &Value::Null);
+    }
+}
@@ -1,10 +1,19 @@
 pub mod ast;
+pub mod checker;
+pub mod dap;
+pub mod debugger;
+pub mod diagnostics;
 pub mod environment;
 pub mod errors;
+pub mod formatter;
 pub mod interpreter;
+pub mod lint;
+pub mod lsp;
 pub mod parser;
+pub mod printer;
 pub mod resolver;
 pub mod test_framework;
 pub mod tokenizer;
 pub mod tokens;
+pub mod transport;
 pub mod types;
@@ -1,5 +1,5 @@
 use super::{
-    ast::*,
+    ast::{self, *},
     errors::{ParseError, ParseResult},
     tokens::{
         Token,
@@ -81,7 +81,8 @@ impl Parser {
                 return;
             }
             match self.peek().type_ {
-                Class | Fun | Var | For | If | While | Print | Return => return,
+                Class | Fun | Var | For | If | While | Print | Return
+                | Throw | Try => return,
                 _ => (),
             }
             self.advance();
@@ -96,6 +97,15 @@ impl Parser {
         Ok(statements)
     }
 
+    /// Parses input consisting of single expression
+    pub fn parse_expression(&mut self) -> ParseResult<Expr> {
+        let expr = self.expression()?;
+        if !self.is_at_end() {
+            return Err(self.error(self.peek(), "Expect end of expression."));
+        }
+        Ok(expr)
+    }
+
     fn declaration(&mut self) -> ParseResult<Stmt> {
         let decl = if self.match_(&[Class]) {
             self.class()
@@ -118,7 +128,7 @@ impl Parser {
 
         let superclass = if self.match_(&[Less]) {
             self.consume(Identifier, "Expect superclass name.")?;
-            Some(self.previous())
+            Some(Expr::variable(self.previous()))
         } else {
             None
         };
@@ -140,6 +150,7 @@ impl Parser {
         self.consume(LeftParen, format!("Expect '(' after {} name.", kind))?;
 
         let mut params = Vec::new();
+        let mut param_types = Vec::new();
         if !self.check(RightParen) {
             loop {
                 if params.len() >= 255 {
@@ -150,27 +161,46 @@ impl Parser {
                 }
                 params
                     .push(self.consume(Identifier, "Expect parameter name.")?);
+                param_types.push(self.annotation()?);
                 if !self.match_(&[Comma]) {
                     break;
                 }
             }
         }
         self.consume(RightParen, "Expect ')' after parameters.")?;
+        let return_type = self.annotation()?;
 
         self.consume(LeftBrace, format!("Expect '{{' before {} body.", kind))?;
         let body = self.block()?;
-        Ok(Function { name, params, body })
+        Ok(Function {
+            name,
+            params,
+            param_types,
+            return_type,
+            body,
+        })
+    }
+
+    /// Parses optional `: Type` following declared name, only checked by
+    /// `lox check`
+    fn annotation(&mut self) -> ParseResult<Option<Token>> {
+        if !self.match_(&[Colon]) {
+            return Ok(None);
+        }
+        self.consume(Identifier, "Expect type name after ':'.")
+            .map(Some)
     }
 
     fn var_declaration(&mut self) -> ParseResult<Stmt> {
         let name = self.consume(Identifier, "Expect variable name.")?;
+        let type_ = self.annotation()?;
         let init = if self.match_(&[Equal]) {
             Some(self.expression()?)
         } else {
             None
         };
         self.consume(Semicolon, "Expect ';' after variable statement.")?;
-        Ok(Stmt::var(name, init))
+        Ok(Stmt::var(name, type_, init))
     }
 
     fn statement(&mut self) -> ParseResult<Stmt> {
@@ -182,6 +212,10 @@ impl Parser {
             self.print_statement()
         } else if self.match_(&[Return]) {
             self.return_statement()
+        } else if self.match_(&[Throw]) {
+            self.throw_statement()
+        } else if self.match_(&[Try]) {
+            self.try_statement()
         } else if self.match_(&[While]) {
             self.while_statement()
         } else if self.match_(&[LeftBrace]) {
@@ -192,6 +226,7 @@ impl Parser {
     }
 
     fn for_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous();
         self.consume(LeftParen, "Expect '(' after for.")?;
 
         let initializer = if self.match_(&[Semicolon]) {
@@ -223,7 +258,8 @@ impl Parser {
         }
 
         body = Stmt::while_(
-            condition.unwrap_or_else(|| Expr::literal(Value::Bool(true))),
+            condition
+                .unwrap_or_else(|| Expr::literal(Value::Bool(true), keyword)),
             body,
         );
 
@@ -266,6 +302,47 @@ impl Parser {
         Ok(Stmt::return_(keyword, value))
     }
 
+    fn throw_statement(&mut self) -> ParseResult<Stmt> {
+        let keyword = self.previous();
+        let value = self.expression()?;
+        self.consume(Semicolon, "Expect ';' after thrown value.")?;
+        Ok(Stmt::throw(keyword, value))
+    }
+
+    fn try_statement(&mut self) -> ParseResult<Stmt> {
+        self.consume(LeftBrace, "Expect '{' after try.")?;
+        let body = self.block()?;
+
+        let catch = if self.match_(&[Catch]) {
+            self.consume(LeftParen, "Expect '(' after catch.")?;
+            let name = self.consume(Identifier, "Expect exception name.")?;
+            self.consume(RightParen, "Expect ')' after exception name.")?;
+            self.consume(LeftBrace, "Expect '{' before catch body.")?;
+            Some(ast::Catch {
+                name,
+                body: self.block()?,
+            })
+        } else {
+            None
+        };
+
+        let finally = if self.match_(&[Finally]) {
+            self.consume(LeftBrace, "Expect '{' after finally.")?;
+            Some(self.block()?)
+        } else {
+            None
+        };
+
+        if catch.is_none() && finally.is_none() {
+            return Err(self.error(
+                self.peek(),
+                "Expect 'catch' or 'finally' after try block.",
+            ));
+        }
+
+        Ok(Stmt::try_(body, catch, finally))
+    }
+
     fn while_statement(&mut self) -> ParseResult<Stmt> {
         self.consume(LeftParen, "Expect '(' after while.")?;
         let condition = self.expression()?;
@@ -304,7 +381,7 @@ impl Parser {
             let equals = self.previous();
             let value = self.assignment()?;
 
-            if let Expr::Variable { name } = expr {
+            if let Expr::Variable { name, .. } = expr {
                 return Ok(Expr::assign(name, value));
             } else if let Expr::Get { object, name } = expr {
                 return Ok(Expr::set(*object, name, value));
@@ -440,34 +517,36 @@ impl Parser {
     }
 
     fn primary(&mut self) -> ParseResult<Expr> {
-        let expr =
-            if self.match_(&[False]) {
-                Expr::literal(Value::Bool(false))
-            } else if self.match_(&[True]) {
-                Expr::literal(Value::Bool(true))
-            } else if self.match_(&[Nil]) {
-                Expr::literal(Value::Nil)
-            } else if self.match_(&[Number, String]) {
-                Expr::literal(self.previous().literal.ok_or_else(|| {
-                    self.error(self.peek(), "Missing literal.")
-                })?)
-            } else if self.match_(&[Super]) {
-                let keyword = self.previous();
-                self.consume(Dot, "Expect '.' after 'super'.")?;
-                let method =
-                    self.consume(Identifier, "Expect superclass method name.")?;
-                Expr::super_(keyword, method)
-            } else if self.match_(&[This]) {
-                Expr::this(self.previous())
-            } else if self.match_(&[Identifier]) {
-                Expr::variable(self.previous())
-            } else if self.match_(&[LeftParen]) {
-                let expr = self.expression()?;
-                self.consume(RightParen, "Expect ')' after expression.")?;
-                Expr::grouping(expr)
-            } else {
-                return Err(self.error(self.peek(), "Expect expression."));
-            };
+        let expr = if self.match_(&[False]) {
+            Expr::literal(Value::Bool(false), self.previous())
+        } else if self.match_(&[True]) {
+            Expr::literal(Value::Bool(true), self.previous())
+        } else if self.match_(&[Nil]) {
+            Expr::literal(Value::Nil, self.previous())
+        } else if self.match_(&[Number, String]) {
+            let token = self.previous();
+            let value = token
+                .literal
+                .clone()
+                .ok_or_else(|| self.error(self.peek(), "Missing literal."))?;
+            Expr::literal(value, token)
+        } else if self.match_(&[Super]) {
+            let keyword = self.previous();
+            self.consume(Dot, "Expect '.' after 'super'.")?;
+            let method =
+                self.consume(Identifier, "Expect superclass method name.")?;
+            Expr::super_(keyword, method)
+        } else if self.match_(&[This]) {
+            Expr::this(self.previous())
+        } else if self.match_(&[Identifier]) {
+            Expr::variable(self.previous())
+        } else if self.match_(&[LeftParen]) {
+            let expr = self.expression()?;
+            self.consume(RightParen, "Expect ')' after expression.")?;
+            Expr::grouping(expr)
+        } else {
+            return Err(self.error(self.peek(), "Expect expression."));
+        };
         Ok(expr)
     }
 }
@@ -483,7 +562,7 @@ mod tests {
     fn test() {
         let expr = |x: &str| {
             Parser::new(
-                Tokenizer::new(x.into())
+                Tokenizer::new(x)
                     .filter(|t| {
                         t.as_ref().map(|t| !t.can_skip()).unwrap_or(true)
                     })
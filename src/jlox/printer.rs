@@ -0,0 +1,478 @@
+//! Prints tokens and syntax tree, either as S-expressions in the style of
+//! the book's `AstPrinter`, or as JSON for external tools
+
+use std::str::FromStr;
+
+use serde_json::{json, Value as Json};
+
+use super::{
+    ast::{Expr, Function, Stmt},
+    tokens::Token,
+    types::Value,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Sexp,
+    Json,
+}
+
+impl FromStr for Format {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "sexp" => Ok(Self::Sexp),
+            "json" => Ok(Self::Json),
+            _ => Err("Unsupported format\nAvailable: sexp, json"),
+        }
+    }
+}
+
+/// One token per line, or JSON array of them
+pub fn tokens(tokens: &[Token], format: Format) -> String {
+    match format {
+        Format::Sexp => tokens
+            .iter()
+            .map(|token| {
+                format!(
+                    "({:?} {:?} {}:{})",
+                    token.type_, token.lexeme, token.start.0, token.start.1
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("\n"),
+        Format::Json => {
+            let tokens: Vec<_> = tokens.iter().map(json_token).collect();
+            serde_json::to_string_pretty(&tokens).unwrap()
+        }
+    }
+}
+
+/// One top-level statement per line, or JSON array of them
+pub fn program(program: &[Stmt], format: Format) -> String {
+    match format {
+        Format::Sexp => {
+            program.iter().map(sexp_stmt).collect::<Vec<_>>().join("\n")
+        }
+        Format::Json => {
+            let program: Vec<_> = program.iter().map(json_stmt).collect();
+            serde_json::to_string_pretty(&program).unwrap()
+        }
+    }
+}
+
+fn parenthesize(name: &str, parts: impl IntoIterator<Item = String>) -> String {
+    let mut out = format!("({}", name);
+    for part in parts {
+        out.push(' ');
+        out.push_str(&part);
+    }
+    out.push(')');
+    out
+}
+
+fn sexp_literal(value: &Value) -> String {
+    match value {
+        Value::String(s) => format!("{:?}", s),
+        value => value.to_string(),
+    }
+}
+
+fn sexp_annotated(name: &Token, type_: &Option<Token>) -> String {
+    match type_ {
+        Some(type_) => format!("{}: {}", name.lexeme, type_.lexeme),
+        None => name.lexeme.clone(),
+    }
+}
+
+fn sexp_block(name: &str, statements: &[Stmt]) -> String {
+    parenthesize(name, statements.iter().map(sexp_stmt))
+}
+
+fn sexp_function(function: &Function) -> String {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .zip(&function.param_types)
+        .map(|(param, type_)| sexp_annotated(param, type_))
+        .collect();
+    let mut head =
+        format!("fun {}({})", function.name.lexeme, params.join(" "));
+    if let Some(type_) = &function.return_type {
+        head = format!("{}: {}", head, type_.lexeme);
+    }
+    sexp_block(&head, &function.body)
+}
+
+pub fn sexp_stmt(stmt: &Stmt) -> String {
+    match stmt {
+        Stmt::Block { statements } => sexp_block("block", statements),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => {
+            let head = match superclass {
+                Some(superclass) => {
+                    format!("class {} < {}", name.lexeme, sexp_expr(superclass))
+                }
+                None => format!("class {}", name.lexeme),
+            };
+            parenthesize(&head, methods.iter().map(sexp_function))
+        }
+        Stmt::Expression { expr } => parenthesize(";", [sexp_expr(expr)]),
+        Stmt::Function(function) => sexp_function(function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => match else_branch {
+            Some(else_branch) => parenthesize(
+                "if-else",
+                [
+                    sexp_expr(condition),
+                    sexp_stmt(then_branch),
+                    sexp_stmt(else_branch),
+                ],
+            ),
+            None => parenthesize(
+                "if",
+                [sexp_expr(condition), sexp_stmt(then_branch)],
+            ),
+        },
+        Stmt::PrintStmt { expr } => parenthesize("print", [sexp_expr(expr)]),
+        Stmt::Return { value, .. } => {
+            parenthesize("return", value.iter().map(sexp_expr))
+        }
+        Stmt::Throw { value, .. } => parenthesize("throw", [sexp_expr(value)]),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => {
+            let mut parts = vec![sexp_block("block", body)];
+            if let Some(catch) = catch {
+                let head = format!("catch {}", catch.name.lexeme);
+                parts.push(sexp_block(&head, &catch.body));
+            }
+            if let Some(finally) = finally {
+                parts.push(sexp_block("finally", finally));
+            }
+            parenthesize("try", parts)
+        }
+        Stmt::Var { name, type_, init } => {
+            let mut parts = vec![sexp_annotated(name, type_)];
+            if let Some(init) = init {
+                parts.push("=".into());
+                parts.push(sexp_expr(init));
+            }
+            parenthesize("var", parts)
+        }
+        Stmt::While { condition, body } => {
+            parenthesize("while", [sexp_expr(condition), sexp_stmt(body)])
+        }
+    }
+}
+
+pub fn sexp_expr(expr: &Expr) -> String {
+    match expr {
+        Expr::Assign { name, value, .. } => {
+            parenthesize("=", [name.lexeme.clone(), sexp_expr(value)])
+        }
+        Expr::Binary { op, left, right } => {
+            parenthesize(&op.lexeme, [sexp_expr(left), sexp_expr(right)])
+        }
+        Expr::Call {
+            callee, arguments, ..
+        } => parenthesize(
+            "call",
+            std::iter::once(sexp_expr(callee))
+                .chain(arguments.iter().map(sexp_expr)),
+        ),
+        Expr::Get { object, name } => {
+            parenthesize(".", [sexp_expr(object), name.lexeme.clone()])
+        }
+        Expr::Grouping { expr } => parenthesize("group", [sexp_expr(expr)]),
+        Expr::Literal { value, .. } => sexp_literal(value),
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => parenthesize(
+            "=",
+            [sexp_expr(object), name.lexeme.clone(), sexp_expr(value)],
+        ),
+        Expr::Super { method, .. } => {
+            parenthesize("super", [method.lexeme.clone()])
+        }
+        Expr::This { .. } => "this".into(),
+        Expr::Unary { op, right } => {
+            parenthesize(&op.lexeme, [sexp_expr(right)])
+        }
+        Expr::Variable { name, .. } => name.lexeme.clone(),
+    }
+}
+
+/// Token with its `pos`, line and column where it starts, and `span`, byte
+/// offsets of its start and end
+pub fn json_token(token: &Token) -> Json {
+    json!({
+        "type": format!("{:?}", token.type_),
+        "lexeme": token.lexeme,
+        "pos": [token.start.0, token.start.1],
+        "span": [token.span.0, token.span.1],
+    })
+}
+
+fn json_literal(value: &Value) -> Json {
+    match value {
+        Value::String(s) => s.as_str().into(),
+        Value::Number(n) => (*n).into(),
+        Value::Bool(b) => (*b).into(),
+        Value::Nil => Json::Null,
+        value => value.to_string().into(),
+    }
+}
+
+fn json_option<T>(value: &Option<T>, f: impl Fn(&T) -> Json) -> Json {
+    value.as_ref().map_or(Json::Null, f)
+}
+
+fn json_block(statements: &[Stmt]) -> Json {
+    statements.iter().map(json_stmt).collect()
+}
+
+fn json_function(function: &Function) -> Json {
+    let params: Vec<_> = function
+        .params
+        .iter()
+        .zip(&function.param_types)
+        .map(|(param, type_)| {
+            json!({
+                "name": json_token(param),
+                "annotation": json_option(type_, json_token),
+            })
+        })
+        .collect();
+    json!({
+        "type": "Function",
+        "name": json_token(&function.name),
+        "params": params,
+        "returns": json_option(&function.return_type, json_token),
+        "body": json_block(&function.body),
+    })
+}
+
+pub fn json_stmt(stmt: &Stmt) -> Json {
+    match stmt {
+        Stmt::Block { statements } => json!({
+            "type": "Block",
+            "statements": json_block(statements),
+        }),
+        Stmt::Class {
+            name,
+            superclass,
+            methods,
+        } => json!({
+            "type": "Class",
+            "name": json_token(name),
+            "superclass": json_option(superclass, json_expr),
+            "methods": methods.iter().map(json_function).collect::<Json>(),
+        }),
+        Stmt::Expression { expr } => json!({
+            "type": "Expression",
+            "expr": json_expr(expr),
+        }),
+        Stmt::Function(function) => json_function(function),
+        Stmt::If {
+            condition,
+            then_branch,
+            else_branch,
+        } => json!({
+            "type": "If",
+            "condition": json_expr(condition),
+            "then": json_stmt(then_branch),
+            "else": json_option(else_branch, |stmt| json_stmt(stmt)),
+        }),
+        Stmt::PrintStmt { expr } => json!({
+            "type": "Print",
+            "expr": json_expr(expr),
+        }),
+        Stmt::Return { keyword, value } => json!({
+            "type": "Return",
+            "keyword": json_token(keyword),
+            "value": json_option(value, json_expr),
+        }),
+        Stmt::Throw { keyword, value } => json!({
+            "type": "Throw",
+            "keyword": json_token(keyword),
+            "value": json_expr(value),
+        }),
+        Stmt::Try {
+            body,
+            catch,
+            finally,
+        } => json!({
+            "type": "Try",
+            "body": json_block(body),
+            "catch": json_option(catch, |catch| json!({
+                "name": json_token(&catch.name),
+                "body": json_block(&catch.body),
+            })),
+            "finally": json_option(finally, |finally| json_block(finally)),
+        }),
+        Stmt::Var { name, type_, init } => json!({
+            "type": "Var",
+            "name": json_token(name),
+            "annotation": json_option(type_, json_token),
+            "init": json_option(init, json_expr),
+        }),
+        Stmt::While { condition, body } => json!({
+            "type": "While",
+            "condition": json_expr(condition),
+            "body": json_stmt(body),
+        }),
+    }
+}
+
+pub fn json_expr(expr: &Expr) -> Json {
+    match expr {
+        Expr::Assign { name, value, .. } => json!({
+            "type": "Assign",
+            "name": json_token(name),
+            "value": json_expr(value),
+        }),
+        Expr::Binary { op, left, right } => json!({
+            "type": "Binary",
+            "op": json_token(op),
+            "left": json_expr(left),
+            "right": json_expr(right),
+        }),
+        Expr::Call {
+            callee,
+            right_paren,
+            arguments,
+        } => json!({
+            "type": "Call",
+            "callee": json_expr(callee),
+            "paren": json_token(right_paren),
+            "arguments": arguments.iter().map(json_expr).collect::<Json>(),
+        }),
+        Expr::Get { object, name } => json!({
+            "type": "Get",
+            "object": json_expr(object),
+            "name": json_token(name),
+        }),
+        Expr::Grouping { expr } => json!({
+            "type": "Grouping",
+            "expr": json_expr(expr),
+        }),
+        Expr::Literal { value, token } => json!({
+            "type": "Literal",
+            "value": json_literal(value),
+            "token": json_token(token),
+        }),
+        Expr::Set {
+            object,
+            name,
+            value,
+        } => json!({
+            "type": "Set",
+            "object": json_expr(object),
+            "name": json_token(name),
+            "value": json_expr(value),
+        }),
+        Expr::Super {
+            keyword, method, ..
+        } => json!({
+            "type": "Super",
+            "keyword": json_token(keyword),
+            "method": json_token(method),
+        }),
+        Expr::This { keyword, .. } => json!({
+            "type": "This",
+            "keyword": json_token(keyword),
+        }),
+        Expr::Unary { op, right } => json!({
+            "type": "Unary",
+            "op": json_token(op),
+            "right": json_expr(right),
+        }),
+        Expr::Variable { name, .. } => json!({
+            "type": "Variable",
+            "name": json_token(name),
+        }),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        super::{parser::Parser, tokenizer::Tokenizer},
+        *,
+    };
+
+    fn parse(source: &str) -> (Vec<Token>, Vec<Stmt>) {
+        let tokens: Vec<_> = Tokenizer::new(source)
+            .map(Result::unwrap)
+            .filter(|t| !t.can_skip())
+            .collect();
+        let program = Parser::new(tokens.clone()).parse().unwrap();
+        (tokens, program)
+    }
+
+    #[test]
+    fn sexp() {
+        let (tokens, ast) = parse(
+            "\
+var a: Number = -1 * (2 + 3);
+fun f(x, y: String) { return x.y = \"s\"; }
+class B < A { m() { super.m(this, nil); } }
+if (a and b) print a; else { a = 1; }
+for (;;) {}
+try { throw 1; } catch (e) {} finally {}
+",
+        );
+        assert_eq!(
+            program(&ast, Format::Sexp),
+            "\
+(var a: Number = (* (- 1) (group (+ 2 3))))
+(fun f(x y: String) (return (= x y \"s\")))
+(class B < A (fun m() (; (call (super m) this nil))))
+(if-else (and a b) (print a) (block (; (= a 1))))
+(while true (block))
+(try (block (throw 1)) (catch e) (finally))"
+        );
+        assert_eq!(
+            super::tokens(&tokens[..3], Format::Sexp),
+            "(Var \"var\" 1:1)\n(Identifier \"a\" 1:5)\n(Colon \":\" 1:6)"
+        );
+    }
+
+    #[test]
+    fn json() {
+        let (tokens, ast) = parse("print a.b(1);");
+        let json: Json =
+            serde_json::from_str(&program(&ast, Format::Json)).unwrap();
+        let call = &json[0]["expr"];
+        assert_eq!(call["type"], "Call");
+        assert_eq!(call["callee"]["type"], "Get");
+        assert_eq!(call["callee"]["object"]["name"]["lexeme"], "a");
+        assert_eq!(call["arguments"][0]["value"], 1.0);
+        assert_eq!(call["paren"]["span"], json!([11, 12]));
+
+        let json: Json =
+            serde_json::from_str(&super::tokens(&tokens, Format::Json))
+                .unwrap();
+        assert_eq!(
+            json[0],
+            json!({
+                "type": "Print",
+                "lexeme": "print",
+                "pos": [1, 1],
+                "span": [0, 5],
+            })
+        );
+    }
+}
@@ -24,24 +24,81 @@ enum ClassType {
     Subclass,
 }
 
+#[derive(Debug)]
+struct Variable {
+    defined: bool,
+    // Index in its scope, where environment at runtime will have it
+    slot: usize,
+    // `None` for implicit variables, like `this`
+    declaration: Option<Token>,
+}
+
+impl Variable {
+    // Implicit variables are alone in their scopes
+    fn implicit() -> Self {
+        Self {
+            defined: true,
+            slot: 0,
+            declaration: None,
+        }
+    }
+}
+
+/// Use of a variable found while resolving
+#[derive(Debug, Clone)]
+pub struct Reference {
+    pub name: Token,
+    // `None` for globals, which are looked up once all are declared
+    local: Option<Token>,
+}
+
+/// Declarations variables refer to, collected for editor tooling
+#[derive(Debug, Default)]
+pub struct Symbols {
+    pub references: Vec<Reference>,
+    // First declaration of every global
+    globals: HashMap<String, Token>,
+}
+
+impl Symbols {
+    /// Declaration `reference` points to, `None` for undefined globals
+    pub fn declaration<'s>(
+        &'s self,
+        reference: &'s Reference,
+    ) -> Option<&'s Token> {
+        match &reference.local {
+            Some(declaration) => Some(declaration),
+            None => self.globals.get(&reference.name.lexeme),
+        }
+    }
+}
+
 #[derive(Debug)]
 pub struct Resolver<'a> {
-    locals: &'a mut HashMap<Expr, usize>,
-    scopes: Vec<HashMap<String, bool>>,
+    locals: &'a mut HashMap<NodeId, (usize, usize)>,
+    scopes: Vec<HashMap<String, Variable>>,
     current_function_type: FunctionType,
     current_class_type: ClassType,
+    symbols: Option<&'a mut Symbols>,
 }
 
 impl<'a> Resolver<'a> {
-    pub fn new(locals: &'a mut HashMap<Expr, usize>) -> Self {
+    pub fn new(locals: &'a mut HashMap<NodeId, (usize, usize)>) -> Self {
         Self {
             locals,
             scopes: vec![],
             current_function_type: FunctionType::None,
             current_class_type: ClassType::None,
+            symbols: None,
         }
     }
 
+    /// Records every declaration and use of variables into `symbols`
+    pub fn with_symbols(mut self, symbols: &'a mut Symbols) -> Self {
+        self.symbols = Some(symbols);
+        self
+    }
+
     fn begin_scope(&mut self) {
         self.scopes.push(HashMap::new());
     }
@@ -57,13 +114,28 @@ impl<'a> Resolver<'a> {
         Ok(())
     }
 
-    fn resolve_local(&mut self, expr: &Expr, name: &Token) {
+    fn resolve_local(&mut self, id: NodeId, name: &Token) {
         for (i, scope) in self.scopes.iter().rev().enumerate() {
-            if scope.contains_key(&name.lexeme) {
-                self.locals.insert(expr.clone(), i);
+            if let Some(variable) = scope.get(&name.lexeme) {
+                self.locals.insert(id, (i, variable.slot));
+                // Implicit variables have nothing to point to
+                if let (Some(symbols), Some(declaration)) =
+                    (&mut self.symbols, &variable.declaration)
+                {
+                    symbols.references.push(Reference {
+                        name: name.clone(),
+                        local: Some(declaration.clone()),
+                    });
+                }
                 return;
             }
         }
+        if let Some(symbols) = &mut self.symbols {
+            symbols.references.push(Reference {
+                name: name.clone(),
+                local: None,
+            });
+        }
     }
 
     fn resolve_function(
@@ -87,16 +159,31 @@ impl<'a> Resolver<'a> {
     }
 
     fn declare(&mut self, name: &Token) -> ResolveResult<()> {
+        if let (None, Some(symbols)) = (self.scopes.last(), &mut self.symbols) {
+            symbols
+                .globals
+                .entry(name.lexeme.clone())
+                .or_insert_with(|| name.clone());
+        }
         if let Some(scope) = self.scopes.last_mut() {
+            let slot = scope.len();
             match scope.entry(name.lexeme.clone()) {
-                Entry::Occupied(_) => {
+                Entry::Occupied(occupied) => {
                     return Err(ResolveError::new(
                         Some(name),
                         "Already variable with this name in this scope.",
+                    )
+                    .with_note(
+                        occupied.get().declaration.as_ref(),
+                        "variable declared here",
                     ))
                 }
                 Entry::Vacant(vacant) => {
-                    vacant.insert(false);
+                    vacant.insert(Variable {
+                        defined: false,
+                        slot,
+                        declaration: Some(name.clone()),
+                    });
                 }
             }
         }
@@ -107,13 +194,13 @@ impl<'a> Resolver<'a> {
         if let Some(scope) = self.scopes.last_mut() {
             match scope.entry(name.lexeme.clone()) {
                 Entry::Occupied(mut occupied) => {
-                    if *occupied.get() {
+                    if occupied.get().defined {
                         return Err(ResolveError::new(
                             Some(name),
                             "Double define.",
                         ));
                     }
-                    occupied.insert(true);
+                    occupied.get_mut().defined = true;
                 }
                 Entry::Vacant(_) => {
                     return Err(ResolveError::new(
@@ -145,25 +232,28 @@ impl<'a> Resolver<'a> {
                 self.define(name)?;
 
                 if let Some(superclass) = superclass {
-                    if superclass.lexeme == name.lexeme {
+                    if superclass.first_token().lexeme == name.lexeme {
                         return Err(ResolveError::new(
-                            Some(superclass),
+                            Some(superclass.first_token()),
                             "A class can't inherit from itself.",
                         ));
                     }
 
                     self.current_class_type = ClassType::Subclass;
-                    self.visit_expr(&Expr::variable(superclass.clone()))?;
+                    self.visit_expr(superclass)?;
 
                     self.begin_scope();
                     self.scopes
                         .last_mut()
                         .unwrap()
-                        .insert("super".to_owned(), true);
+                        .insert("super".to_owned(), Variable::implicit());
                 }
 
                 self.begin_scope();
-                self.scopes.last_mut().unwrap().insert("this".into(), true);
+                self.scopes
+                    .last_mut()
+                    .unwrap()
+                    .insert("this".into(), Variable::implicit());
 
                 for method in methods {
                     let typ = if method.name.lexeme == "init" {
@@ -221,11 +311,33 @@ impl<'a> Resolver<'a> {
                     self.visit_expr(value)?;
                 }
             }
+            Stmt::Throw { value, .. } => self.visit_expr(value)?,
+            Stmt::Try {
+                body,
+                catch,
+                finally,
+            } => {
+                self.begin_scope();
+                self.resolve(body)?;
+                self.end_scope();
+                if let Some(Catch { name, body }) = catch {
+                    self.begin_scope();
+                    self.declare(name)?;
+                    self.define(name)?;
+                    self.resolve(body)?;
+                    self.end_scope();
+                }
+                if let Some(finally) = finally {
+                    self.begin_scope();
+                    self.resolve(finally)?;
+                    self.end_scope();
+                }
+            }
             Stmt::While { condition, body } => {
                 self.visit_expr(condition)?;
                 self.visit_stmt(body)?;
             }
-            Stmt::Var { name, init } => {
+            Stmt::Var { name, init, .. } => {
                 self.declare(name)?;
                 if let Some(init) = init {
                     self.visit_expr(init)?;
@@ -238,9 +350,9 @@ impl<'a> Resolver<'a> {
 
     fn visit_expr(&mut self, expr: &Expr) -> ResolveResult<()> {
         match expr {
-            Expr::Assign { name, value } => {
+            Expr::Assign { id, name, value } => {
                 self.visit_expr(value)?;
-                self.resolve_local(expr, name);
+                self.resolve_local(*id, name);
             }
             Expr::Binary { left, right, .. } => {
                 self.visit_expr(left)?;
@@ -261,7 +373,7 @@ impl<'a> Resolver<'a> {
                 self.visit_expr(value)?;
                 self.visit_expr(object)?;
             }
-            Expr::Super { keyword, .. } => match self.current_class_type {
+            Expr::Super { id, keyword, .. } => match self.current_class_type {
                 ClassType::None => {
                     return Err(ResolveError::new(
                         Some(keyword),
@@ -274,32 +386,35 @@ impl<'a> Resolver<'a> {
                         "Can't use 'super' in a class with no superclass.",
                     ))
                 }
-                ClassType::Subclass => self.resolve_local(expr, keyword),
+                ClassType::Subclass => self.resolve_local(*id, keyword),
             },
-            Expr::This { keyword } => {
+            Expr::This { id, keyword } => {
                 if matches!(self.current_class_type, ClassType::None) {
                     return Err(ResolveError::new(
                         Some(keyword),
                         "Can't use 'this' outside of a class.",
                     ));
                 }
-                self.resolve_local(expr, keyword)
+                self.resolve_local(*id, keyword)
             }
             Expr::Unary { right, .. } => self.visit_expr(right)?,
-            Expr::Variable { name } => {
-                if self
+            Expr::Variable { id, name } => {
+                let uninitialized = self
                     .scopes
                     .last()
                     .and_then(|x| x.get(&name.lexeme))
-                    .map(|x| !*x)
-                    .unwrap_or(false)
-                {
+                    .filter(|x| !x.defined);
+                if let Some(variable) = uninitialized {
                     return Err(ResolveError::new(
                         Some(name),
                         "Can't read local variable in its own initializer.",
+                    )
+                    .with_note(
+                        variable.declaration.as_ref(),
+                        "variable declared here",
                     ));
                 }
-                self.resolve_local(expr, name)
+                self.resolve_local(*id, name)
             }
         }
         Ok(())
@@ -1,6 +1,9 @@
 use std::{fs, io, path::Path, string::FromUtf8Error, time::Instant};
 
+use crate::coverage::Coverage;
+
 use super::{
+    diagnostics::{Color, Palette},
     errors::{ParseError, ResolveError, RuntimeError, TokenizerError},
     interpreter::*,
     parser::*,
@@ -11,37 +14,27 @@ use super::{
 
 use anyhow::Result;
 
-macro_rules! term {
-    (ESC) => {
-        "\x1b["
-    };
-    (GREEN) => {
-        concat!(term!(ESC), "32m")
-    };
-    (RED) => {
-        concat!(term!(ESC), "31m")
-    };
-    (RESET) => {
-        concat!(term!(ESC), "m")
-    };
-}
-const OK: &str = concat!(term!(GREEN), "ok", term!(RESET));
-const FAILED: &str = concat!(term!(RED), "FAILED", term!(RESET));
-
 const SKIP: &[&str] = &["benchmark", "expressions", "limit", "scanning"];
 
 fn run_tests_rec(
     prefix: impl AsRef<Path>,
     dir: impl AsRef<Path>,
+    palette: Palette,
     passes: &mut usize,
     fails: &mut usize,
+    mut coverage: Option<&mut Coverage>,
 ) -> Result<()> {
     for entry in fs::read_dir(dir)? {
         let entry = entry?;
         let file_type = entry.file_type()?;
         let path = entry.path();
         if file_type.is_file() {
-            let res = run_test_without_prefix(prefix.as_ref(), &path);
+            let res = run_test_without_prefix(
+                prefix.as_ref(),
+                &path,
+                palette,
+                coverage.as_deref_mut(),
+            );
             match res {
                 Ok(()) => *passes += 1,
                 Err(_) => {
@@ -49,21 +42,45 @@ fn run_tests_rec(
                     *fails += 1;
                 }
             }
-        } else if file_type.is_dir() {
-            if !SKIP.iter().any(|x| x == &path.file_name().unwrap()) {
-                run_tests_rec(prefix.as_ref(), path, passes, fails)?;
-            }
+        } else if file_type.is_dir()
+            && !SKIP.iter().any(|x| x == &path.file_name().unwrap())
+        {
+            run_tests_rec(
+                prefix.as_ref(),
+                path,
+                palette,
+                passes,
+                fails,
+                coverage.as_deref_mut(),
+            )?;
         }
     }
     Ok(())
 }
 
-pub fn run_tests(dir: impl AsRef<Path>) -> Result<()> {
+/// Runs all tests in `dir`, recording executed lines in `coverage` if given
+pub fn run_tests(
+    dir: impl AsRef<Path>,
+    color: Color,
+    coverage: Option<&mut Coverage>,
+) -> Result<()> {
+    let palette = Palette::new(color);
     let mut passes = 0;
     let mut fails = 0;
     let timer = Instant::now();
-    run_tests_rec(dir.as_ref(), dir.as_ref(), &mut passes, &mut fails)?;
-    let result = if fails == 0 { OK } else { FAILED };
+    run_tests_rec(
+        dir.as_ref(),
+        dir.as_ref(),
+        palette,
+        &mut passes,
+        &mut fails,
+        coverage,
+    )?;
+    let result = if fails == 0 {
+        palette.green("ok")
+    } else {
+        palette.red("FAILED")
+    };
     eprintln!(
         "test result: {}. {} passed, {} failed; finished in {:?}",
         result,
@@ -88,18 +105,30 @@ pub enum RunError {
     Runtime(#[from] RuntimeError),
 }
 
-fn run(tokens: Vec<Token>, output: &mut Vec<u8>) -> Result<(), RunError> {
+fn run(
+    tokens: Vec<Token>,
+    output: &mut Vec<u8>,
+    file: &Path,
+    mut coverage: Option<&mut Coverage>,
+) -> Result<(), RunError> {
     let mut parser = Parser::new(tokens);
-    let mut program = parser.parse()?;
+    let program = parser.parse()?;
 
     let mut interpreter = Interpreter::new(output);
+    interpreter.file = Some(file.display().to_string());
+    // Interpreter owns coverage while running, it's handed back below
+    if let Some(coverage) = coverage.as_deref_mut() {
+        interpreter.coverage = Some(std::mem::take(coverage));
+    }
 
     let mut resolver = Resolver::new(&mut interpreter.locals);
     resolver.resolve(&program)?;
 
-    interpreter
-        .interpret(&mut program)
-        .map_err(|x| x.into_error())?;
+    let result = interpreter.interpret(&program);
+    if let Some(coverage) = coverage {
+        *coverage = interpreter.coverage.take().unwrap();
+    }
+    result.map_err(|x| x.into_error())?;
 
     drop(interpreter);
     Ok(())
@@ -150,7 +179,7 @@ fn extract_expects(tokens: &[Token]) -> Expect {
                 .trim_start_matches("// ")
                 .strip_prefix("expect: ")
         })
-        .flat_map(|x| std::array::IntoIter::new([x, "\n"]))
+        .flat_map(|x| [x, "\n"])
         .collect();
 
     Expect {
@@ -179,13 +208,19 @@ pub enum TestError {
     WrongOutput(String, String),
 }
 
-pub fn run_test(path: impl AsRef<Path>) -> Result<()> {
-    run_test_without_prefix("", path)
+pub fn run_test(
+    path: impl AsRef<Path>,
+    color: Color,
+    coverage: Option<&mut Coverage>,
+) -> Result<()> {
+    run_test_without_prefix("", path, Palette::new(color), coverage)
 }
 
 fn run_test_without_prefix(
     prefix: impl AsRef<Path>,
     path: impl AsRef<Path>,
+    palette: Palette,
+    coverage: Option<&mut Coverage>,
 ) -> Result<()> {
     eprint!(
         "test {} ... ",
@@ -194,14 +229,14 @@ fn run_test_without_prefix(
             .unwrap()
             .display()
     );
-    let error = match test_handler(path) {
+    let error = match test_handler(path, coverage) {
         Ok(()) => {
-            eprintln!("{}", OK);
+            eprintln!("{}", palette.green("ok"));
             return Ok(());
         }
         Err(e) => e,
     };
-    eprintln!("{}", FAILED);
+    eprintln!("{}", palette.red("FAILED"));
     match &error {
         TestError::Tokenizer(Some(expected), got) => {
             eprintln!("    expected tokenize error: {:?}", expected);
@@ -233,7 +268,7 @@ fn run_test_without_prefix(
 // Check for expected tokenize error on first line
 // Can't use `extract_expects` because it takes already tokenized input
 fn first_line_expect(source: &str) -> Option<String> {
-    Tokenizer::new(&source)
+    Tokenizer::new(source)
         .next()
         // if there was an error on first token, it probably wasn't an expect
         .transpose()
@@ -248,8 +283,11 @@ fn first_line_expect(source: &str) -> Option<String> {
         .map(ToOwned::to_owned)
 }
 
-fn test_handler(file: impl AsRef<Path>) -> Result<(), TestError> {
-    let source = fs::read_to_string(file)?;
+fn test_handler(
+    file: impl AsRef<Path>,
+    coverage: Option<&mut Coverage>,
+) -> Result<(), TestError> {
+    let source = fs::read_to_string(&file)?;
     let mut all_tokens = match tokenize(&source) {
         Ok(tokens) => tokens,
         Err(e) => {
@@ -268,12 +306,18 @@ fn test_handler(file: impl AsRef<Path>) -> Result<(), TestError> {
     let tokens = all_tokens;
 
     let mut output = vec![];
-    let res = run(tokens, &mut output);
+    let res = run(tokens, &mut output, file.as_ref(), coverage);
     let output = String::from_utf8(output)?;
     match (res, expected.runtime_error) {
         (Ok(()), None) if output == expected.output => Ok(()),
         (Ok(()), None) => Err(TestError::WrongOutput(expected.output, output)),
-        (Err(e), Some(re)) if e.to_string().ends_with(&re) => Ok(()),
+        (Err(e), Some(re)) if e.to_string().ends_with(&re) => {
+            if output == expected.output {
+                Ok(())
+            } else {
+                Err(TestError::WrongOutput(expected.output, output))
+            }
+        }
         (Err(e), Some(re)) => Err(TestError::Run(Some(re), e)),
         (Err(e), None) => Err(TestError::Run(None, e)),
         (Ok(_), Some(re)) => Err(TestError::MissingRunError(re)),
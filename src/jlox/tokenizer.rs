@@ -5,6 +5,9 @@ pub struct Tokenizer<'a> {
     start: usize,
     current: usize,
     line_pos: (u32, u32),
+    // Byte offset of current line, and position of current token's start
+    line_start: usize,
+    start_pos: (u32, u32),
     had_eof: bool,
 }
 
@@ -15,10 +18,18 @@ impl<'a> Tokenizer<'a> {
             start: 0,
             current: 0,
             line_pos: (1, 0),
+            line_start: 0,
+            start_pos: (1, 1),
             had_eof: false,
         }
     }
 
+    /// Byte offsets of last scanned token, or of the text that failed to
+    /// scan
+    pub fn span(&self) -> (usize, usize) {
+        (self.start, self.current)
+    }
+
     fn advance(&mut self) -> char {
         let char = self.peek();
         self.current += char.len_utf8();
@@ -67,6 +78,7 @@ impl<'a> Tokenizer<'a> {
                 if self.peek() == '\n' {
                     self.line_pos.0 += 1;
                     self.line_pos.1 = 0;
+                    self.line_start = self.current + 1;
                 }
                 self.advance();
             }
@@ -105,9 +117,11 @@ impl<'a> Tokenizer<'a> {
         Some(match lexeme {
             "and" => And,
             "break" => Break,
+            "catch" => Catch,
             "class" => Class,
             "else" => Else,
             "false" => False,
+            "finally" => Finally,
             "for" => For,
             "fun" => Fun,
             "if" => If,
@@ -117,14 +131,16 @@ impl<'a> Tokenizer<'a> {
             "return" => Return,
             "super" => Super,
             "this" => This,
+            "throw" => Throw,
             "true" => True,
+            "try" => Try,
             "var" => Var,
             "while" => While,
             _ => return None,
         })
     }
 
-    fn from_type(&self, type_: TokenType) -> Token {
+    fn token(&self, type_: TokenType) -> Token {
         self.new_token(type_, None)
     }
 
@@ -135,6 +151,8 @@ impl<'a> Tokenizer<'a> {
             literal,
             lexeme,
             pos: self.line_pos,
+            start: self.start_pos,
+            span: (self.start, self.current),
         }
     }
 
@@ -142,30 +160,34 @@ impl<'a> Tokenizer<'a> {
         use TokenType::*;
 
         self.start = self.current;
+        // Counted the way diagnostics do, from the span
+        let column = self.source[self.line_start..self.start].chars().count();
+        self.start_pos = (self.line_pos.0, column as u32 + 1);
         if self.is_at_end() {
             self.had_eof = true;
-            return Ok(self.from_type(Eof));
+            return Ok(self.token(Eof));
         }
 
         let c = self.advance();
         match c {
-            '(' => Ok(self.from_type(LeftParen)),
-            ')' => Ok(self.from_type(RightParen)),
-            '{' => Ok(self.from_type(LeftBrace)),
-            '}' => Ok(self.from_type(RightBrace)),
-            ',' => Ok(self.from_type(Comma)),
-            '.' => Ok(self.from_type(Dot)),
-            '-' => Ok(self.from_type(Minus)),
-            '+' => Ok(self.from_type(Plus)),
-            ';' => Ok(self.from_type(Semicolon)),
-            '*' => Ok(self.from_type(Star)),
+            '(' => Ok(self.token(LeftParen)),
+            ')' => Ok(self.token(RightParen)),
+            '{' => Ok(self.token(LeftBrace)),
+            '}' => Ok(self.token(RightBrace)),
+            ':' => Ok(self.token(Colon)),
+            ',' => Ok(self.token(Comma)),
+            '.' => Ok(self.token(Dot)),
+            '-' => Ok(self.token(Minus)),
+            '+' => Ok(self.token(Plus)),
+            ';' => Ok(self.token(Semicolon)),
+            '*' => Ok(self.token(Star)),
             '!' => Ok({
                 let type_ = if self.match_('=') { BangEqual } else { Bang };
-                self.from_type(type_)
+                self.token(type_)
             }),
             '=' => Ok({
                 let type_ = if self.match_('=') { EqualEqual } else { Equal };
-                self.from_type(type_)
+                self.token(type_)
             }),
             '>' => Ok({
                 let type_ = if self.match_('=') {
@@ -173,11 +195,11 @@ impl<'a> Tokenizer<'a> {
                 } else {
                     Greater
                 };
-                self.from_type(type_)
+                self.token(type_)
             }),
             '<' => Ok({
                 let type_ = if self.match_('=') { LessEqual } else { Less };
-                self.from_type(type_)
+                self.token(type_)
             }),
             '/' => {
                 if self.match_('/') {
@@ -185,16 +207,17 @@ impl<'a> Tokenizer<'a> {
                     while self.peek() != '\n' && !self.is_at_end() {
                         self.advance();
                     }
-                    Ok(self.from_type(Comment))
+                    Ok(self.token(Comment))
                 } else {
-                    Ok(self.from_type(Slash))
+                    Ok(self.token(Slash))
                 }
             }
-            ' ' | '\r' | '\t' => Ok(self.from_type(Whitespace)),
+            ' ' | '\r' | '\t' => Ok(self.token(Whitespace)),
             '\n' => {
                 self.line_pos.0 += 1;
                 self.line_pos.1 = 0;
-                Ok(self.from_type(Whitespace))
+                self.line_start = self.current;
+                Ok(self.token(Whitespace))
             }
             '"' => {
                 let string =
@@ -213,7 +236,7 @@ impl<'a> Tokenizer<'a> {
                 let keyword = self
                     .get_keyword(&self.source[self.start..self.current])
                     .unwrap_or(Identifier);
-                Ok(self.from_type(keyword))
+                Ok(self.token(keyword))
             }
             c => Err(TokenizerError::UnexpectedChar(c)),
         }
@@ -236,11 +259,8 @@ mod tests {
 
     #[test]
     fn test() {
-        let run = |x: &str| {
-            Tokenizer::new(x.into())
-                .collect::<Result<Vec<_>, _>>()
-                .unwrap()
-        };
+        let run =
+            |x: &str| Tokenizer::new(x).collect::<Result<Vec<_>, _>>().unwrap();
 
         assert_eq!(run(r#""test""#)[0].type_, TokenType::String);
         assert_eq!(run(r#""test""#)[0].lexeme, "\"test\"");
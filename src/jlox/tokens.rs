@@ -8,6 +8,7 @@ pub enum TokenType {
     RightParen,
     LeftBrace,
     RightBrace,
+    Colon,
     Comma,
     Dot,
     Minus,
@@ -31,9 +32,11 @@ pub enum TokenType {
 
     And,
     Break,
+    Catch,
     Class,
     Else,
     False,
+    Finally,
     Fun,
     For,
     If,
@@ -43,7 +46,9 @@ pub enum TokenType {
     Return,
     Super,
     This,
+    Throw,
     True,
+    Try,
     Var,
     While,
 
@@ -59,6 +64,11 @@ pub struct Token {
     pub lexeme: String,
     pub literal: Option<Value>,
     pub pos: (u32, u32),
+    /// Line and column of the token's first character, where diagnostics
+    /// point, `pos` is recorded at its end
+    pub start: (u32, u32),
+    /// Byte offsets of the token's start and end in the source
+    pub span: (usize, usize),
 }
 
 impl Token {
@@ -0,0 +1,67 @@
+//! Messages framed with `Content-Length` header, as used by both Debug
+//! Adapter Protocol and Language Server Protocol
+
+use std::io::{self, BufRead, Write};
+
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+fn invalid(message: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message.to_string())
+}
+
+/// Reads next message, returns `None` at end of input
+pub fn read<T: DeserializeOwned>(
+    input: &mut dyn BufRead,
+) -> io::Result<Option<T>> {
+    let mut length = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header)? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                length = Some(value.trim().parse().map_err(invalid)?);
+            }
+        }
+    }
+    let length = length.ok_or_else(|| invalid("Missing Content-Length"))?;
+    let mut content = vec![0; length];
+    input.read_exact(&mut content)?;
+    serde_json::from_slice(&content).map(Some).map_err(invalid)
+}
+
+pub fn write(output: &mut dyn Write, message: &Value) -> io::Result<()> {
+    let content = message.to_string();
+    write!(output, "Content-Length: {}\r\n\r\n", content.len())?;
+    output.write_all(content.as_bytes())?;
+    output.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let mut buffer = vec![];
+        write(&mut buffer, &json!({ "a": 1 })).unwrap();
+        write(&mut buffer, &json!("b")).unwrap();
+        assert!(buffer.starts_with(b"Content-Length: 7\r\n\r\n{\"a\":1}"));
+
+        let mut input = &buffer[..];
+        assert_eq!(read::<Value>(&mut input).unwrap(), Some(json!({"a": 1})));
+        assert_eq!(read::<Value>(&mut input).unwrap(), Some(json!("b")));
+        assert_eq!(read::<Value>(&mut input).unwrap(), None);
+
+        let mut input = &b"Content-Type: x\r\n\r\n{}"[..];
+        assert!(read::<Value>(&mut input).is_err());
+    }
+}
@@ -49,7 +49,7 @@ pub enum Value {
 }
 
 impl ValueRef {
-    pub fn fun<F>(arity: usize, f: F) -> Self
+    pub fn fun<F>(name: &'static str, arity: usize, f: F) -> Self
     where
         F: Fn(&mut Interpreter, &mut [ValueRef]) -> RuntimeResult<ValueRef>
             + Send
@@ -57,6 +57,7 @@ impl ValueRef {
             + 'static,
     {
         Self::from_value(Value::Fun(Fun::Native {
+            name,
             inner: Arc::new(f),
             arity,
         }))
@@ -70,11 +71,11 @@ impl ValueRef {
         self.0.read().unwrap().clone()
     }
 
-    pub fn get(&self) -> RwLockReadGuard<Value> {
+    pub fn get(&self) -> RwLockReadGuard<'_, Value> {
         self.0.read().unwrap()
     }
 
-    pub fn get_mut(&self) -> RwLockWriteGuard<Value> {
+    pub fn get_mut(&self) -> RwLockWriteGuard<'_, Value> {
         self.0.write().unwrap()
     }
 
@@ -112,7 +113,7 @@ impl Hash for Value {
             Self::Number(n) => n.to_le_bytes().hash(state),
             Self::String(s) => s.hash(state),
             Self::Bool(b) => b.hash(state),
-            Self::Nil => ().hash(state),
+            Self::Nil => {}
         }
     }
 }
@@ -159,7 +160,11 @@ type NativeFun = Arc<
 
 #[derive(Clone)]
 pub enum Fun {
-    Native { inner: NativeFun, arity: usize },
+    Native {
+        name: &'static str,
+        inner: NativeFun,
+        arity: usize,
+    },
     Lox(LoxFunction),
 }
 
@@ -181,6 +186,13 @@ impl Fun {
             Self::Lox(f) => f.arity(),
         }
     }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Self::Native { name, .. } => name,
+            Self::Lox(f) => f.name(),
+        }
+    }
 }
 
 impl fmt::Debug for Fun {
@@ -197,8 +209,9 @@ impl fmt::Debug for Fun {
 impl Hash for Fun {
     fn hash<H: Hasher>(&self, state: &mut H) {
         match self {
-            Self::Native { inner, arity } => {
-                Arc::as_ptr(&inner).hash(state);
+            Self::Native { name, inner, arity } => {
+                name.hash(state);
+                Arc::as_ptr(inner).hash(state);
                 arity.hash(state);
             }
             Self::Lox { .. } => (),
@@ -208,7 +221,8 @@ impl Hash for Fun {
 
 #[derive(Debug, Clone, Hash, PartialEq, Eq)]
 pub struct LoxFunction {
-    declaration: Box<ast::Function>,
+    // Shared, so calls, which clone the function value, don't copy the body
+    declaration: Arc<ast::Function>,
     closure: Environment,
     is_init: bool,
 }
@@ -220,7 +234,7 @@ impl LoxFunction {
         is_init: bool,
     ) -> Self {
         Self {
-            declaration: Box::new(declaration),
+            declaration: Arc::new(declaration),
             closure,
             is_init,
         }
@@ -234,17 +248,31 @@ impl LoxFunction {
         let mut environment = self.closure.enclose();
         for (param, arg) in self.declaration.params.iter().zip(arguments.iter())
         {
-            environment.define(param.lexeme.to_string(), arg.clone());
+            environment.define(&param.lexeme, arg.clone());
         }
         let result =
-            interpreter.execute_block(&mut self.declaration.body, environment);
+            interpreter.execute_block(&self.declaration.body, environment);
         match result {
-            Ok(()) if self.is_init => self.closure.get_at_str(0, "this"),
+            Ok(()) if self.is_init => self.closure.get_at(0, 0, None),
             Ok(()) => Ok(ValueRef::nil()),
             Err(ControlFlow::Return(_)) if self.is_init => {
-                self.closure.get_at_str(0, "this")
+                self.closure.get_at(0, 0, None)
             }
             Err(ControlFlow::Return(value)) => Ok(value),
+            Err(ControlFlow::Error(mut error)) => {
+                error.push_frame(
+                    &self.declaration.name.lexeme,
+                    interpreter.file.as_deref(),
+                );
+                Err(ControlFlow::Error(error))
+            }
+            Err(ControlFlow::Throw(value, mut error)) => {
+                error.push_frame(
+                    &self.declaration.name.lexeme,
+                    interpreter.file.as_deref(),
+                );
+                Err(ControlFlow::Throw(value, error))
+            }
             Err(err) => Err(err),
         }
     }
@@ -253,6 +281,10 @@ impl LoxFunction {
         self.declaration.params.len()
     }
 
+    pub fn name(&self) -> &str {
+        &self.declaration.name.lexeme
+    }
+
     pub fn bind(&self, instance: &ValueRef) -> RuntimeResult<Self> {
         if !instance.is_instance() {
             return Err(RuntimeError::wrapped(
@@ -261,7 +293,7 @@ impl LoxFunction {
             ));
         }
         let mut closure = self.closure.enclose();
-        closure.define("this".into(), instance.clone());
+        closure.define("this", instance.clone());
         Ok(Self {
             closure,
             ..self.clone()
@@ -346,6 +378,14 @@ impl Instance {
     pub fn set(&mut self, name: &Token, value: ValueRef) {
         self.fields.insert(name.lexeme.clone(), value);
     }
+
+    pub fn field(&self, name: &str) -> Option<ValueRef> {
+        self.fields.get(name).cloned()
+    }
+
+    pub fn set_field(&mut self, name: &str, value: ValueRef) {
+        self.fields.insert(name.to_owned(), value);
+    }
 }
 
 impl fmt::Display for Instance {
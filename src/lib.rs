@@ -1,14 +1,37 @@
 pub mod clox;
+pub mod coverage;
 pub mod jlox;
+pub mod profile;
 
-use std::{fs, path::Path};
+use std::{collections::HashMap, fs, io::Write, path::Path};
 
 use crate::{
-    clox::compiler::compile,
+    clox::{
+        chunk::Chunk,
+        compiler::compile,
+        debug,
+        optimizer::{optimize, OptLevel},
+        trace::Tracer,
+        verifier::verify,
+        vm::Hooks,
+    },
+    coverage::Coverage,
     jlox::{
-        errors::TokenizerError, interpreter::*, parser::*, resolver::Resolver,
-        tokenizer::*, tokens::*,
+        ast::Stmt,
+        checker,
+        debugger::{Console, Debugger},
+        diagnostics::{Color, Palette, Renderer},
+        errors::{FormatError, TokenizerError},
+        formatter,
+        interpreter::*,
+        lint::{self, Lint},
+        parser::*,
+        printer,
+        resolver::Resolver,
+        tokenizer::*,
+        tokens::*,
     },
+    profile::Profiler,
 };
 
 use anyhow::Result;
@@ -18,8 +41,12 @@ use jlox::test_framework;
 pub trait Lox {
     fn interpret(&mut self, source: String) -> Result<()>;
 
+    /// Called before running a script from `file`, so errors can point to it
+    fn set_file(&mut self, _file: &Path) {}
+
     fn run_file<P: AsRef<Path>>(&mut self, file: P) -> Result<()> {
-        let script = fs::read_to_string(file)?;
+        let script = fs::read_to_string(&file)?;
+        self.set_file(file.as_ref());
         self.interpret(script)?;
         Ok(())
     }
@@ -60,64 +87,413 @@ pub trait Lox {
 
 pub struct JLox {
     interpreter: Interpreter<'static>,
+    palette: Palette,
 }
 
 impl Default for JLox {
     fn default() -> Self {
-        Self {
-            interpreter: Interpreter::new(std::io::stdout()),
-        }
+        Self::new(Color::default())
     }
 }
 
 impl JLox {
-    pub fn run_test<A: AsRef<Path>>(path: A) -> Result<()> {
-        test_framework::run_test(path)
+    pub fn new(color: Color) -> Self {
+        Self::with_output(std::io::stdout(), color)
+    }
+
+    /// Creates interpreter printing program output to `output`
+    pub fn with_output(
+        output: impl std::io::Write + 'static,
+        color: Color,
+    ) -> Self {
+        Self {
+            interpreter: Interpreter::new(output),
+            palette: Palette::new(color),
+        }
+    }
+
+    /// Records calls and statement hits of every script run from now on
+    pub fn enable_profiler(&mut self) {
+        self.interpreter.profiler = Some(Profiler::new("statement"));
+    }
+
+    /// Prints profiler report to stderr and optionally writes folded stacks
+    /// to `folded`, does nothing unless profiler is enabled
+    pub fn write_profile(&self, folded: Option<&Path>) -> Result<()> {
+        if let Some(profiler) = &self.interpreter.profiler {
+            profiler.report(folded)?;
+        }
+        Ok(())
+    }
+
+    /// Records executed lines of every script run from now on
+    pub fn enable_coverage(&mut self) {
+        self.interpreter.coverage = Some(Coverage::new());
+    }
+
+    /// Stops before first statement and lets user step through script from
+    /// command prompt
+    pub fn enable_debugger(&mut self) {
+        let mut editor = rustyline::Editor::<()>::new();
+        let input = move |prompt: &str| {
+            let line = editor.readline(prompt).ok()?;
+            editor.add_history_entry(&line);
+            Some(line)
+        };
+        let console = Console::new(input, std::io::stdout());
+        self.set_debugger(Debugger::new(console));
+    }
+
+    pub fn set_debugger(&mut self, debugger: Debugger) {
+        self.interpreter.debugger = Some(debugger);
     }
 
-    pub fn run_tests<A: AsRef<Path>>(path: A) -> Result<()> {
-        test_framework::run_tests(path)
+    /// Writes lcov report to `lcov` and prints summary to stderr, does
+    /// nothing unless coverage is enabled
+    pub fn write_coverage(&self, lcov: &Path) -> Result<()> {
+        if let Some(coverage) = &self.interpreter.coverage {
+            coverage.report(lcov)?;
+        }
+        Ok(())
+    }
+
+    /// Formats files in place, or with `check` only reports ones that
+    /// aren't formatted. Without files, formats stdin to stdout.
+    pub fn format_files<P: AsRef<Path>>(
+        files: &[P],
+        check: bool,
+        color: Color,
+    ) -> Result<()> {
+        let palette = Palette::new(color);
+        let format = |source: &str, file: Option<&str>| {
+            formatter::format(source).map_err(|e| match e {
+                FormatError::Parse(e) => {
+                    let renderer = Renderer::new(source, file, palette);
+                    anyhow::anyhow!(renderer.render(&e))
+                }
+                e => e.into(),
+            })
+        };
+
+        if files.is_empty() {
+            let source = std::io::read_to_string(std::io::stdin())?;
+            let formatted = format(&source, None)?;
+            if check && formatted != source {
+                anyhow::bail!("Input is not formatted");
+            }
+            if !check {
+                print!("{}", formatted);
+            }
+            return Ok(());
+        }
+
+        let mut unformatted = 0;
+        for file in files {
+            let file = file.as_ref();
+            let source = fs::read_to_string(file)?;
+            let name = file.display().to_string();
+            let formatted = format(&source, Some(&name))?;
+            if formatted == source {
+                continue;
+            }
+            if check {
+                eprintln!("{} is not formatted", name);
+                unformatted += 1;
+            } else {
+                fs::write(file, formatted)?;
+            }
+        }
+        if unformatted > 0 {
+            anyhow::bail!("{} file(s) not formatted", unformatted);
+        }
+        Ok(())
+    }
+
+    /// Prints tokens and syntax tree of file instead of running it, each
+    /// one if its format is given
+    pub fn dump_file<P: AsRef<Path>>(
+        &self,
+        file: P,
+        tokens: Option<printer::Format>,
+        ast: Option<printer::Format>,
+    ) -> Result<()> {
+        let source = fs::read_to_string(&file)?;
+        let name = file.as_ref().display().to_string();
+        let renderer = Renderer::new(&source, Some(&name), self.palette);
+        let code: Vec<Token> = Tokenizer::new(&source)
+            .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
+            .collect::<std::result::Result<_, TokenizerError>>()?;
+        let mut out = std::io::stdout().lock();
+        if let Some(format) = tokens {
+            write_line(&mut out, &printer::tokens(&code, format))?;
+        }
+        if let Some(format) = ast {
+            let program = Parser::new(code)
+                .parse()
+                .map_err(|e| anyhow::anyhow!(renderer.render(&e)))?;
+            write_line(&mut out, &printer::program(&program, format))?;
+        }
+        Ok(())
+    }
+
+    // Parses and resolves script without running it, returns its tokens
+    // with comments, and the program
+    fn analyze(
+        source: &str,
+        renderer: &Renderer,
+    ) -> Result<(Vec<Token>, Vec<Stmt>)> {
+        let tokens: Vec<Token> = Tokenizer::new(source)
+            .filter(|t| {
+                t.as_ref()
+                    .map(|t| t.type_ != TokenType::Whitespace)
+                    .unwrap_or(true)
+            })
+            .collect::<std::result::Result<_, TokenizerError>>()?;
+        let code = tokens.iter().filter(|t| !t.can_skip()).cloned();
+        let program = Parser::new(code.collect())
+            .parse()
+            .map_err(|e| anyhow::anyhow!(renderer.render(&e)))?;
+        Resolver::new(&mut HashMap::new())
+            .resolve(&program)
+            .map_err(|e| anyhow::anyhow!(renderer.render(&e)))?;
+        Ok((tokens, program))
+    }
+
+    /// Prints lint warnings of files to stderr, failing if there are any.
+    /// Lints in `allow` are not reported.
+    pub fn lint_files<P: AsRef<Path>>(
+        files: &[P],
+        allow: &[Lint],
+        color: Color,
+    ) -> Result<()> {
+        let palette = Palette::new(color);
+        let mut warnings = 0;
+        for file in files {
+            let file = file.as_ref();
+            let source = fs::read_to_string(file)?;
+            let name = file.display().to_string();
+            let renderer = Renderer::new(&source, Some(&name), palette);
+            let (tokens, program) = Self::analyze(&source, &renderer)?;
+            for warning in lint::lint(&program, &tokens, allow) {
+                eprintln!("{}\n", renderer.render_warning(&warning));
+                warnings += 1;
+            }
+        }
+        if warnings > 0 {
+            anyhow::bail!("{} warning(s)", warnings);
+        }
+        Ok(())
+    }
+
+    /// Prints type errors of files to stderr, failing if there are any
+    pub fn check_files<P: AsRef<Path>>(
+        files: &[P],
+        color: Color,
+    ) -> Result<()> {
+        let palette = Palette::new(color);
+        let mut errors = 0;
+        for file in files {
+            let file = file.as_ref();
+            let source = fs::read_to_string(file)?;
+            let name = file.display().to_string();
+            let renderer = Renderer::new(&source, Some(&name), palette);
+            let (_, program) = Self::analyze(&source, &renderer)?;
+            for error in checker::check(&program) {
+                eprintln!("{}\n", renderer.render(&error));
+                errors += 1;
+            }
+        }
+        if errors > 0 {
+            anyhow::bail!("{} type error(s)", errors);
+        }
+        Ok(())
+    }
+
+    pub fn run_test<A: AsRef<Path>>(
+        path: A,
+        color: Color,
+        coverage: Option<&mut Coverage>,
+    ) -> Result<()> {
+        test_framework::run_test(path, color, coverage)
+    }
+
+    pub fn run_tests<A: AsRef<Path>>(
+        path: A,
+        color: Color,
+        coverage: Option<&mut Coverage>,
+    ) -> Result<()> {
+        test_framework::run_tests(path, color, coverage)
     }
 }
 
 impl Lox for JLox {
     fn interpret(&mut self, source: String) -> Result<()> {
+        let file = self.interpreter.file.clone();
+        let renderer = Renderer::new(&source, file.as_deref(), self.palette);
+        if let Some(debugger) = &mut self.interpreter.debugger {
+            debugger.set_source(&source);
+        }
+
         let tokenizer = Tokenizer::new(&source);
         let tokens: Vec<Token> = tokenizer
             .filter(|t| t.as_ref().map(|t| !t.can_skip()).unwrap_or(true))
             .collect::<std::result::Result<_, TokenizerError>>()?;
 
         let mut parser = Parser::new(tokens);
-        let mut program = parser.parse()?;
+        let program = parser
+            .parse()
+            .map_err(|e| anyhow::anyhow!(renderer.render(&e)))?;
 
         let mut resolver = Resolver::new(&mut self.interpreter.locals);
-        resolver.resolve(&program)?;
+        resolver
+            .resolve(&program)
+            .map_err(|e| anyhow::anyhow!(renderer.render(&e)))?;
 
-        self.interpreter.interpret(&mut program)?;
+        self.interpreter.interpret(&program).map_err(|e| {
+            let error = e.into_error();
+            anyhow::anyhow!(
+                "{}\n{}",
+                renderer.render(&error),
+                error.traceback()
+            )
+        })?;
 
         Ok(())
     }
+
+    fn set_file(&mut self, file: &Path) {
+        self.interpreter.file = Some(file.display().to_string());
+    }
 }
 
 pub struct CLox {
     state: VmState,
-    debug: bool,
+    tracer: Option<Tracer>,
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+    opt_level: OptLevel,
 }
 
 impl CLox {
-    pub fn new(debug: bool) -> Self {
+    pub fn new(tracer: Option<Tracer>, opt_level: OptLevel) -> Self {
         Self {
             state: Default::default(),
-            debug,
+            tracer,
+            profiler: None,
+            coverage: None,
+            opt_level,
+        }
+    }
+
+    /// Counts executed instructions of every script run from now on
+    pub fn enable_profiler(&mut self) {
+        self.profiler = Some(Profiler::new("opcode"));
+    }
+
+    /// Prints profiler report to stderr and optionally writes folded stacks
+    /// to `folded`, does nothing unless profiler is enabled
+    pub fn write_profile(&self, folded: Option<&Path>) -> Result<()> {
+        if let Some(profiler) = &self.profiler {
+            profiler.report(folded)?;
+        }
+        Ok(())
+    }
+
+    /// Records executed lines of every script run from now on
+    pub fn enable_coverage(&mut self) {
+        self.coverage = Some(Coverage::new());
+    }
+
+    /// Writes lcov report to `lcov` and prints summary to stderr, does
+    /// nothing unless coverage is enabled
+    pub fn write_coverage(&self, lcov: &Path) -> Result<()> {
+        if let Some(coverage) = &self.coverage {
+            coverage.report(lcov)?;
         }
+        Ok(())
+    }
+
+    fn compile(&mut self, source: &str) -> Result<Chunk> {
+        let chunk = compile(source)?;
+        if let Some(tracer) = &mut self.tracer {
+            if self.opt_level != OptLevel::O0 {
+                tracer.chunk(&chunk, "unoptimized")?;
+            }
+        }
+        Ok(optimize(&chunk, self.opt_level))
+    }
+
+    fn run(&mut self, chunk: &Chunk) -> Result<()> {
+        let mut vm = Vm::new(chunk, &mut self.state);
+        let hooks = Hooks {
+            tracer: self.tracer.as_mut(),
+            profiler: self.profiler.as_mut(),
+            coverage: self.coverage.as_mut(),
+        };
+        vm.interpret(hooks).map_err(|error| {
+            anyhow::anyhow!("{}\n{}", error, error.traceback())
+        })?;
+        Ok(())
+    }
+
+    /// Compiles `input` script into bytecode file `output`
+    pub fn compile_file<P: AsRef<Path>, Q: AsRef<Path>>(
+        &mut self,
+        input: P,
+        output: Q,
+    ) -> Result<()> {
+        let script = fs::read_to_string(input)?;
+        let chunk = self.compile(&script)?;
+        fs::write(output, chunk.serialize())?;
+        Ok(())
+    }
+
+    /// Prints disassembly of script or bytecode file instead of running it
+    pub fn disassemble_file<P: AsRef<Path>>(
+        &mut self,
+        file: P,
+        format: debug::Format,
+    ) -> Result<()> {
+        let chunk = self.load(file.as_ref())?;
+        let name = file.as_ref().display().to_string();
+        let instructions = debug::disassemble(&chunk);
+        debug::render(&mut std::io::stdout(), &name, &instructions, format)?;
+        Ok(())
+    }
+
+    fn load(&mut self, file: &Path) -> Result<Chunk> {
+        if file.extension() == Some("loxc".as_ref()) {
+            let chunk = Chunk::deserialize(&fs::read(file)?)?;
+            verify(&chunk)?;
+            Ok(chunk)
+        } else {
+            self.compile(&fs::read_to_string(file)?)
+        }
+    }
+
+    /// Runs bytecode file produced by `compile_file`
+    pub fn run_bytecode_file<P: AsRef<Path>>(&mut self, file: P) -> Result<()> {
+        let chunk = self.load(file.as_ref())?;
+        self.set_file(file.as_ref());
+        self.run(&chunk)
     }
 }
 
 impl Lox for CLox {
     fn interpret(&mut self, source: String) -> Result<()> {
-        let chunk = compile(&source)?;
-        let mut vm = Vm::new(&chunk, &mut self.state);
-        vm.interpret(self.debug)?;
-        Ok(())
+        let chunk = self.compile(&source)?;
+        self.run(&chunk)
+    }
+
+    fn set_file(&mut self, file: &Path) {
+        self.state.file = Some(file.display().to_string());
+    }
+}
+
+// Dumps are often piped into `head`, which closes pipe before they're done
+fn write_line(out: &mut impl Write, text: &str) -> std::io::Result<()> {
+    match writeln!(out, "{}", text) {
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        result => result,
     }
 }
@@ -1,9 +1,21 @@
 use std::{path::PathBuf, str::FromStr};
 
 use anyhow::Result;
-use structopt::StructOpt;
+use structopt::{
+    clap::{Error, ErrorKind},
+    StructOpt,
+};
 
-use lox::{CLox, JLox, Lox};
+use lox::{
+    clox::{
+        debug::Format,
+        optimizer::OptLevel,
+        trace::{LineRange, TraceConfig, Tracer},
+    },
+    coverage::Coverage,
+    jlox::{dap, diagnostics::Color, lint::Lint, lsp, printer},
+    CLox, JLox, Lox,
+};
 
 enum Backend {
     JLox,
@@ -22,31 +34,224 @@ impl FromStr for Backend {
     }
 }
 
+#[derive(StructOpt)]
+enum Command {
+    /// Serve Debug Adapter Protocol for jlox over stdio
+    Dap,
+    /// Serve Language Server Protocol for Lox over stdio
+    Lsp,
+    /// Format Lox files in place, or stdin to stdout when none are given
+    Fmt {
+        /// Only check formatting, failing if any file would change
+        #[structopt(long)]
+        check: bool,
+        files: Vec<PathBuf>,
+    },
+    /// Check types of Lox files against their annotations
+    Check { files: Vec<PathBuf> },
+    /// Report likely mistakes in Lox files
+    Lint {
+        /// Don't report given lint, like unused-variable, can be repeated
+        #[structopt(long, number_of_values = 1)]
+        allow: Vec<Lint>,
+        files: Vec<PathBuf>,
+    },
+}
+
 #[derive(StructOpt)]
 struct Opt {
+    #[structopt(subcommand)]
+    command: Option<Command>,
     #[structopt(short, long)]
     test: bool,
+    /// Trace clox execution, implied by any of --trace-* options
     #[structopt(short, long)]
     debug: bool,
+    /// Write clox trace to file instead of stdout
+    #[structopt(long)]
+    trace_file: Option<PathBuf>,
+    /// Only trace instructions in given function, can be repeated
+    #[structopt(long = "trace-function", number_of_values = 1)]
+    trace_functions: Vec<String>,
+    /// Only trace given line or range of lines like 5-10, can be repeated
+    #[structopt(long = "trace-lines", number_of_values = 1)]
+    trace_lines: Vec<LineRange>,
+    /// Number of values from the top of the stack shown in trace
+    #[structopt(long)]
+    trace_stack_depth: Option<usize>,
+    /// Trace format: text, or json for one record per instruction
+    #[structopt(long)]
+    trace_format: Option<Format>,
+    /// Profile script and print report at exit: executed instructions for
+    /// clox, function calls and statement hits for jlox
+    #[structopt(long)]
+    profile: bool,
+    /// Write folded stacks for flamegraph tools to file, implies --profile
+    #[structopt(long)]
+    profile_folded: Option<PathBuf>,
+    /// Print jlox tokens as sexp or json instead of running
+    #[structopt(long)]
+    dump_tokens: Option<printer::Format>,
+    /// Print jlox syntax tree as sexp or json instead of running
+    #[structopt(long)]
+    dump_ast: Option<printer::Format>,
+    /// Step through jlox script from a command prompt
+    #[structopt(long)]
+    debugger: bool,
+    /// Record executed lines, print summary and write lcov report to file
+    #[structopt(long)]
+    coverage: Option<PathBuf>,
+    /// Required unless running a subcommand
+    #[structopt(short, long)]
+    backend: Option<Backend>,
+    /// clox optimization level: -O0 or -O1
+    #[structopt(short = "O", default_value = "1")]
+    opt_level: OptLevel,
+    /// Compile clox script into bytecode file instead of running it
+    #[structopt(short, long)]
+    compile: bool,
+    /// Output of --compile, defaults to input with `.loxc` extension
     #[structopt(short, long)]
-    backend: Backend,
+    output: Option<PathBuf>,
+    /// Print clox disassembly as text or json instead of running
+    #[structopt(long)]
+    disassemble: Option<Format>,
+    /// Colorize output: auto, always or never
+    #[structopt(long, default_value = "auto")]
+    color: Color,
     input: Option<PathBuf>,
 }
 
+impl Opt {
+    fn profiling(&self) -> bool {
+        self.profile || self.profile_folded.is_some()
+    }
+
+    fn tracer(&mut self) -> Result<Option<Tracer>> {
+        let enabled = self.debug
+            || self.trace_file.is_some()
+            || !self.trace_functions.is_empty()
+            || !self.trace_lines.is_empty()
+            || self.trace_stack_depth.is_some()
+            || self.trace_format.is_some();
+        if !enabled {
+            return Ok(None);
+        }
+        let config = TraceConfig {
+            file: self.trace_file.take(),
+            functions: std::mem::take(&mut self.trace_functions),
+            lines: std::mem::take(&mut self.trace_lines),
+            stack_depth: self.trace_stack_depth,
+            format: self.trace_format.unwrap_or(Format::Text),
+        };
+        Ok(Some(Tracer::new(config)?))
+    }
+}
+
 fn main() -> Result<()> {
-    let opt = Opt::from_args();
-    match opt.backend {
-        Backend::JLox => match opt.input {
-            Some(path) if opt.test && path.is_file() => JLox::run_test(path)?,
-            Some(path) if opt.test && path.is_dir() => JLox::run_tests(path)?,
-            None if opt.test => JLox::run_tests("./tests")?,
-            Some(file) => JLox::default().run_file(file)?,
-            None => JLox::default().run_repl()?,
-        },
-        Backend::CLox => match opt.input {
-            Some(path) => CLox::new(opt.debug).run_file(path)?,
-            None => CLox::new(opt.debug).run_repl()?,
-        },
+    let mut opt = Opt::from_args();
+    let profiling = opt.profiling();
+    let lcov = opt.coverage.take();
+    match opt.command {
+        Some(Command::Dap) => {
+            let input = std::io::BufReader::new(std::io::stdin());
+            return Ok(dap::serve(input, std::io::stdout())?);
+        }
+        Some(Command::Lsp) => {
+            let input = std::io::stdin().lock();
+            return Ok(lsp::serve(input, std::io::stdout().lock())?);
+        }
+        Some(Command::Fmt { check, files }) => {
+            return JLox::format_files(&files, check, opt.color);
+        }
+        Some(Command::Check { files }) => {
+            return JLox::check_files(&files, opt.color);
+        }
+        Some(Command::Lint { allow, files }) => {
+            return JLox::lint_files(&files, &allow, opt.color);
+        }
+        None => {}
+    }
+    let backend = opt.backend.take().unwrap_or_else(|| {
+        Error::with_description(
+            "The following required arguments were not provided:\n    \
+             --backend <backend>",
+            ErrorKind::MissingRequiredArgument,
+        )
+        .exit()
+    });
+    match backend {
+        Backend::JLox => {
+            let mut tests_coverage = lcov.as_ref().map(|_| Coverage::new());
+            let mut jlox = JLox::new(opt.color);
+            if profiling {
+                jlox.enable_profiler();
+            }
+            if lcov.is_some() {
+                jlox.enable_coverage();
+            }
+            if opt.debugger {
+                jlox.enable_debugger();
+            }
+            let result = match opt.input {
+                Some(path) if opt.test && path.is_file() => {
+                    JLox::run_test(path, opt.color, tests_coverage.as_mut())
+                }
+                Some(path) if opt.test && path.is_dir() => {
+                    JLox::run_tests(path, opt.color, tests_coverage.as_mut())
+                }
+                None if opt.test => JLox::run_tests(
+                    "./tests",
+                    opt.color,
+                    tests_coverage.as_mut(),
+                ),
+                Some(file)
+                    if opt.dump_tokens.is_some() || opt.dump_ast.is_some() =>
+                {
+                    jlox.dump_file(file, opt.dump_tokens, opt.dump_ast)
+                }
+                Some(file) => jlox.run_file(file),
+                None => jlox.run_repl(),
+            };
+            if let Some(lcov) = &lcov {
+                match &tests_coverage {
+                    Some(coverage) if opt.test => coverage.report(lcov)?,
+                    _ => jlox.write_coverage(lcov)?,
+                }
+            }
+            jlox.write_profile(opt.profile_folded.as_deref())?;
+            result?
+        }
+        Backend::CLox => {
+            let mut clox = CLox::new(opt.tracer()?, opt.opt_level);
+            if profiling {
+                clox.enable_profiler();
+            }
+            if lcov.is_some() {
+                clox.enable_coverage();
+            }
+            let result = match opt.input {
+                Some(path) if opt.disassemble.is_some() => {
+                    clox.disassemble_file(path, opt.disassemble.unwrap())
+                }
+                Some(path) if opt.compile => {
+                    let output = opt
+                        .output
+                        .unwrap_or_else(|| path.with_extension("loxc"));
+                    clox.compile_file(path, output)
+                }
+                Some(path) if path.extension() == Some("loxc".as_ref()) => {
+                    clox.run_bytecode_file(path)
+                }
+                Some(path) => clox.run_file(path),
+                None => clox.run_repl(),
+            };
+            if let Some(lcov) = &lcov {
+                clox.write_coverage(lcov)?;
+            }
+            clox.write_profile(opt.profile_folded.as_deref())?;
+            result?
+        }
     }
     Ok(())
 }
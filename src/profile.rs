@@ -0,0 +1,286 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::Path,
+    time::{Duration, Instant},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct FunctionStats {
+    pub calls: u64,
+    /// Time spent in function, including functions it called
+    pub inclusive: Duration,
+    /// Time spent in function itself
+    pub exclusive: Duration,
+}
+
+struct Frame {
+    function: String,
+    start: Instant,
+    children: Duration,
+}
+
+/// Time spent in functions and hit counts of executed code, shared by both
+/// backends, which only name functions and units of code like statements or
+/// opcodes
+pub struct Profiler {
+    // What is counted, for the report
+    unit: &'static str,
+    functions: HashMap<String, FunctionStats>,
+    // Keyed by line and name of unit, as units don't have identity
+    counts: HashMap<(Option<usize>, &'static str), u64>,
+    // Exclusive time per call stack
+    stacks: HashMap<String, Duration>,
+    frames: Vec<Frame>,
+}
+
+impl Profiler {
+    pub fn new(unit: &'static str) -> Self {
+        Self {
+            unit,
+            functions: HashMap::new(),
+            counts: HashMap::new(),
+            stacks: HashMap::new(),
+            frames: vec![],
+        }
+    }
+
+    pub fn enter(&mut self, function: &str) {
+        self.frames.push(Frame {
+            function: function.into(),
+            start: Instant::now(),
+            children: Duration::ZERO,
+        });
+    }
+
+    pub fn exit(&mut self) {
+        let stack = self
+            .frames
+            .iter()
+            .map(|f| f.function.as_str())
+            .collect::<Vec<_>>()
+            .join(";");
+        let frame = match self.frames.pop() {
+            Some(frame) => frame,
+            None => return,
+        };
+        let inclusive = frame.start.elapsed();
+        let exclusive = inclusive.saturating_sub(frame.children);
+
+        // Time of recursive calls is already part of the outermost one
+        let recursive =
+            self.frames.iter().any(|f| f.function == frame.function);
+        let stats = self.functions.entry(frame.function).or_default();
+        stats.calls += 1;
+        if !recursive {
+            stats.inclusive += inclusive;
+        }
+        stats.exclusive += exclusive;
+        *self.stacks.entry(stack).or_default() += exclusive;
+        if let Some(parent) = self.frames.last_mut() {
+            parent.children += inclusive;
+        }
+    }
+
+    /// Records unit of code named `name`, which is about to be executed
+    pub fn hit(&mut self, line: Option<usize>, name: &'static str) {
+        *self.counts.entry((line, name)).or_default() += 1;
+    }
+
+    /// Function stats, slowest first
+    pub fn function_stats(&self) -> Vec<(&str, FunctionStats)> {
+        let mut functions: Vec<_> = self
+            .functions
+            .iter()
+            .map(|(name, &stats)| (name.as_str(), stats))
+            .collect();
+        functions.sort_by(|a, b| {
+            b.1.inclusive.cmp(&a.1.inclusive).then(a.0.cmp(b.0))
+        });
+        functions
+    }
+
+    /// Hit counts per line and unit, most executed first
+    pub fn counts(&self) -> Vec<(Option<usize>, &'static str, u64)> {
+        let mut counts: Vec<_> = self
+            .counts
+            .iter()
+            .map(|(&(line, name), &count)| (line, name, count))
+            .collect();
+        counts.sort_by(|a, b| {
+            b.2.cmp(&a.2).then(a.0.cmp(&b.0)).then(a.1.cmp(b.1))
+        });
+        counts
+    }
+
+    /// Hit counts per unit name, most executed first
+    pub fn name_counts(&self) -> Vec<(&'static str, u64)> {
+        sorted(self.counts.iter().map(|(&(_, name), &count)| (name, count)))
+    }
+
+    /// Hit counts per line, most executed first
+    pub fn line_counts(&self) -> Vec<(usize, u64)> {
+        sorted(
+            self.counts
+                .iter()
+                .filter_map(|(&(line, _), &count)| Some((line?, count))),
+        )
+    }
+
+    /// Prints report to stderr and optionally writes folded stacks to
+    /// `folded`
+    pub fn report(&self, folded: Option<&Path>) -> io::Result<()> {
+        self.write_report(&mut io::stderr())?;
+        if let Some(path) = folded {
+            let mut file = BufWriter::new(File::create(path)?);
+            self.write_folded(&mut file)?;
+            file.flush()?;
+        }
+        Ok(())
+    }
+
+    /// Writes report with slowest functions, most executed units and lines
+    /// first
+    pub fn write_report(&self, out: &mut dyn Write) -> io::Result<()> {
+        let total: u64 = self.counts.values().sum();
+        let percent = |count: u64| 100.0 * count as f64 / total.max(1) as f64;
+
+        writeln!(out, "== profile ==")?;
+        writeln!(
+            out,
+            "{:<20} {:>8} {:>12} {:>12}",
+            "function", "calls", "total", "self"
+        )?;
+        for (name, stats) in self.function_stats() {
+            writeln!(
+                out,
+                "{:<20} {:>8} {:>12.3?} {:>12.3?}",
+                name, stats.calls, stats.inclusive, stats.exclusive
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<20} {:>12} {:>7}", self.unit, "count", "%")?;
+        for (name, count) in self.name_counts() {
+            writeln!(
+                out,
+                "{:<20} {:>12} {:>6.2}%",
+                name,
+                count,
+                percent(count)
+            )?;
+        }
+
+        writeln!(out)?;
+        writeln!(out, "{:<20} {:>12} {:>7}", "line", "count", "%")?;
+        for (line, count) in self.line_counts() {
+            writeln!(
+                out,
+                "{:<20} {:>12} {:>6.2}%",
+                line,
+                count,
+                percent(count)
+            )?;
+        }
+        writeln!(out, "{} {}s executed", total, self.unit)
+    }
+
+    /// Writes one `frame;frame microseconds` line per call stack, as
+    /// expected by flamegraph tools
+    pub fn write_folded(&self, out: &mut dyn Write) -> io::Result<()> {
+        let mut stacks: Vec<_> = self.stacks.iter().collect();
+        stacks.sort();
+        for (stack, time) in stacks {
+            writeln!(out, "{} {}", stack, time.as_micros())?;
+        }
+        Ok(())
+    }
+}
+
+// Sums counts by key, sorting by count, biggest first, with ties broken by
+// key
+fn sorted<K: Ord + std::hash::Hash>(
+    counts: impl Iterator<Item = (K, u64)>,
+) -> Vec<(K, u64)> {
+    let mut sums: HashMap<K, u64> = HashMap::new();
+    for (key, count) in counts {
+        *sums.entry(key).or_default() += count;
+    }
+    let mut sums: Vec<_> = sums.into_iter().collect();
+    sums.sort_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(&b.0)));
+    sums
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts() {
+        let mut profiler = Profiler::new("opcode");
+        profiler.hit(Some(1), "OP_ADD");
+        profiler.hit(Some(1), "OP_ADD");
+        profiler.hit(Some(2), "OP_ADD");
+        profiler.hit(Some(2), "OP_POP");
+        profiler.hit(None, "OP_POP");
+
+        assert_eq!(
+            profiler.counts(),
+            vec![
+                (Some(1), "OP_ADD", 2),
+                (None, "OP_POP", 1),
+                (Some(2), "OP_ADD", 1),
+                (Some(2), "OP_POP", 1),
+            ]
+        );
+        assert_eq!(profiler.name_counts(), vec![("OP_ADD", 3), ("OP_POP", 2)]);
+        assert_eq!(profiler.line_counts(), vec![(1, 2), (2, 2)]);
+
+        let mut report = vec![];
+        profiler.write_report(&mut report).unwrap();
+        let report = String::from_utf8(report).unwrap();
+        assert!(report.ends_with("5 opcodes executed\n"));
+    }
+
+    #[test]
+    fn nested_frames() {
+        let mut profiler = Profiler::new("opcode");
+        profiler.enter("script");
+        profiler.enter("f");
+        std::thread::sleep(Duration::from_millis(2));
+        profiler.exit();
+        profiler.exit();
+
+        let functions: HashMap<_, _> =
+            profiler.function_stats().into_iter().collect();
+        let (script, f) = (functions["script"], functions["f"]);
+        assert!(script.inclusive >= f.inclusive);
+        assert!(script.exclusive < f.inclusive);
+        assert_eq!(f.inclusive, f.exclusive);
+
+        let mut folded = vec![];
+        profiler.write_folded(&mut folded).unwrap();
+        let folded = String::from_utf8(folded).unwrap();
+        assert!(folded.starts_with("script "));
+        assert!(folded.contains("\nscript;f "));
+    }
+
+    #[test]
+    fn recursive_frames() {
+        let mut profiler = Profiler::new("opcode");
+        profiler.enter("script");
+        profiler.enter("f");
+        profiler.enter("f");
+        std::thread::sleep(Duration::from_millis(2));
+        profiler.exit();
+        profiler.exit();
+        profiler.exit();
+
+        let functions: HashMap<_, _> =
+            profiler.function_stats().into_iter().collect();
+        let (script, f) = (functions["script"], functions["f"]);
+        assert_eq!(f.calls, 2);
+        assert!(f.inclusive <= script.inclusive);
+    }
+}